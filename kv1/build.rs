@@ -1,12 +1,28 @@
 use std::fs;
 
-const PROTO_PATH: &'static str = "src/proto";
+const PROTO_PATH: &str = "src/proto";
 
 fn main() {
     _ = fs::create_dir_all(PROTO_PATH);
     let mut config = prost_build::Config::new();
-    config.bytes(&["."]);
-    config.type_attribute(".", "#[derive(PartialOrd)]");
+    config.bytes(["."]);
+    // 用 BTreeMap，这样 ValueMap 可以 derive PartialOrd，编码的顺序也是固定的
+    config.btree_map(["."]);
+    // prost 生成的枚举已经 derive 了 PartialOrd，只给 message 和 oneof 加
+    config.message_attribute(".", "#[derive(PartialOrd)]");
+    config.enum_attribute(".abi.CommandRequest.request_data", "#[derive(PartialOrd)]");
+    config.enum_attribute(".abi.Value.value", "#[derive(PartialOrd)]");
+    // Auth 里有密码，自己实现 Debug，日志里不会出现密码
+    config.skip_debug([".abi.Auth"]);
+    // HTTP 网关用 JSON 表示 CommandRequest 和 CommandResponse
+    config.type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]");
+    config.type_attribute(".", "#[serde(rename_all = \"snake_case\")]");
+    config.message_attribute(".", "#[serde(default)]");
+    // Value 在 JSON 里用自然的表示，比如 10 而不是 {"integer": 10}，和 REST 接口一样
+    config.message_attribute(
+        ".abi.Value",
+        "#[serde(try_from = \"serde_json::Value\", into = \"serde_json::Value\")]",
+    );
     config
         .out_dir(PROTO_PATH)
         .compile_protos(&["abi.proto"], &["./src/proto"])
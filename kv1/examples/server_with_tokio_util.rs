@@ -33,10 +33,13 @@ async fn main() -> Result<()> {
                 let cmd = CommandRequest::decode(data).unwrap();
                 info!("Got a command: {:?}", cmd);
 
-                let resp = svc.execute(cmd);
+                let mut resp = svc.execute(cmd);
 
-                if let Err(e) = framed.send(Bytes::from(resp.encode_to_vec())).await {
-                    info!("Failed to send response: {:?}", e);
+                while let Some(resp) = resp.next().await {
+                    match framed.send(Bytes::from(resp.encode_to_vec())).await {
+                        Ok(()) => svc.notify_sent(),
+                        Err(e) => info!("Failed to send response: {:?}", e),
+                    }
                 }
             }
             info!("Client {:?} disconnected", addr);
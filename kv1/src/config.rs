@@ -0,0 +1,180 @@
+use crate::{KvError, MAX_FRAME};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+// kvs 的配置文件，所有的字段都有默认值，例如：
+//
+// [general]
+// addr = "0.0.0.0:9527"
+// resp_addr = "0.0.0.0:6379"
+// max_connections = 1024
+//
+// [storage]
+// type = "sled"
+// path = "/var/lib/kvs"
+//
+// [tls]
+// cert = "/etc/kvs/server.cert"
+// key = "/etc/kvs/server.key"
+//
+// [log]
+// level = "info"
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub general: GeneralConfig,
+    pub storage: StorageConfig,
+    // 没有配置时不用 TLS
+    pub tls: Option<TlsConfig>,
+    pub log: LogConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GeneralConfig {
+    pub addr: String,
+    // 下面几个地址没有配置时不启动对应的服务
+    pub resp_addr: Option<String>,
+    pub http_addr: Option<String>,
+    pub admin_addr: Option<String>,
+    // kv1 和 RESP 的连接加起来最多有多少个，超过之后新的连接要等待
+    pub max_connections: usize,
+    pub max_frame: usize,
+    // 收到 SIGTERM 之后，最多等这么多秒让已有的连接处理完
+    pub shutdown_timeout: u64,
+    // 最多给多少个 table 导出 kv_keys，统计要扫描整个 table，默认不导出
+    pub table_metrics: usize,
+}
+
+impl Default for GeneralConfig {
+    fn default() -> Self {
+        Self {
+            addr: "127.0.0.1:9527".into(),
+            resp_addr: None,
+            http_addr: None,
+            admin_addr: None,
+            max_connections: 1024,
+            max_frame: MAX_FRAME,
+            shutdown_timeout: 30,
+            table_metrics: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum StorageConfig {
+    // 有 path 时用 WAL 持久化到这个目录
+    Memory { path: Option<PathBuf> },
+    Sled { path: PathBuf },
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self::Memory { path: None }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    // 配置了 CA 时要求客户端提供证书
+    pub ca: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    // trace、debug、info、warn 或者 error
+    pub level: String,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".into(),
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, KvError> {
+        std::fs::read_to_string(path)?.parse()
+    }
+}
+
+impl FromStr for ServerConfig {
+    type Err = KvError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        toml::from_str(s).map_err(|e| KvError::ConfigError(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_should_be_parsed() {
+        let config: ServerConfig = r#"
+            [general]
+            addr = "0.0.0.0:9527"
+            admin_addr = "127.0.0.1:9090"
+            max_connections = 10
+
+            [storage]
+            type = "sled"
+            path = "/tmp/kvs"
+
+            [tls]
+            cert = "server.cert"
+            key = "server.key"
+
+            [log]
+            level = "debug"
+        "#
+        .parse()
+        .unwrap();
+
+        assert_eq!(config.general.addr, "0.0.0.0:9527");
+        assert_eq!(config.general.admin_addr.as_deref(), Some("127.0.0.1:9090"));
+        assert_eq!(config.general.resp_addr, None);
+        assert_eq!(config.general.max_connections, 10);
+        // 没有配置的字段用默认值
+        assert_eq!(config.general.max_frame, MAX_FRAME);
+        assert_eq!(
+            config.storage,
+            StorageConfig::Sled {
+                path: "/tmp/kvs".into()
+            }
+        );
+        assert_eq!(config.tls.unwrap().ca, None);
+        assert_eq!(config.log.level, "debug");
+    }
+
+    #[test]
+    fn empty_config_should_use_defaults() {
+        let config: ServerConfig = "".parse().unwrap();
+        assert_eq!(config, ServerConfig::default());
+        assert_eq!(config.storage, StorageConfig::Memory { path: None });
+    }
+
+    #[test]
+    fn invalid_config_should_be_rejected() {
+        for s in [
+            "[general]\nadress = \"0.0.0.0:9527\"",
+            "[storage]\ntype = \"redis\"",
+            "[storage]\ntype = \"sled\"",
+            "[tls]\ncert = \"server.cert\"",
+        ] {
+            assert!(matches!(
+                s.parse::<ServerConfig>(),
+                Err(KvError::ConfigError(_))
+            ));
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use crate::Value;
+use sled::transaction::TransactionError;
 use thiserror::Error;
 
 #[derive(Error, Debug, PartialEq)]
@@ -11,7 +12,7 @@ pub enum KvError {
     #[error("Cannot convert value: {0:?} to {1}")]
     ConvertError(Value, &'static str),
     #[error("Cannot process command {0} with table: {1}, key: {2}. Error: {3}")]
-    StorageError(&'static str, String, String, String),
+    StorageError(String, String, String, String),
 
     #[error("Failed to encode protobuf message")]
     EncodeError(#[from] prost::EncodeError),
@@ -23,4 +24,38 @@ pub enum KvError {
 
     #[error("Failed to get from sled")]
     SledError(#[from] sled::Error),
+
+    #[error("Frame error: {0}")]
+    FrameError(String),
+    #[error("I/O error: {0}")]
+    IoError(String),
+    #[error("Failed to parse {0}")]
+    CertificateParseError(&'static str),
+    #[error("TLS error: {0}")]
+    TlsError(String),
+    #[error("Transaction is rolled back: {0}")]
+    Rollback(Box<KvError>),
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+    #[error("Cannot write to a read-only follower")]
+    ReadOnly,
+    #[error("Invalid config: {0}")]
+    ConfigError(String),
+}
+
+impl From<std::io::Error> for KvError {
+    fn from(e: std::io::Error) -> Self {
+        Self::IoError(e.to_string())
+    }
+}
+
+impl From<TransactionError<KvError>> for KvError {
+    fn from(e: TransactionError<KvError>) -> Self {
+        match e {
+            TransactionError::Abort(e) => e,
+            TransactionError::Storage(e) => e.into(),
+        }
+    }
 }
@@ -1,12 +1,17 @@
+mod config;
 mod error;
+mod metrics;
+mod network;
 mod proto;
 mod service;
 mod storage;
 
+pub use config::*;
 pub use error::*;
+pub use network::*;
 pub use proto::*;
 pub use service::*;
-pub use storage::{MemTable, SledDb, Storage};
+pub use storage::{FsyncPolicy, MemTable, SledDb, Storage, StorageTx, TransactionFn, WalOptions};
 
 pub fn add(left: u64, right: u64) -> u64 {
     left + right
@@ -0,0 +1,213 @@
+use crate::command_request::RequestData;
+use crate::{CommandRequest, CommandResponse, ErrorCode, KvError, Storage};
+use parking_lot::Mutex;
+use prometheus::{
+    Encoder, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder, exponential_buckets,
+    register_histogram_vec, register_int_counter_vec, register_int_gauge, register_int_gauge_vec,
+};
+use std::sync::LazyLock;
+use std::time::Instant;
+
+// 指标都注册在 prometheus 默认的 registry 里，和应用自己的指标一起导出
+
+static COMMANDS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!("kv_commands_total", "Number of commands", &["command"]).unwrap()
+});
+
+// 从收到命令到第一个响应的时间，订阅这样一直推送的命令也只算第一个响应
+static LATENCY: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "kv_command_duration_seconds",
+        "Time from receiving a command to its first response",
+        &["command"],
+        exponential_buckets(0.00005, 2.0, 16).unwrap()
+    )
+    .unwrap()
+});
+
+static ERRORS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!("kv_errors_total", "Number of errors", &["error"]).unwrap()
+});
+
+static CONNECTIONS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "kv_connections",
+        "Number of open connections",
+        &["protocol"]
+    )
+    .unwrap()
+});
+
+static CONNECTIONS_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "kv_connections_total",
+        "Number of accepted connections",
+        &["protocol"]
+    )
+    .unwrap()
+});
+
+static TABLES: LazyLock<IntGauge> =
+    LazyLock::new(|| register_int_gauge!("kv_tables", "Number of tables").unwrap());
+
+static KEYS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!("kv_keys", "Number of keys in a table", &["table"]).unwrap()
+});
+
+// 执行命令前调用，返回的函数在第一个响应出来时调用
+pub(crate) fn command_started(cmd: &CommandRequest) -> impl FnOnce() + Send + 'static {
+    let name = command_name(cmd);
+    COMMANDS.with_label_values(&[name]).inc();
+    let start = Instant::now();
+    move || {
+        LATENCY
+            .with_label_values(&[name])
+            .observe(start.elapsed().as_secs_f64())
+    }
+}
+
+// 按最终发给客户端的响应统计错误，hook 改过的响应也以改过的为准
+pub(crate) fn record_response(res: &CommandResponse) {
+    if let Some(e) = &res.error {
+        ERRORS.with_label_values(&[error_name(e.code())]).inc();
+    }
+}
+
+// 连接建立时创建，drop 的时候连接数减一
+pub(crate) struct ConnectionGuard(&'static str);
+
+impl ConnectionGuard {
+    pub(crate) fn new(protocol: &'static str) -> Self {
+        CONNECTIONS_TOTAL.with_label_values(&[protocol]).inc();
+        CONNECTIONS.with_label_values(&[protocol]).inc();
+        Self(protocol)
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        CONNECTIONS.with_label_values(&[self.0]).dec();
+    }
+}
+
+// 存储的大小在导出的时候才统计
+// 同时导出时，要避免 kv_keys 在导出之前被另一个 store 的数据覆盖
+static ENCODE_LOCK: Mutex<()> = Mutex::new(());
+
+// table_metrics 是最多给多少个 table 导出 kv_keys
+pub(crate) fn encode(store: &dyn Storage, table_metrics: usize) -> Result<String, KvError> {
+    let _guard = ENCODE_LOCK.lock();
+    let tables = store.tables()?;
+    TABLES.set(tables.len() as i64);
+    // 删掉已经被 drop 的 table
+    KEYS.reset();
+    for table in tables.iter().take(table_metrics) {
+        KEYS.with_label_values(&[table.as_str()])
+            .set(store.len(table)? as i64);
+    }
+
+    let mut buf = Vec::new();
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buf)
+        .map_err(|e| KvError::Internal(e.to_string()))?;
+    String::from_utf8(buf).map_err(|e| KvError::Internal(e.to_string()))
+}
+
+fn command_name(cmd: &CommandRequest) -> &'static str {
+    let Some(data) = cmd.request_data.as_ref() else {
+        return "unknown";
+    };
+    match data {
+        RequestData::Hget(_) => "hget",
+        RequestData::Hgetall(_) => "hgetall",
+        RequestData::Hmget(_) => "hmget",
+        RequestData::Hset(_) => "hset",
+        RequestData::Hmset(_) => "hmset",
+        RequestData::Hdel(_) => "hdel",
+        RequestData::Hmdel(_) => "hmdel",
+        RequestData::Hexist(_) => "hexist",
+        RequestData::Hmexist(_) => "hmexist",
+        RequestData::Subscribe(_) => "subscribe",
+        RequestData::Unsubscribe(_) => "unsubscribe",
+        RequestData::Publish(_) => "publish",
+        RequestData::Hexpire(_) => "hexpire",
+        RequestData::Httl(_) => "httl",
+        RequestData::Hincrby(_) => "hincrby",
+        RequestData::Hincrbyfloat(_) => "hincrbyfloat",
+        RequestData::Hcas(_) => "hcas",
+        RequestData::Hscan(_) => "hscan",
+        RequestData::Hlen(_) => "hlen",
+        RequestData::Hdrop(_) => "hdrop",
+        RequestData::Tables(_) => "tables",
+        RequestData::Transaction(_) => "transaction",
+        RequestData::Auth(_) => "auth",
+        RequestData::Replicate(_) => "replicate",
+        RequestData::Negotiate(_) => "negotiate",
+    }
+}
+
+fn error_name(code: ErrorCode) -> &'static str {
+    match code {
+        ErrorCode::None => "None",
+        ErrorCode::NotFound => "NotFound",
+        ErrorCode::InvalidCommand => "InvalidCommand",
+        ErrorCode::ConvertError => "ConvertError",
+        ErrorCode::StorageError => "StorageError",
+        ErrorCode::EncodeError => "EncodeError",
+        ErrorCode::DecodeError => "DecodeError",
+        ErrorCode::Internal => "Internal",
+        ErrorCode::SledError => "SledError",
+        ErrorCode::FrameError => "FrameError",
+        ErrorCode::IoError => "IoError",
+        ErrorCode::CertificateParseError => "CertificateParseError",
+        ErrorCode::TlsError => "TlsError",
+        ErrorCode::Rollback => "Rollback",
+        ErrorCode::Unauthorized => "Unauthorized",
+        ErrorCode::PermissionDenied => "PermissionDenied",
+        ErrorCode::ReadOnly => "ReadOnly",
+        ErrorCode::ConfigError => "ConfigError",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemTable, Service, ServiceInner};
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn metrics_should_be_recorded() {
+        let service: Service = ServiceInner::new(MemTable::new()).table_metrics(1).into();
+        let cmds = vec![
+            CommandRequest::new_hset("metrics_t1", "k1", "v1".into()),
+            CommandRequest::new_hset("metrics_t1", "k2", "v2".into()),
+            CommandRequest::new_hget("metrics_t1", "k1"),
+            CommandRequest::new_hget("metrics_t1", "k3"),
+        ];
+        for cmd in cmds {
+            service.execute(cmd).next().await.unwrap();
+        }
+        let _connection = ConnectionGuard::new("test");
+
+        // 其它测试也会记录指标，只检查这个测试一定会产生的部分
+        let text = service.metrics().unwrap();
+        assert!(text.contains("kv_commands_total{command=\"hget\"}"));
+        assert!(text.contains("kv_command_duration_seconds_bucket{command=\"hset\""));
+        assert!(text.contains("kv_errors_total{error=\"NotFound\"}"));
+        assert!(text.contains("kv_connections{protocol=\"test\"} 1"));
+        assert!(text.contains("kv_keys{table=\"metrics_t1\"} 2"));
+    }
+
+    #[tokio::test]
+    async fn table_metrics_should_be_limited() {
+        let store = MemTable::new();
+        for table in ["limit_t1", "limit_t2", "limit_t3"] {
+            store.set(table, "k".into(), "v".into(), None).unwrap();
+        }
+        // 默认不导出 kv_keys
+        let text = encode(&store, 0).unwrap();
+        assert!(!text.contains("kv_keys{"));
+        let text = encode(&store, 2).unwrap();
+        assert_eq!(text.matches("kv_keys{").count(), 2);
+    }
+}
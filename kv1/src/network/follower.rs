@@ -0,0 +1,167 @@
+use super::ProstClientStream;
+use crate::{CommandRequest, KvError, Storage, dispatch};
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tracing::{info, warn};
+
+// 从 leader 复制数据到本地的 store
+// 对外提供服务时，follower 的 Service 要用 read_only，避免客户端直接修改数据
+pub struct Follower<Store> {
+    store: Store,
+    auth: Option<CommandRequest>,
+}
+
+impl<Store: Storage> Follower<Store> {
+    pub fn new(store: Store) -> Self {
+        Self { store, auth: None }
+    }
+
+    // leader 配置了 ACL 时，复制之前先用这个 Auth 命令认证
+    pub fn auth(mut self, cmd: CommandRequest) -> Self {
+        self.auth = Some(cmd);
+        self
+    }
+
+    // 一直复制到连接断开或者出错为止
+    // 每次都会重新加载快照，所以断开之后可以用新的连接再次调用
+    pub async fn run<S>(&self, stream: S) -> Result<(), KvError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        let mut client = ProstClientStream::new(stream);
+        if let Some(cmd) = &self.auth {
+            client.execute(cmd.clone()).await?.into_result()?;
+        }
+
+        // 快照可能很大，leader 支持的话就压缩
+        client.negotiate_compression().await?;
+        client.inner.send(CommandRequest::new_replicate()).await?;
+        let mut loaded = false;
+        while let Some(res) = client.inner.next().await {
+            let res = res?.into_result()?;
+            // 第一个响应是快照的开始，先清空本地的数据
+            if !loaded {
+                info!("Loading snapshot from leader");
+                self.reset()?;
+                loaded = true;
+            }
+            for cmd in res.commands {
+                let res = dispatch(cmd, &self.store);
+                if res.status >= 300 {
+                    warn!("Failed to apply replicated command: {}", res.message);
+                }
+            }
+        }
+        info!("Leader closed the replication stream");
+        Ok(())
+    }
+
+    fn reset(&self) -> Result<(), KvError> {
+        for table in self.store.tables()? {
+            self.store.drop_table(&table)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        MemTable, ProstServerStream, Service, ServiceInner, Value, assert_res_error, assert_res_ok,
+    };
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[tokio::test]
+    async fn follower_should_catch_up_and_stream_changes() -> anyhow::Result<()> {
+        let leader_store = Arc::new(MemTable::new());
+        leader_store.set("t1", "k1".into(), "v1".into(), None)?;
+        leader_store.set("t1", "k2".into(), 2.into(), None)?;
+        leader_store.set("t2", "k1".into(), "v1".into(), None)?;
+        let addr = start_server(ServiceInner::new(leader_store.clone()).leader().into()).await?;
+
+        // follower 本地原来的数据会被快照覆盖
+        let store = Arc::new(MemTable::new());
+        store.set("stale", "k1".into(), "v1".into(), None)?;
+        let follower = Follower::new(store.clone());
+        let stream = TcpStream::connect(addr).await?;
+        tokio::spawn(async move { follower.run(stream).await });
+
+        wait_for(|| store.get("t2", "k1") == Ok(Some("v1".into()))).await;
+        assert_eq!(store.get("t1", "k2"), Ok(Some(2.into())));
+        assert_eq!(store.tables(), Ok(vec!["t1".into(), "t2".into()]));
+
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream);
+        client
+            .execute(CommandRequest::new_hincrby("t1", "k2", 3))
+            .await?;
+        client.execute(CommandRequest::new_hdel("t1", "k1")).await?;
+        client.execute(CommandRequest::new_hdrop("t2")).await?;
+        client
+            .execute(CommandRequest::new_hset("t3", "k1", "v3".into()))
+            .await?;
+
+        wait_for(|| store.get("t3", "k1") == Ok(Some("v3".into()))).await;
+        assert_eq!(store.get("t1", "k2"), Ok(Some(5.into())));
+        assert_eq!(store.get("t1", "k1"), Ok(None));
+        assert_eq!(store.tables(), Ok(vec!["t1".into(), "t3".into()]));
+
+        // follower 自己的 Service 只能读
+        let service: Service<_> = ServiceInner::new(store.clone()).read_only().into();
+        let mut session = service.session();
+        let res = session
+            .execute(CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .next()
+            .await
+            .unwrap();
+        assert_res_error(res.as_ref().clone(), 403, "read-only");
+        let res = session
+            .execute(CommandRequest::new_hget("t3", "k1"))
+            .next()
+            .await
+            .unwrap();
+        assert_res_ok(res.as_ref().clone(), &["v3".into()], &[]);
+        assert_eq!(leader_store.get("t1", "k1"), Ok(None::<Value>));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn follower_should_fail_without_leader() -> anyhow::Result<()> {
+        let addr = start_server(ServiceInner::new(MemTable::new()).into()).await?;
+        let store = Arc::new(MemTable::new());
+        store.set("t1", "k1".into(), "v1".into(), None)?;
+
+        let stream = TcpStream::connect(addr).await?;
+        let res = Follower::new(store.clone()).run(stream).await;
+        assert!(matches!(res, Err(KvError::InvalidCommand(msg)) if msg.contains("not enabled")));
+        // 复制没有开始，本地的数据不会被清掉
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+        Ok(())
+    }
+
+    async fn wait_for(f: impl Fn() -> bool) {
+        for _ in 0..100 {
+            if f() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("condition is not met in time");
+    }
+
+    async fn start_server<Store: Storage>(service: Service<Store>) -> anyhow::Result<SocketAddr> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(ProstServerStream::new(stream, service.clone()).process());
+            }
+        });
+        Ok(addr)
+    }
+}
@@ -0,0 +1,281 @@
+use crate::KvError;
+use bytes::{Buf, BufMut, BytesMut};
+use flate2::{read::GzDecoder, write::GzEncoder};
+use prost::Message;
+use std::io::{Read, Write};
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio_util::codec::{Decoder, Encoder};
+
+// 长度头占 4 个字节，大端序
+pub const LEN_LEN: usize = 4;
+// 默认单个 frame 最大 64M
+pub const MAX_FRAME: usize = 64 * 1024 * 1024;
+// 长度是对方给的，一次最多预先分配这么多，剩下的等数据到了再扩容
+pub(crate) const MAX_RESERVE: usize = 64 * 1024;
+// 长度头的最高位表示 frame 是 gzip 压缩过的，剩下的 31 位是长度
+pub const COMPRESSED_FLAG: u32 = 1 << 31;
+// 默认超过 1K 的 frame 才压缩
+pub const COMPRESSION_THRESHOLD: usize = 1024;
+// 协商时用的压缩算法的名字
+pub const GZIP: &str = "gzip";
+
+// 帧格式: | len: u32 | protobuf message |
+// In 是从字节流里解码出来的消息，Out 是要编码发送出去的消息
+// 解码时总是能处理压缩过的 frame，编码时只有协商打开了压缩才会压缩
+#[derive(Debug)]
+pub struct ProstCodec<In, Out> {
+    max_frame: usize,
+    compression: Compression,
+    compression_threshold: usize,
+    _in: PhantomData<In>,
+    _out: PhantomData<Out>,
+}
+
+// 压缩的开关，协商成功之后打开
+// 和 codec 共享，所以 Framed 被 split 之后也能打开
+#[derive(Debug, Clone, Default)]
+pub struct Compression(Arc<AtomicBool>);
+
+impl Compression {
+    pub fn enable(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+impl<In, Out> ProstCodec<In, Out> {
+    pub fn new() -> Self {
+        Self::with_max_frame(MAX_FRAME)
+    }
+
+    // 长度只有 31 位，超过的部分没有意义
+    pub fn with_max_frame(max_frame: usize) -> Self {
+        Self {
+            max_frame: max_frame.min((COMPRESSED_FLAG - 1) as usize),
+            compression: Compression::default(),
+            compression_threshold: COMPRESSION_THRESHOLD,
+            _in: PhantomData,
+            _out: PhantomData,
+        }
+    }
+
+    pub fn compression_threshold(mut self, threshold: usize) -> Self {
+        self.compression_threshold = threshold;
+        self
+    }
+
+    pub fn max_frame(&self) -> usize {
+        self.max_frame
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression.clone()
+    }
+}
+
+impl<In, Out> Default for ProstCodec<In, Out> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<In, Out> Decoder for ProstCodec<In, Out>
+where
+    In: Message + Default,
+{
+    type Item = In;
+    type Error = KvError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if src.len() < LEN_LEN {
+            return Ok(None);
+        }
+
+        // 只看长度，不消费，数据不够时要把整个 frame 留到下一次
+        let mut header = [0u8; LEN_LEN];
+        header.copy_from_slice(&src[..LEN_LEN]);
+        let header = u32::from_be_bytes(header);
+        let compressed = header & COMPRESSED_FLAG != 0;
+        let len = (header & !COMPRESSED_FLAG) as usize;
+        if len > self.max_frame {
+            return Err(KvError::FrameError(format!(
+                "frame length {} exceeds limit {}",
+                len, self.max_frame
+            )));
+        }
+
+        if src.len() < LEN_LEN + len {
+            src.reserve((LEN_LEN + len - src.len()).min(MAX_RESERVE));
+            return Ok(None);
+        }
+
+        src.advance(LEN_LEN);
+        let data = src.split_to(len);
+        if !compressed {
+            return Ok(Some(In::decode(data.freeze())?));
+        }
+
+        // 解压之后也不能超过限制，避免很小的 frame 解压出很大的数据
+        let mut buf = Vec::new();
+        GzDecoder::new(&data[..])
+            .take(self.max_frame as u64 + 1)
+            .read_to_end(&mut buf)?;
+        if buf.len() > self.max_frame {
+            return Err(KvError::FrameError(format!(
+                "decompressed frame length exceeds limit {}",
+                self.max_frame
+            )));
+        }
+        Ok(Some(In::decode(buf.as_slice())?))
+    }
+}
+
+impl<In, Out> Encoder<Out> for ProstCodec<In, Out>
+where
+    Out: Message,
+{
+    type Error = KvError;
+
+    fn encode(&mut self, item: Out, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let len = item.encoded_len();
+        if len > self.max_frame {
+            return Err(KvError::FrameError(format!(
+                "frame length {} exceeds limit {}",
+                len, self.max_frame
+            )));
+        }
+
+        if !self.compression.is_enabled() || len <= self.compression_threshold {
+            dst.reserve(LEN_LEN + len);
+            dst.put_u32(len as u32);
+            item.encode(dst)?;
+            return Ok(());
+        }
+
+        let mut buf = Vec::with_capacity(len);
+        item.encode(&mut buf)?;
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(&buf)?;
+        let compressed = encoder.finish()?;
+        // 压缩之后没有变小，就直接发送原来的数据
+        let (header, data) = match compressed.len() < len {
+            true => (compressed.len() as u32 | COMPRESSED_FLAG, compressed),
+            false => (len as u32, buf),
+        };
+        dst.reserve(LEN_LEN + data.len());
+        dst.put_u32(header);
+        dst.put_slice(&data);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CommandRequest, CommandResponse, Value};
+
+    #[test]
+    fn codec_should_encode_and_decode() {
+        let mut codec = ProstCodec::<CommandRequest, CommandRequest>::new();
+        let mut buf = BytesMut::new();
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        codec.encode(cmd.clone(), &mut buf).unwrap();
+
+        assert_eq!(buf.len(), LEN_LEN + cmd.encoded_len());
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(cmd));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn codec_should_handle_partial_frames() {
+        let mut codec = ProstCodec::<CommandResponse, CommandResponse>::new();
+        let mut encoded = BytesMut::new();
+        let res: CommandResponse = Value::from("world").into();
+        codec.encode(res.clone(), &mut encoded).unwrap();
+        codec.encode(res.clone(), &mut encoded).unwrap();
+
+        // 先只给 2 个字节的长度头，再给半个 frame
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(&encoded.split_to(2));
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        buf.extend_from_slice(&encoded.split_to(4));
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+
+        // 剩下的数据一次性到达，里面有一个半 frame 加上第二个 frame 的剩余部分
+        buf.extend_from_slice(&encoded);
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(res.clone()));
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(res));
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+    }
+
+    #[test]
+    fn codec_should_not_reserve_the_whole_frame_up_front() {
+        let mut codec = ProstCodec::<CommandRequest, CommandRequest>::new();
+        // 只收到长度头时，不会按声明的长度分配内存
+        let mut buf = BytesMut::new();
+        buf.put_u32((MAX_FRAME - 1) as u32);
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        assert!(buf.capacity() <= LEN_LEN + MAX_RESERVE);
+    }
+
+    #[test]
+    fn codec_should_reject_oversized_frame() {
+        let mut codec = ProstCodec::<CommandRequest, CommandRequest>::with_max_frame(16);
+        let mut buf = BytesMut::new();
+        let cmd = CommandRequest::new_hset("table", "key", "a long enough value".into());
+        assert!(matches!(
+            codec.encode(cmd, &mut buf),
+            Err(KvError::FrameError(_))
+        ));
+
+        buf.put_u32(17);
+        assert!(matches!(
+            codec.decode(&mut buf),
+            Err(KvError::FrameError(_))
+        ));
+    }
+
+    #[test]
+    fn codec_should_compress_large_frames_when_enabled() {
+        let mut codec = ProstCodec::<CommandRequest, CommandRequest>::new();
+        let big = CommandRequest::new_hset("t1", "k1", "a".repeat(10000).into());
+        let small = CommandRequest::new_hset("t1", "k1", "v1".into());
+
+        // 没有打开压缩时不压缩
+        let mut buf = BytesMut::new();
+        codec.encode(big.clone(), &mut buf).unwrap();
+        assert_eq!(buf.len(), LEN_LEN + big.encoded_len());
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(big.clone()));
+
+        codec.compression().enable();
+        codec.encode(big.clone(), &mut buf).unwrap();
+        assert!(buf.len() < 1000);
+        assert_ne!(buf[0] & 0x80, 0);
+        // 小于阈值的 frame 不压缩
+        codec.encode(small.clone(), &mut buf).unwrap();
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(big));
+        assert_eq!(buf.len(), LEN_LEN + small.encoded_len());
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(small));
+    }
+
+    #[test]
+    fn codec_should_reject_oversized_decompressed_frame() {
+        let mut codec = ProstCodec::<CommandRequest, CommandRequest>::new();
+        codec.compression().enable();
+        let mut buf = BytesMut::new();
+        let cmd = CommandRequest::new_hset("t1", "k1", "a".repeat(10000).into());
+        codec.encode(cmd, &mut buf).unwrap();
+
+        // 压缩后的数据很小，解压之后超过了限制
+        let mut codec = ProstCodec::<CommandRequest, CommandRequest>::with_max_frame(1024);
+        assert!(matches!(
+            codec.decode(&mut buf),
+            Err(KvError::FrameError(_))
+        ));
+    }
+}
@@ -0,0 +1,293 @@
+use super::{collect_response, is_streaming};
+use crate::{CommandRequest, CommandResponse, KvError, Service, Storage, Value};
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode, header::AUTHORIZATION, header::CONTENT_TYPE};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+
+// HTTP 网关，和 TCP 的服务端共用同一个 Service
+// GET/PUT/DELETE /tables/{table}/keys/{key} 读写一个 key，value 用 JSON 的值表示
+// POST /command 执行一个 JSON 格式的 CommandRequest，返回 JSON 格式的 CommandResponse
+// 配置了 ACL 时，用 Authorization: Bearer <token> 认证
+pub fn gateway<Store: Storage>(service: Service<Store>) -> Router {
+    Router::new()
+        .route(
+            "/tables/{table}/keys/{key}",
+            get(get_key::<Store>)
+                .put(put_key::<Store>)
+                .delete(delete_key::<Store>),
+        )
+        .route("/command", post(command::<Store>))
+        .with_state(service)
+}
+
+// 管理用的 HTTP 接口，GET /metrics 返回 Prometheus 文本格式的指标
+// 一般只监听内网地址，所以没有认证
+pub fn admin<Store: Storage>(service: Service<Store>) -> Router {
+    Router::new()
+        .route("/metrics", get(metrics::<Store>))
+        .with_state(service)
+}
+
+async fn metrics<Store: Storage>(State(service): State<Service<Store>>) -> Response {
+    match service.metrics() {
+        Ok(text) => ([(CONTENT_TYPE, "text/plain; version=0.0.4")], text).into_response(),
+        Err(e) => error_response(e.into()),
+    }
+}
+
+async fn get_key<Store: Storage>(
+    State(service): State<Service<Store>>,
+    Path((table, key)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Response {
+    let res = execute(&service, &headers, CommandRequest::new_hget(table, key)).await;
+    value_response(res)
+}
+
+// 返回原来的值
+async fn put_key<Store: Storage>(
+    State(service): State<Service<Store>>,
+    Path((table, key)): Path<(String, String)>,
+    headers: HeaderMap,
+    Json(value): Json<serde_json::Value>,
+) -> Response {
+    let value = match Value::try_from(value) {
+        Ok(v) => v,
+        Err(e) => return error_response(e.into()),
+    };
+    let cmd = CommandRequest::new_hset(table, key, value);
+    value_response(execute(&service, &headers, cmd).await)
+}
+
+// 返回删除的值
+async fn delete_key<Store: Storage>(
+    State(service): State<Service<Store>>,
+    Path((table, key)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Response {
+    let res = execute(&service, &headers, CommandRequest::new_hdel(table, key)).await;
+    value_response(res)
+}
+
+async fn command<Store: Storage>(
+    State(service): State<Service<Store>>,
+    headers: HeaderMap,
+    Json(cmd): Json<CommandRequest>,
+) -> Response {
+    // 一个 HTTP 请求只能有一个响应，订阅和复制要用 TCP 连接
+    if is_streaming(&cmd) {
+        let res: CommandResponse =
+            KvError::InvalidCommand("Streaming commands are not supported over HTTP".into()).into();
+        return (status_code(&res), Json(res)).into_response();
+    }
+    let res = execute(&service, &headers, cmd).await;
+    (status_code(&res), Json(res)).into_response()
+}
+
+// 每个 HTTP 请求用一个新的 Session，有 token 时先认证
+async fn execute<Store: Storage>(
+    service: &Service<Store>,
+    headers: &HeaderMap,
+    cmd: CommandRequest,
+) -> CommandResponse {
+    let mut session = service.session();
+    if let Some(token) = bearer_token(headers) {
+        let res = collect_response(session.execute(CommandRequest::new_auth_token(token))).await;
+        if !(200..300).contains(&res.status) {
+            return res;
+        }
+    }
+    collect_response(session.execute(cmd)).await
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+fn status_code(res: &CommandResponse) -> StatusCode {
+    u16::try_from(res.status)
+        .ok()
+        .and_then(|v| StatusCode::from_u16(v).ok())
+        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+fn value_response(res: CommandResponse) -> Response {
+    if !(200..300).contains(&res.status) {
+        return error_response(res);
+    }
+    let status = status_code(&res);
+    let value = res.values.into_iter().next().unwrap_or_default();
+    (status, Json(serde_json::Value::from(value))).into_response()
+}
+
+fn error_response(res: CommandResponse) -> Response {
+    let code = res.error.as_ref().map(|e| e.code()).unwrap_or_default();
+    let body = serde_json::json!({
+        "status": res.status,
+        "code": code.as_str_name(),
+        "message": res.message,
+    });
+    (status_code(&res), Json(body)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Acl, MemTable, Permission, ServiceInner, User};
+    use axum::body::{Body, to_bytes};
+    use axum::http::{Method, Request};
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn gateway_should_read_and_write_keys() -> anyhow::Result<()> {
+        let app = gateway::<MemTable>(ServiceInner::new(MemTable::new()).into());
+
+        let (status, body) = call(&app, Method::GET, "/tables/t1/keys/k1", None, None).await?;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["status"], 404);
+        assert_eq!(body["code"], "ERROR_CODE_NOT_FOUND");
+
+        let value = Some(json!("v1"));
+        let (status, body) = call(&app, Method::PUT, "/tables/t1/keys/k1", value, None).await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!(null));
+
+        let value = Some(json!(42));
+        let (_, body) = call(&app, Method::PUT, "/tables/t1/keys/k1", value, None).await?;
+        assert_eq!(body, json!("v1"));
+        let (status, body) = call(&app, Method::GET, "/tables/t1/keys/k1", None, None).await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!(42));
+
+        // 嵌套的文档原样返回
+        let doc = json!({ "name": "alice", "tags": ["a", null], "profile": { "age": 30 } });
+        let value = Some(doc.clone());
+        call(&app, Method::PUT, "/tables/t1/keys/k2", value, None).await?;
+        let (_, body) = call(&app, Method::GET, "/tables/t1/keys/k2", None, None).await?;
+        assert_eq!(body, doc);
+
+        // 不能表示成 Value 的 JSON
+        let value = Some(json!({ "$binary": "not base64!" }));
+        let (status, _) = call(&app, Method::PUT, "/tables/t1/keys/k3", value, None).await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (_, body) = call(&app, Method::DELETE, "/tables/t1/keys/k1", None, None).await?;
+        assert_eq!(body, json!(42));
+        let (status, _) = call(&app, Method::GET, "/tables/t1/keys/k1", None, None).await?;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn gateway_should_execute_json_command() -> anyhow::Result<()> {
+        let app = gateway::<MemTable>(ServiceInner::new(MemTable::new()).into());
+
+        let cmd = json!({
+            "request_data": { "hset": { "table": "t1", "pair": { "key": "k1", "value": 10 } } }
+        });
+        let (status, _) = call(&app, Method::POST, "/command", Some(cmd), None).await?;
+        assert_eq!(status, StatusCode::OK);
+
+        let cmd = json!({ "request_data": { "hget": { "table": "t1", "key": "k1" } } });
+        let (status, body) = call(&app, Method::POST, "/command", Some(cmd), None).await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["values"][0], 10);
+
+        let cmd = json!({ "request_data": { "hget": { "table": "t1", "key": "k2" } } });
+        let (status, body) = call(&app, Method::POST, "/command", Some(cmd), None).await?;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["status"], 404);
+
+        let cmd = json!({ "request_data": { "subscribe": { "topic": "lobby" } } });
+        let (status, _) = call(&app, Method::POST, "/command", Some(cmd), None).await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn gateway_should_treat_2xx_as_success() -> anyhow::Result<()> {
+        // hook 可能把成功的 status 改成其它 2xx
+        let service = ServiceInner::new(MemTable::new()).fn_before_send(|res| {
+            if res.status == 200 {
+                res.status = 201;
+            }
+        });
+        let app = gateway::<MemTable>(service.into());
+
+        let value = Some(json!("v1"));
+        call(&app, Method::PUT, "/tables/t1/keys/k1", value, None).await?;
+        let (status, body) = call(&app, Method::GET, "/tables/t1/keys/k1", None, None).await?;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(body, json!("v1"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn gateway_should_authenticate_with_bearer_token() -> anyhow::Result<()> {
+        let acl = Acl::new().user(
+            User::new("bot")
+                .token("t0ken")
+                .grant("t1", Permission::Read),
+        );
+        let app = gateway::<MemTable>(ServiceInner::new(MemTable::new()).acl(acl).into());
+
+        let (status, _) = call(&app, Method::GET, "/tables/t1/keys/k1", None, None).await?;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let token = Some("wrong");
+        let (status, _) = call(&app, Method::GET, "/tables/t1/keys/k1", None, token).await?;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let token = Some("t0ken");
+        let (status, _) = call(&app, Method::GET, "/tables/t1/keys/k1", None, token).await?;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let value = Some(json!("v1"));
+        let (status, _) = call(&app, Method::PUT, "/tables/t1/keys/k1", value, token).await?;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn admin_should_export_metrics() -> anyhow::Result<()> {
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        let app = gateway(service.clone());
+        call(&app, Method::GET, "/tables/admin_t1/keys/k1", None, None).await?;
+
+        let req = Request::builder().uri("/metrics").body(Body::empty())?;
+        let res = admin(service).oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = to_bytes(res.into_body(), usize::MAX).await?;
+        let text = String::from_utf8(body.to_vec())?;
+        assert!(text.contains("kv_commands_total{command=\"hget\"}"));
+        Ok(())
+    }
+
+    async fn call(
+        app: &Router,
+        method: Method,
+        uri: &str,
+        body: Option<serde_json::Value>,
+        token: Option<&str>,
+    ) -> anyhow::Result<(StatusCode, serde_json::Value)> {
+        let mut req = Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            req = req.header(AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let req = match body {
+            Some(body) => req
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))?,
+            None => req.body(Body::empty())?,
+        };
+        let res = app.clone().oneshot(req).await?;
+        let status = res.status();
+        let body = to_bytes(res.into_body(), usize::MAX).await?;
+        Ok((status, serde_json::from_slice(&body)?))
+    }
+}
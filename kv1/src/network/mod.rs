@@ -0,0 +1,844 @@
+mod follower;
+mod frame;
+mod gateway;
+mod pipeline;
+mod resp;
+mod stream_result;
+mod tls;
+
+use crate::command_request::RequestData;
+use crate::metrics::ConnectionGuard;
+use crate::{
+    CommandRequest, CommandResponse, KvError, Kvpair, MemTable, Service, Session, Storage,
+    StreamingResponse, Value,
+};
+use futures::stream::{BoxStream, SelectAll};
+use futures::{Sink, SinkExt, Stream, StreamExt, stream};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc;
+use tokio_util::codec::Framed;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+
+pub use follower::Follower;
+pub use frame::{
+    COMPRESSED_FLAG, COMPRESSION_THRESHOLD, Compression, GZIP, LEN_LEN, MAX_FRAME, ProstCodec,
+};
+pub use gateway::{admin, gateway};
+pub use pipeline::PipelineClient;
+pub use resp::{RespCodec, RespServerStream, RespValue};
+pub use stream_result::StreamResult;
+pub use tls::{TlsClientConnector, TlsServerAcceptor};
+
+// 带着请求 id 的一组响应
+type IdResponses = BoxStream<'static, (u64, Arc<CommandResponse>)>;
+
+// 一个连接上最多同时执行这么多带 id 的请求，超过之后先不读新的请求
+const MAX_IN_FLIGHT: usize = 128;
+// 等待 writer 发送的响应个数
+const OUTGOING_CAPACITY: usize = 64;
+
+// 服务端读 CommandRequest，写 CommandResponse
+pub struct ProstServerStream<S, Store = MemTable> {
+    inner: Framed<S, ProstCodec<CommandRequest, CommandResponse>>,
+    service: Service<Store>,
+    shutdown: CancellationToken,
+}
+
+// 客户端读 CommandResponse，写 CommandRequest
+pub struct ProstClientStream<S> {
+    inner: Framed<S, ProstCodec<CommandResponse, CommandRequest>>,
+}
+
+impl<S, Store> ProstServerStream<S, Store>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+    Store: Storage,
+{
+    pub fn new(stream: S, service: Service<Store>) -> Self {
+        Self::with_codec(stream, service, ProstCodec::new())
+    }
+
+    pub fn with_codec(
+        stream: S,
+        service: Service<Store>,
+        codec: ProstCodec<CommandRequest, CommandResponse>,
+    ) -> Self {
+        Self {
+            inner: Framed::new(stream, codec),
+            service,
+            shutdown: CancellationToken::new(),
+        }
+    }
+
+    // token 取消之后不再读新的请求，已经收到的请求处理完之后关闭连接
+    pub fn with_shutdown(mut self, shutdown: CancellationToken) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    pub async fn process(self) -> Result<(), KvError> {
+        let _connection = ConnectionGuard::new("prost");
+        let service = self.service;
+        let mut session = service.session();
+        let compression = self.inner.codec().compression();
+        let (sink, mut stream) = self.inner.split();
+        // 响应交给 writer 发送，发送的时候也能继续读请求
+        // 否则客户端和服务端都在写、都不读的时候会互相等待
+        let (tx, rx) = mpsc::channel(OUTGOING_CAPACITY);
+        let writer = write_responses(sink, rx, service.clone());
+
+        let reader = async move {
+            // 订阅或者复制推送的数据，一个连接同时只能有一个
+            let mut active: Option<(u64, StreamingResponse)> = None;
+            // 带 id 的请求在后台并发执行，响应按完成的顺序发送
+            let mut pending: SelectAll<IdResponses> = SelectAll::new();
+            loop {
+                tokio::select! {
+                    // 优先发送响应，保证普通命令的响应顺序和请求一致
+                    biased;
+                    (id, res) = next_active(&mut active), if active.is_some() => match res {
+                        Some(res) => send(&tx, id, res.as_ref().clone(), None).await?,
+                        None => active = None,
+                    },
+                    Some((id, res)) = pending.next() => {
+                        send(&tx, id, res.as_ref().clone(), None).await?
+                    }
+                    _ = self.shutdown.cancelled() => break,
+                    // 在执行的请求太多时先不读，等有请求执行完
+                    cmd = stream.next(), if pending.len() < MAX_IN_FLIGHT => {
+                        let Some(cmd) = cmd else { break };
+                        let cmd = cmd?;
+                        debug!("Got a new command: {:?}", cmd);
+                        let id = cmd.id;
+                        if let Some(RequestData::Negotiate(param)) = &cmd.request_data {
+                            // 响应还是不压缩的，writer 发完之后再打开压缩
+                            let gzip = param.compression.iter().any(|v| v == GZIP);
+                            let values: Vec<Value> = match gzip {
+                                true => vec![GZIP.into()],
+                                false => Vec::new(),
+                            };
+                            let enable = gzip.then(|| compression.clone());
+                            send(&tx, id, values.into(), enable).await?;
+                        } else if is_streaming(&cmd) {
+                            if active.is_some() {
+                                let res = KvError::InvalidCommand(
+                                    "Connection already has an active stream".into(),
+                                );
+                                send(&tx, id, res.into(), None).await?;
+                            } else {
+                                active = Some((id, session.execute(cmd)));
+                            }
+                        } else if id != 0 && !matches!(cmd.request_data, Some(RequestData::Auth(_))) {
+                            pending.push(spawn_execute(session.clone(), cmd));
+                        } else {
+                            // 普通命令的响应要连续发完，中间不能插入其它命令的响应
+                            let mut responses = session.execute(cmd);
+                            while let Some(res) = responses.next().await {
+                                send(&tx, id, res.as_ref().clone(), None).await?;
+                            }
+                        }
+                    }
+                }
+            }
+            // 连接已经不会再有新的请求，或者服务端要关闭了，把还在执行的请求的响应发完，订阅直接结束
+            while let Some((id, res)) = pending.next().await {
+                send(&tx, id, res.as_ref().clone(), None).await?;
+            }
+            Ok::<_, KvError>(())
+        };
+
+        tokio::try_join!(reader, writer)?;
+        Ok(())
+    }
+}
+
+async fn next_active(
+    active: &mut Option<(u64, StreamingResponse)>,
+) -> (u64, Option<Arc<CommandResponse>>) {
+    match active {
+        Some((id, responses)) => (*id, responses.next().await),
+        None => (0, None),
+    }
+}
+
+// 在后台执行带 id 的请求，不会阻塞后面的请求
+fn spawn_execute<Store: Storage>(mut session: Session<Store>, cmd: CommandRequest) -> IdResponses {
+    let id = cmd.id;
+    let (tx, rx) = mpsc::channel(16);
+    tokio::spawn(async move {
+        let mut responses = session.execute(cmd);
+        while let Some(res) = responses.next().await {
+            // 连接已经断开了
+            if tx.send(res).await.is_err() {
+                break;
+            }
+        }
+    });
+    stream::unfold(rx, move |mut rx| async move {
+        let res = rx.recv().await?;
+        Some(((id, res), rx))
+    })
+    .boxed()
+}
+
+// 订阅和复制会一直推送数据，直到取消或者连接断开
+fn is_streaming(cmd: &CommandRequest) -> bool {
+    matches!(
+        cmd.request_data,
+        Some(RequestData::Subscribe(_) | RequestData::Replicate(_))
+    )
+}
+
+// 发给 writer 的响应，带着发完之后要打开的压缩
+type Outgoing = (CommandResponse, Option<Compression>);
+
+// 响应带上请求的 id 交给 writer
+async fn send(
+    tx: &mpsc::Sender<Outgoing>,
+    id: u64,
+    mut res: CommandResponse,
+    compression: Option<Compression>,
+) -> Result<(), KvError> {
+    res.id = id;
+    tx.send((res, compression))
+        .await
+        .map_err(|_| KvError::Internal("Connection is closed".into()))
+}
+
+// 把响应依次写到连接，写完之后触发 after_send 的 hook
+async fn write_responses<W, Store>(
+    mut sink: W,
+    mut rx: mpsc::Receiver<Outgoing>,
+    service: Service<Store>,
+) -> Result<(), KvError>
+where
+    W: Sink<CommandResponse, Error = KvError> + Unpin,
+    Store: Storage,
+{
+    while let Some((res, compression)) = rx.recv().await {
+        sink.send(res).await?;
+        service.notify_sent();
+        if let Some(compression) = compression {
+            compression.enable();
+        }
+    }
+    Ok(())
+}
+
+impl<S> ProstClientStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    pub fn new(stream: S) -> Self {
+        Self::with_codec(stream, ProstCodec::new())
+    }
+
+    pub fn with_codec(stream: S, codec: ProstCodec<CommandResponse, CommandRequest>) -> Self {
+        Self {
+            inner: Framed::new(stream, codec),
+        }
+    }
+
+    // 分成多个响应返回的结果会合并成一个
+    pub async fn execute(&mut self, cmd: CommandRequest) -> Result<CommandResponse, KvError> {
+        self.inner.send(cmd).await?;
+        let mut res = self.next_response().await?;
+        while res.more {
+            let next = self.next_response().await?;
+            if next.status != res.status {
+                return Ok(next);
+            }
+            res.values.extend(next.values);
+            res.pairs.extend(next.pairs);
+            res.more = next.more;
+        }
+        Ok(res)
+    }
+
+    // 和服务端协商压缩，成功之后超过阈值的 frame 双向都会压缩
+    // 旧版本的服务端不支持时返回 false，连接照常使用
+    pub async fn negotiate_compression(&mut self) -> Result<bool, KvError> {
+        let res = self.execute(CommandRequest::new_negotiate(&[GZIP])).await?;
+        let accepted = res.status == 200 && res.values.contains(&GZIP.into());
+        if accepted {
+            self.inner.codec().compression().enable();
+        }
+        Ok(accepted)
+    }
+
+    // 像 Hgetall 这样结果很大的命令，边收边返回其中的 Kvpair
+    // 要把流读完再执行下一个命令，否则剩下的响应会被下一个命令读到
+    pub async fn execute_pairs(
+        &mut self,
+        cmd: CommandRequest,
+    ) -> Result<impl Stream<Item = Result<Kvpair, KvError>> + '_, KvError> {
+        self.inner.send(cmd).await?;
+        let responses = stream::unfold(Some(self), |client| async move {
+            let client = client?;
+            let (pairs, client) = match client
+                .next_response()
+                .await
+                .and_then(|res| res.into_result())
+            {
+                Ok(res) => {
+                    let more = res.more;
+                    (
+                        res.pairs.into_iter().map(Ok).collect(),
+                        more.then_some(client),
+                    )
+                }
+                Err(e) => (vec![Err(e)], None),
+            };
+            Some((stream::iter(pairs), client))
+        });
+        Ok(responses.flatten())
+    }
+
+    async fn next_response(&mut self) -> Result<CommandResponse, KvError> {
+        match self.inner.next().await {
+            Some(res) => res,
+            None => Err(KvError::Internal("Didn't get any response".into())),
+        }
+    }
+
+    // 订阅之后这个连接只用来接收推送的数据
+    pub async fn execute_streaming(mut self, cmd: CommandRequest) -> Result<StreamResult, KvError>
+    where
+        S: 'static,
+    {
+        self.inner.send(cmd).await?;
+        StreamResult::new(self.inner).await
+    }
+}
+
+// Hgetall 这样分成多个响应的结果合并成一个
+pub(crate) async fn collect_response(mut responses: StreamingResponse) -> CommandResponse {
+    let mut res = match responses.next().await {
+        Some(res) => res.as_ref().clone(),
+        None => return KvError::Internal("Didn't get any response".into()).into(),
+    };
+    while res.more {
+        let Some(next) = responses.next().await else {
+            break;
+        };
+        if next.status != res.status {
+            return next.as_ref().clone();
+        }
+        res.values.extend(next.values.iter().cloned());
+        res.pairs.extend(next.pairs.iter().cloned());
+        res.more = next.more;
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Acl, ErrorCode, Permission, ServiceInner, SledDb, User, Value, assert_res_error,
+        assert_res_ok,
+    };
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tempfile::tempdir;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_util::codec::{FramedRead, FramedWrite};
+
+    #[tokio::test]
+    async fn client_server_basic_communication_should_work() -> anyhow::Result<()> {
+        let addr = start_server(ServiceInner::new(MemTable::new()).into()).await?;
+
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream);
+
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        let res = client.execute(cmd).await?;
+        assert_res_ok(res, &[Value::default()], &[]);
+
+        let cmd = CommandRequest::new_hget("t1", "k1");
+        let res = client.execute(cmd).await?;
+        assert_res_ok(res, &["v1".into()], &[]);
+
+        let cmd = CommandRequest::new_hget("t1", "k2");
+        let res = client.execute(cmd).await?;
+        assert_res_error(res, 404, "Not found");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_should_get_typed_errors() -> anyhow::Result<()> {
+        let addr = start_server(ServiceInner::new(MemTable::new()).into()).await?;
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream);
+
+        let res = client.execute(CommandRequest::new_hget("t1", "k1")).await?;
+        assert_eq!(res.error.as_ref().unwrap().code(), ErrorCode::NotFound);
+        assert_eq!(
+            res.into_result().err(),
+            Some(KvError::NotFound("t1".into(), "k1".into()))
+        );
+
+        client
+            .execute(CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .await?;
+        let res = client.execute(CommandRequest::new_hget("t1", "k1")).await?;
+        assert!(res.into_result().is_ok());
+
+        let res = client
+            .execute(CommandRequest::new_hincrby("t1", "k1", 1))
+            .await?;
+        assert_eq!(
+            res.into_result().err(),
+            Some(KvError::ConvertError("v1".into(), "Integer"))
+        );
+
+        // 事务回滚时能拿到导致回滚的错误，status 也和它一致
+        let cmd = CommandRequest::new_transaction(vec![
+            CommandRequest::new_hset("t1", "k2", "v2".into()),
+            CommandRequest::new_hincrby("t1", "k2", 1),
+        ]);
+        let res = client.execute(cmd).await?;
+        assert_eq!(res.status, 500);
+        assert_eq!(
+            res.into_result().err(),
+            Some(KvError::Rollback(Box::new(KvError::ConvertError(
+                "v2".into(),
+                "Integer"
+            ))))
+        );
+
+        // Hexist 没找到也是 404
+        let res = client
+            .execute(CommandRequest::new_hexist("t1", "k3"))
+            .await?;
+        assert_eq!(res.status, 404);
+        assert!(matches!(res.into_result(), Err(KvError::NotFound(..))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_server_should_negotiate_compression() -> anyhow::Result<()> {
+        let addr = start_server(ServiceInner::new(MemTable::new()).into()).await?;
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream);
+        assert!(client.negotiate_compression().await?);
+
+        let value: Value = "a".repeat(100_000).into();
+        let cmd = CommandRequest::new_hset("t1", "k1", value.clone());
+        client.execute(cmd).await?;
+        let res = client.execute(CommandRequest::new_hget("t1", "k1")).await?;
+        assert_res_ok(res, &[value], &[]);
+
+        // 没有协商的客户端照常使用
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream);
+        let res = client.execute(CommandRequest::new_hget("t1", "k1")).await?;
+        assert_eq!(res.values, &["a".repeat(100_000).into()]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn negotiate_should_fall_back_with_old_server() -> anyhow::Result<()> {
+        let (client, server) = tokio::io::duplex(4096);
+        // 旧版本的服务端不认识 Negotiate，解码出来的请求是空的
+        tokio::spawn(async move {
+            let mut server =
+                Framed::new(server, ProstCodec::<CommandRequest, CommandResponse>::new());
+            while let Some(Ok(_)) = server.next().await {
+                let res = KvError::InvalidCommand("Request has no data".into()).into();
+                server.send(res).await.unwrap();
+            }
+        });
+        let mut client = ProstClientStream::new(client);
+        assert!(!client.negotiate_compression().await?);
+        assert!(!client.inner.codec().compression().is_enabled());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn server_should_finish_requests_on_shutdown() -> anyhow::Result<()> {
+        // 每个命令执行前等一会，保证关闭的时候请求还在执行
+        let service: Service = ServiceInner::new(MemTable::new())
+            .fn_received_async(|_: CommandRequest| async {
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await
+            })
+            .into();
+        let (client, server) = tokio::io::duplex(4096);
+        let shutdown = CancellationToken::new();
+        let server = ProstServerStream::new(server, service).with_shutdown(shutdown.clone());
+        let handle = tokio::spawn(server.process());
+
+        let mut client = Framed::new(client, ProstCodec::<CommandResponse, CommandRequest>::new());
+        client
+            .send(CommandRequest::new_hset("t1", "k1", "v1".into()).with_id(1))
+            .await?;
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        shutdown.cancel();
+
+        // 已经收到的请求还是会有响应，之后连接关闭
+        let res = client.next().await.unwrap()?;
+        assert_eq!((res.id, res.status), (1, 200));
+        assert!(client.next().await.is_none());
+        handle.await??;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_server_with_sleddb_should_work() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let addr = start_server(ServiceInner::new(SledDb::new(dir)).into()).await?;
+
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream);
+
+        let cmd = CommandRequest::new_hset("t1", "k1", 10.into());
+        client.execute(cmd).await?;
+        let res = client.execute(CommandRequest::new_hget("t1", "k1")).await?;
+        assert_res_ok(res, &[10.into()], &[]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn server_should_handle_large_values() -> anyhow::Result<()> {
+        let (client, server) = tokio::io::duplex(1024);
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        tokio::spawn(ProstServerStream::new(server, service).process());
+
+        let mut client = ProstClientStream::new(client);
+        let v: Value = "x".repeat(64 * 1024).as_str().into();
+        client
+            .execute(CommandRequest::new_hset("t1", "big", v.clone()))
+            .await?;
+        let res = client
+            .execute(CommandRequest::new_hget("t1", "big"))
+            .await?;
+        assert_res_ok(res, &[v], &[]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_server_hgetall_should_stream() -> anyhow::Result<()> {
+        let (client, server) = tokio::io::duplex(4096);
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        tokio::spawn(ProstServerStream::new(server, service).process());
+
+        let mut client = ProstClientStream::new(client);
+        let pairs: Vec<_> = (0..1000)
+            .map(|i| Kvpair::new(format!("k{:04}", i), Value::from(i as i64)))
+            .collect();
+        client
+            .execute(CommandRequest::new_hmset("t1", pairs.clone()))
+            .await?;
+
+        let stream = client
+            .execute_pairs(CommandRequest::new_hgetall("t1"))
+            .await?;
+        let mut data: Vec<_> = stream
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<Result<_, _>>()?;
+        data.sort_by(|a, b| a.key.cmp(&b.key));
+        assert_eq!(data, pairs);
+
+        // execute 会把所有的响应合并起来
+        let res = client.execute(CommandRequest::new_hgetall("t1")).await?;
+        assert_eq!(res.pairs.len(), 1000);
+        assert!(!res.more);
+
+        let res = client
+            .execute(CommandRequest::new_hget("t1", "k0001"))
+            .await?;
+        assert_res_ok(res, &[1.into()], &[]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn after_send_hook_should_fire_after_each_response() -> anyhow::Result<()> {
+        let sent = Arc::new(AtomicUsize::new(0));
+        let counter = sent.clone();
+        let service: Service = ServiceInner::new(MemTable::new())
+            .fn_after_send(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+            })
+            .into();
+        let (client, server) = tokio::io::duplex(1024);
+        tokio::spawn(ProstServerStream::new(server, service).process());
+
+        let mut client = ProstClientStream::new(client);
+        client
+            .execute(CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .await?;
+        client.execute(CommandRequest::new_hget("t1", "k1")).await?;
+
+        // hook 在响应写出去之后才调用，客户端可能先收到了响应
+        for _ in 0..50 {
+            if sent.load(Ordering::SeqCst) == 2 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(sent.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn connection_should_be_authenticated() -> anyhow::Result<()> {
+        let acl = Acl::new().user(
+            User::new("alice")
+                .password("secret")
+                .grant("t1", Permission::ReadWrite),
+        );
+        let service: Service = ServiceInner::new(MemTable::new()).acl(acl).into();
+        let addr = start_server(service).await?;
+
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream);
+        let res = client.execute(CommandRequest::new_hget("t1", "k1")).await?;
+        assert_res_error(res, 401, "authentication required");
+        let res = client
+            .execute(CommandRequest::new_auth("alice", "wrong"))
+            .await?;
+        assert_res_error(res, 401, "invalid credentials");
+        let res = client
+            .execute(CommandRequest::new_auth("alice", "secret"))
+            .await?;
+        assert_res_ok(res, &[], &[]);
+
+        let res = client
+            .execute(CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .await?;
+        assert_res_ok(res, &[Value::default()], &[]);
+        let res = client
+            .execute(CommandRequest::new_hset("t2", "k1", "v1".into()))
+            .await?;
+        assert_res_error(res, 403, "Permission denied");
+
+        // 认证状态属于连接，新的连接要重新认证
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream);
+        let res = client.execute(CommandRequest::new_hget("t1", "k1")).await?;
+        assert_res_error(res, 401, "Unauthorized");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn auth_secrets_should_not_be_logged() -> anyhow::Result<()> {
+        // 把这个线程的日志都写到 logs 里，服务端也在这个线程上执行
+        #[derive(Clone, Default)]
+        struct Logs(Arc<parking_lot::Mutex<Vec<u8>>>);
+        impl std::io::Write for Logs {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let logs = Logs::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::TRACE)
+            .with_writer({
+                let logs = logs.clone();
+                move || logs.clone()
+            })
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let acl = Acl::new().user(User::new("alice").password("secret-password"));
+        let service: Service = ServiceInner::new(MemTable::new()).acl(acl).into();
+        // 不通过 Session 执行的 Auth 会被拒绝，拒绝的时候也会记日志
+        service
+            .execute(CommandRequest::new_auth("alice", "secret-password"))
+            .next()
+            .await;
+        let addr = start_server(service).await?;
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream);
+        let res = client
+            .execute(CommandRequest::new_auth("alice", "secret-password"))
+            .await?;
+        assert_res_ok(res, &[], &[]);
+
+        let logs = String::from_utf8(logs.0.lock().clone())?;
+        assert!(logs.contains("alice"));
+        assert!(!logs.contains("secret-password"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn requests_with_id_should_be_processed_concurrently() -> anyhow::Result<()> {
+        // 读 slow 这个 table 的请求要等一会儿才执行
+        let service: Service = ServiceInner::new(MemTable::new())
+            .fn_received_async(|cmd| async move {
+                if let Some(RequestData::Hget(v)) = cmd.request_data
+                    && v.table == "slow"
+                {
+                    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                }
+            })
+            .into();
+        let (client, server) = tokio::io::duplex(4096);
+        tokio::spawn(ProstServerStream::new(server, service).process());
+
+        let mut client = Framed::new(client, ProstCodec::<CommandResponse, CommandRequest>::new());
+        client
+            .send(CommandRequest::new_hget("slow", "k1").with_id(1))
+            .await?;
+        client
+            .send(CommandRequest::new_hset("t1", "k1", "v1".into()).with_id(2))
+            .await?;
+        client
+            .send(CommandRequest::new_hget("t1", "k1").with_id(3))
+            .await?;
+
+        // 后发的请求先完成，每个响应都带着对应请求的 id
+        let res = client.next().await.unwrap()?;
+        assert_eq!(res.id, 2);
+        let res = client.next().await.unwrap()?;
+        assert_eq!(res.id, 3);
+        assert_eq!(res.values, vec!["v1".into()]);
+        let res = client.next().await.unwrap()?;
+        assert_eq!(res.id, 1);
+        assert_eq!(res.status, 404);
+
+        // 没有 id 的请求还是按顺序逐个处理
+        client.send(CommandRequest::new_hget("slow", "k1")).await?;
+        client.send(CommandRequest::new_hget("t1", "k1")).await?;
+        let res = client.next().await.unwrap()?;
+        assert_eq!((res.id, res.status), (0, 404));
+        let res = client.next().await.unwrap()?;
+        assert_eq!((res.id, res.status), (0, 200));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn in_flight_requests_should_be_limited() -> anyhow::Result<()> {
+        // 所有的请求都卡在执行之前，一直不会完成
+        let received = Arc::new(AtomicUsize::new(0));
+        let counter = received.clone();
+        let service: Service = ServiceInner::new(MemTable::new())
+            .fn_received(move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+            })
+            .fn_received_async(|_| std::future::pending())
+            .into();
+        let (client, server) = tokio::io::duplex(1024 * 1024);
+        tokio::spawn(ProstServerStream::new(server, service).process());
+
+        let mut client = Framed::new(client, ProstCodec::<CommandResponse, CommandRequest>::new());
+        for id in 1..=MAX_IN_FLIGHT as u64 * 2 {
+            client
+                .send(CommandRequest::new_hget("t1", "k1").with_id(id))
+                .await?;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert_eq!(received.load(Ordering::SeqCst), MAX_IN_FLIGHT);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pending_responses_should_be_sent_after_client_closes_write() -> anyhow::Result<()> {
+        let (client, server) = tokio::io::duplex(4096);
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        tokio::spawn(ProstServerStream::new(server, service).process());
+
+        let (read, write) = tokio::io::split(client);
+        let mut sink =
+            FramedWrite::new(write, ProstCodec::<CommandResponse, CommandRequest>::new());
+        let stream = FramedRead::new(read, ProstCodec::<CommandResponse, CommandRequest>::new());
+        for i in 1..=10 {
+            sink.send(CommandRequest::new_hset("t1", "k1", i.into()).with_id(i as u64))
+                .await?;
+        }
+        sink.close().await?;
+        drop(sink);
+
+        let mut ids: Vec<_> = stream.map(|res| res.unwrap().id).collect().await;
+        ids.sort();
+        assert_eq!(ids, (1..=10).collect::<Vec<_>>());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_server_pub_sub_should_work() -> anyhow::Result<()> {
+        let addr = start_server(ServiceInner::new(MemTable::new()).into()).await?;
+
+        let stream = TcpStream::connect(addr).await?;
+        let subscriber = ProstClientStream::new(stream);
+        let mut sub = subscriber
+            .execute_streaming(CommandRequest::new_subscribe("lobby"))
+            .await?;
+        let id = sub.id;
+
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream);
+        let res = client
+            .execute(CommandRequest::new_publish("lobby", vec!["hello".into()]))
+            .await?;
+        assert_res_ok(res, &[], &[]);
+
+        let data = sub.next().await.unwrap()?;
+        assert_res_ok(data, &["hello".into()], &[]);
+
+        // 别的连接不能取消这个订阅
+        let res = client
+            .execute(CommandRequest::new_unsubscribe("lobby", id))
+            .await?;
+        assert_res_error(res, 404, "Not found");
+        client
+            .execute(CommandRequest::new_publish("lobby", vec!["world".into()]))
+            .await?;
+        let data = sub.next().await.unwrap()?;
+        assert_res_ok(data, &["world".into()], &[]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unsubscribe_on_same_connection_should_end_stream() -> anyhow::Result<()> {
+        let (client, server) = tokio::io::duplex(1024);
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        tokio::spawn(ProstServerStream::new(server, service).process());
+
+        let mut client = Framed::new(client, ProstCodec::<CommandResponse, CommandRequest>::new());
+        client.send(CommandRequest::new_subscribe("lobby")).await?;
+        let res = client.next().await.unwrap()?;
+        let id: i64 = res.values[0].clone().try_into()?;
+
+        client
+            .send(CommandRequest::new_publish("lobby", vec![1.into()]))
+            .await?;
+        client
+            .send(CommandRequest::new_unsubscribe("lobby", id as _))
+            .await?;
+        // 先是 publish 的响应，然后是推送的数据，最后是 unsubscribe 的响应
+        assert_res_ok(client.next().await.unwrap()?, &[], &[]);
+        assert_res_ok(client.next().await.unwrap()?, &[1.into()], &[]);
+        assert_res_ok(client.next().await.unwrap()?, &[], &[]);
+
+        // 订阅结束后，连接可以继续执行普通命令
+        client.send(CommandRequest::new_hget("t1", "k1")).await?;
+        assert_res_error(client.next().await.unwrap()?, 404, "Not found");
+        Ok(())
+    }
+
+    async fn start_server<Store>(service: Service<Store>) -> anyhow::Result<SocketAddr>
+    where
+        Store: Storage,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let stream = ProstServerStream::new(stream, service.clone());
+                tokio::spawn(stream.process());
+            }
+        });
+        Ok(addr)
+    }
+}
@@ -0,0 +1,181 @@
+use super::ProstCodec;
+use crate::{CommandRequest, CommandResponse, KvError};
+use futures::{SinkExt, StreamExt};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc;
+use tokio_util::codec::Framed;
+use tracing::warn;
+
+type Pending = mpsc::UnboundedSender<CommandResponse>;
+
+// 一个连接上可以同时有多个请求在执行，用请求的 id 把响应交给对应的调用者
+// 订阅这样的流式命令要用 ProstClientStream::execute_streaming
+#[derive(Clone)]
+pub struct PipelineClient {
+    sender: mpsc::Sender<(CommandRequest, Pending)>,
+}
+
+impl PipelineClient {
+    pub fn new<S>(stream: S) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel(64);
+        tokio::spawn(async move {
+            if let Err(e) = run(Framed::new(stream, ProstCodec::new()), receiver).await {
+                warn!("Pipeline connection is closed: {:?}", e);
+            }
+        });
+        Self { sender }
+    }
+
+    // 和 ProstClientStream::execute 一样，分成多个响应返回的结果会合并成一个
+    pub async fn execute(&self, cmd: CommandRequest) -> Result<CommandResponse, KvError> {
+        let closed = || KvError::Internal("Connection is closed".into());
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        self.sender.send((cmd, tx)).await.map_err(|_| closed())?;
+        let mut res = rx.recv().await.ok_or_else(closed)?;
+        while res.more {
+            let next = rx.recv().await.ok_or_else(closed)?;
+            if next.status != res.status {
+                return Ok(next);
+            }
+            res.values.extend(next.values);
+            res.pairs.extend(next.pairs);
+            res.more = next.more;
+        }
+        Ok(res)
+    }
+}
+
+// 后台任务：给请求分配 id 后发出去，收到响应后按 id 交给等待的调用者
+// 读和写同时进行，写被阻塞的时候也能继续读响应，不会和服务端互相等待
+async fn run<S>(
+    framed: Framed<S, ProstCodec<CommandResponse, CommandRequest>>,
+    mut receiver: mpsc::Receiver<(CommandRequest, Pending)>,
+) -> Result<(), KvError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    let (mut sink, mut stream) = framed.split();
+    let pending: Mutex<HashMap<u64, Pending>> = Mutex::new(HashMap::new());
+
+    let writer = async {
+        let mut next_id = 0;
+        // 所有的 PipelineClient 都已经 drop 了，不会再有人等响应
+        while let Some((cmd, tx)) = receiver.recv().await {
+            next_id += 1;
+            pending.lock().insert(next_id, tx);
+            sink.send(cmd.with_id(next_id)).await?;
+        }
+        Ok::<_, KvError>(())
+    };
+
+    let reader = async {
+        while let Some(res) = stream.next().await {
+            let res = res?;
+            let id = res.id;
+            let done = !res.more;
+            let mut pending = pending.lock();
+            match pending.get(&id) {
+                Some(tx) => {
+                    // 调用者已经不等这个响应了
+                    let _ = tx.send(res);
+                }
+                None => warn!("Got a response for unknown request {}", id),
+            }
+            if done {
+                pending.remove(&id);
+            }
+        }
+        Ok(())
+    };
+
+    tokio::select! {
+        res = writer => res,
+        res = reader => res,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Kvpair, MemTable, ProstServerStream, Service, ServiceInner, Value, assert_res_ok};
+    use futures::future::join_all;
+
+    #[tokio::test]
+    async fn pipeline_client_should_have_many_requests_in_flight() -> anyhow::Result<()> {
+        let (client, server) = tokio::io::duplex(4096);
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        tokio::spawn(ProstServerStream::new(server, service).process());
+        let client = PipelineClient::new(client);
+
+        let pairs: Vec<_> = (0..1000)
+            .map(|i| Kvpair::new(format!("k{:04}", i), Value::from(i as i64)))
+            .collect();
+        client
+            .execute(CommandRequest::new_hmset("big", pairs))
+            .await?;
+
+        // 分成多个响应的 Hgetall 和其它请求交错返回，也能正确地合并
+        let hgetall = client.execute(CommandRequest::new_hgetall("big"));
+        let requests = (0..100).map(|i| {
+            let client = client.clone();
+            async move {
+                let key = format!("k{}", i);
+                client
+                    .execute(CommandRequest::new_hset("t1", &key, i.into()))
+                    .await?;
+                client.execute(CommandRequest::new_hget("t1", &key)).await
+            }
+        });
+        let (all, results) = tokio::join!(hgetall, join_all(requests));
+        assert_eq!(all?.pairs.len(), 1000);
+        for (i, res) in results.into_iter().enumerate() {
+            assert_res_ok(res?, &[(i as i64).into()], &[]);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pipeline_client_should_not_deadlock_with_full_buffers() -> anyhow::Result<()> {
+        // 请求和响应都比连接的缓冲区大很多，两边要一边写一边读
+        let (client, server) = tokio::io::duplex(1024);
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        tokio::spawn(ProstServerStream::new(server, service).process());
+        let client = PipelineClient::new(client);
+
+        let value = Value::from("v".repeat(64 * 1024));
+        let pairs: Vec<_> = (0..50)
+            .map(|i| Kvpair::new(format!("k{}", i), value.clone()))
+            .collect();
+        client
+            .execute(CommandRequest::new_hmset("t1", pairs))
+            .await?;
+        // hset 返回旧的值，请求和响应都很大
+        let requests = (0..50).map(|i| {
+            let (client, value) = (client.clone(), value.clone());
+            async move {
+                let cmd = CommandRequest::new_hset("t1", format!("k{}", i), value);
+                client.execute(cmd).await
+            }
+        });
+        let timeout = std::time::Duration::from_secs(10);
+        let results = tokio::time::timeout(timeout, join_all(requests)).await?;
+        for res in results {
+            assert_res_ok(res?, std::slice::from_ref(&value), &[]);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pipeline_client_should_fail_when_connection_is_closed() {
+        let (client, server) = tokio::io::duplex(4096);
+        drop(server);
+        let client = PipelineClient::new(client);
+        let res = client.execute(CommandRequest::new_hget("t1", "k1")).await;
+        assert!(res.is_err());
+    }
+}
@@ -0,0 +1,705 @@
+use super::collect_response;
+use super::frame::{MAX_FRAME, MAX_RESERVE};
+use crate::metrics::ConnectionGuard;
+use crate::{
+    CommandRequest, CommandResponse, KvError, Kvpair, MemTable, Service, Session, Storage, Value,
+    value,
+};
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::{Decoder, Encoder, Framed};
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+// 和 redis 一样，一行最长 64K
+const MAX_INLINE_LEN: usize = 64 * 1024;
+const MAX_ARGS: i64 = 1024 * 1024;
+
+// RESP 的回复，RESP2 里没有 Null 和 Map，会转换成 null bulk string 和数组
+#[derive(Debug, Clone, PartialEq)]
+pub enum RespValue {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Bytes),
+    Null,
+    Array(Vec<RespValue>),
+    Map(Vec<(RespValue, RespValue)>),
+}
+
+impl RespValue {
+    fn ok() -> Self {
+        Self::Simple("OK".into())
+    }
+
+    fn encode(&self, buf: &mut BytesMut, resp3: bool) {
+        match self {
+            Self::Simple(s) => put_line(buf, b'+', s),
+            Self::Error(s) => put_line(buf, b'-', s),
+            Self::Integer(n) => put_line(buf, b':', &n.to_string()),
+            Self::Bulk(b) => {
+                put_line(buf, b'$', &b.len().to_string());
+                buf.put_slice(b);
+                buf.put_slice(b"\r\n");
+            }
+            Self::Null if resp3 => buf.put_slice(b"_\r\n"),
+            Self::Null => buf.put_slice(b"$-1\r\n"),
+            Self::Array(items) => {
+                put_line(buf, b'*', &items.len().to_string());
+                for item in items {
+                    item.encode(buf, resp3);
+                }
+            }
+            Self::Map(pairs) => {
+                match resp3 {
+                    true => put_line(buf, b'%', &pairs.len().to_string()),
+                    false => put_line(buf, b'*', &(pairs.len() * 2).to_string()),
+                }
+                for (k, v) in pairs {
+                    k.encode(buf, resp3);
+                    v.encode(buf, resp3);
+                }
+            }
+        }
+    }
+}
+
+impl From<&str> for RespValue {
+    fn from(s: &str) -> Self {
+        Self::Bulk(Bytes::copy_from_slice(s.as_bytes()))
+    }
+}
+
+impl From<&Value> for RespValue {
+    fn from(v: &Value) -> Self {
+        match &v.value {
+            None => Self::Null,
+            Some(value::Value::String(s)) => s.as_str().into(),
+            Some(value::Value::Binary(b)) => Self::Bulk(b.clone()),
+            Some(value::Value::Integer(i)) => i.to_string().as_str().into(),
+            Some(value::Value::Float(f)) => f.to_string().as_str().into(),
+            Some(value::Value::Bool(b)) => b.to_string().as_str().into(),
+            Some(value::Value::Null(_)) => Self::Null,
+            Some(value::Value::List(l)) => Self::Array(l.values.iter().map(Into::into).collect()),
+            Some(value::Value::Map(m)) => Self::Map(
+                m.values
+                    .iter()
+                    .map(|(k, v)| (k.as_str().into(), v.into()))
+                    .collect(),
+            ),
+            // 秒数，小数部分是纳秒
+            // nanos 总是正的，1970 年之前的时间要借一秒，比如 -2 秒加 0.5 秒是 -1.5
+            Some(value::Value::Timestamp(t)) if t.seconds < 0 && t.nanos > 0 => {
+                format!("-{}.{:09}", -(t.seconds + 1), 1_000_000_000 - t.nanos)
+                    .as_str()
+                    .into()
+            }
+            Some(value::Value::Timestamp(t)) => {
+                format!("{}.{:09}", t.seconds, t.nanos).as_str().into()
+            }
+        }
+    }
+}
+
+fn put_line(buf: &mut BytesMut, prefix: u8, s: &str) {
+    buf.put_u8(prefix);
+    buf.put_slice(s.as_bytes());
+    buf.put_slice(b"\r\n");
+}
+
+// 解码客户端发来的命令（bulk string 的数组，或者 telnet 这样的 inline 命令），编码 RespValue
+// 连接执行 HELLO 3 之后用 RESP3 编码
+#[derive(Debug)]
+pub struct RespCodec {
+    pub resp3: bool,
+    max_frame: usize,
+}
+
+impl RespCodec {
+    pub fn new() -> Self {
+        Self::with_max_frame(MAX_FRAME)
+    }
+
+    // 一个命令所有的参数加起来不能超过 max_frame
+    pub fn with_max_frame(max_frame: usize) -> Self {
+        Self {
+            resp3: false,
+            max_frame,
+        }
+    }
+
+    pub fn max_frame(&self) -> usize {
+        self.max_frame
+    }
+}
+
+impl Default for RespCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for RespCodec {
+    type Item = Vec<Bytes>;
+    type Error = KvError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if src.is_empty() {
+            return Ok(None);
+        }
+        if src[0] != b'*' {
+            return decode_inline(src);
+        }
+
+        let Some((n, mut pos)) = read_int(src, 1)? else {
+            return Ok(None);
+        };
+        if n > MAX_ARGS {
+            return Err(protocol_error("invalid multibulk length"));
+        }
+        let mut ranges = Vec::new();
+        for _ in 0..n.max(0) {
+            if pos >= src.len() {
+                return Ok(None);
+            }
+            if src[pos] != b'$' {
+                return Err(protocol_error("expected '$'"));
+            }
+            let Some((len, start)) = read_int(src, pos + 1)? else {
+                return Ok(None);
+            };
+            let Ok(len) = usize::try_from(len) else {
+                return Err(protocol_error("invalid bulk length"));
+            };
+            let end = start.saturating_add(len);
+            if end > self.max_frame {
+                return Err(protocol_error("too big request"));
+            }
+            if src.len() < end + 2 {
+                // 提前分配一部分空间，避免大的 bulk string 反复扩容
+                src.reserve((end + 2 - src.len()).min(MAX_RESERVE));
+                return Ok(None);
+            }
+            if &src[end..end + 2] != b"\r\n" {
+                return Err(protocol_error("expected CRLF"));
+            }
+            ranges.push(start..end);
+            pos = end + 2;
+        }
+        let frame = src.split_to(pos).freeze();
+        Ok(Some(ranges.into_iter().map(|r| frame.slice(r)).collect()))
+    }
+}
+
+impl Encoder<RespValue> for RespCodec {
+    type Error = KvError;
+
+    fn encode(&mut self, item: RespValue, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        item.encode(dst, self.resp3);
+        Ok(())
+    }
+}
+
+// 以空白分隔参数的一行命令，比如 "PING\r\n"
+fn decode_inline(src: &mut BytesMut) -> Result<Option<Vec<Bytes>>, KvError> {
+    let Some(end) = find_crlf(src, 0) else {
+        if src.len() > MAX_INLINE_LEN {
+            return Err(protocol_error("too big inline request"));
+        }
+        return Ok(None);
+    };
+    let line = src.split_to(end + 2).freeze();
+    let args = line[..end]
+        .split(|c| c.is_ascii_whitespace())
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| line.slice_ref(arg))
+        .collect();
+    Ok(Some(args))
+}
+
+// 读取从 start 开始到 CRLF 的整数，返回这个整数和下一行开始的位置
+fn read_int(src: &[u8], start: usize) -> Result<Option<(i64, usize)>, KvError> {
+    let Some(end) = find_crlf(src, start) else {
+        if src.len() - start > MAX_INLINE_LEN {
+            return Err(protocol_error("too big length"));
+        }
+        return Ok(None);
+    };
+    let n = std::str::from_utf8(&src[start..end])
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| protocol_error("invalid length"))?;
+    Ok(Some((n, end + 2)))
+}
+
+fn find_crlf(src: &[u8], start: usize) -> Option<usize> {
+    src[start..]
+        .windows(2)
+        .position(|w| w == b"\r\n")
+        .map(|i| start + i)
+}
+
+fn protocol_error(msg: &str) -> KvError {
+    KvError::FrameError(format!("Protocol error: {}", msg))
+}
+
+// 执行完之后 CommandResponse 怎么转换成 RESP 的回复
+#[derive(Debug)]
+enum Reply {
+    // HGET：value，不存在时是 null
+    Value,
+    // HMGET：按请求的顺序返回 value，不存在的是 null
+    Values(Vec<String>),
+    // HGETALL
+    Map,
+    // HEXISTS：1 或者 0
+    Exists,
+    // HSET：新增了几个 field，也就是之前的值为空的个数
+    Added,
+    // HDEL：删除了几个 field，也就是之前的值不为空的个数
+    Deleted,
+    // HMSET
+    Ok,
+    // AUTH
+    Auth,
+}
+
+enum Request {
+    Kv(CommandRequest, Reply),
+    Reply(RespValue),
+}
+
+// redis 里 hash 的 key 对应 kv1 的 table，field 对应 table 里的 key
+fn parse(name: &str, args: &[Bytes]) -> Result<Request, RespValue> {
+    let s = text;
+    let pairs = |args: &[Bytes]| -> Result<Vec<Kvpair>, RespValue> {
+        args.chunks(2)
+            .map(|kv| Ok(Kvpair::new(s(&kv[0])?, to_value(&kv[1]))))
+            .collect()
+    };
+    let kv = |cmd, reply| Ok(Request::Kv(cmd, reply));
+
+    match (name, args) {
+        ("PING", []) => Ok(Request::Reply(RespValue::Simple("PONG".into()))),
+        ("PING" | "ECHO", [msg]) => Ok(Request::Reply(RespValue::Bulk(msg.clone()))),
+        ("SELECT", [db]) if db.as_ref() == b"0" => Ok(Request::Reply(RespValue::ok())),
+        ("SELECT", [_]) => Err(RespValue::Error("ERR DB index is out of range".into())),
+        // CLIENT SETNAME、CLIENT SETINFO 之类的命令，客户端库连接时会发送，直接返回 OK
+        ("CLIENT", [_, ..]) => Ok(Request::Reply(RespValue::ok())),
+        // redis-cli 启动时会用 COMMAND DOCS 获取命令的帮助
+        ("COMMAND", _) => Ok(Request::Reply(RespValue::Array(vec![]))),
+        ("AUTH", [token]) => kv(CommandRequest::new_auth_token(s(token)?), Reply::Auth),
+        ("AUTH", [user, pass]) => kv(CommandRequest::new_auth(s(user)?, s(pass)?), Reply::Auth),
+        ("HGET", [key, field]) => kv(CommandRequest::new_hget(s(key)?, s(field)?), Reply::Value),
+        ("HMGET", [key, fields @ ..]) if !fields.is_empty() => {
+            let fields = fields.iter().map(s).collect::<Result<Vec<_>, _>>()?;
+            kv(
+                CommandRequest::new_hmget(s(key)?, fields.clone()),
+                Reply::Values(fields),
+            )
+        }
+        ("HGETALL", [key]) => kv(CommandRequest::new_hgetall(s(key)?), Reply::Map),
+        ("HEXISTS", [key, field]) => kv(
+            CommandRequest::new_hmexist(s(key)?, vec![s(field)?]),
+            Reply::Exists,
+        ),
+        // 在事务里逐个设置，这样才知道哪些 field 是新增的
+        ("HSET", [key, rest @ ..]) if !rest.is_empty() && rest.len() % 2 == 0 => {
+            let key = s(key)?;
+            let commands = pairs(rest)?
+                .into_iter()
+                .map(|p| CommandRequest::new_hset(&key, p.key, p.value.unwrap_or_default()))
+                .collect();
+            kv(CommandRequest::new_transaction(commands), Reply::Added)
+        }
+        ("HMSET", [key, rest @ ..]) if !rest.is_empty() && rest.len() % 2 == 0 => {
+            kv(CommandRequest::new_hmset(s(key)?, pairs(rest)?), Reply::Ok)
+        }
+        ("HDEL", [key, fields @ ..]) if !fields.is_empty() => {
+            let key = s(key)?;
+            let commands = fields
+                .iter()
+                .map(|f| Ok(CommandRequest::new_hdel(&key, s(f)?)))
+                .collect::<Result<_, RespValue>>()?;
+            kv(CommandRequest::new_transaction(commands), Reply::Deleted)
+        }
+        (
+            "PING" | "ECHO" | "SELECT" | "CLIENT" | "AUTH" | "HGET" | "HMGET" | "HGETALL"
+            | "HEXISTS" | "HSET" | "HMSET" | "HDEL",
+            _,
+        ) => Err(RespValue::Error(format!(
+            "ERR wrong number of arguments for '{}' command",
+            name.to_lowercase()
+        ))),
+        _ => Err(RespValue::Error(format!(
+            "ERR unknown command '{}'",
+            name.to_lowercase()
+        ))),
+    }
+}
+
+// key、field、用户名和密码都是字符串，不是合法的 UTF-8 时拒绝执行
+// 用 lossy 转换的话，不同的 field 可能变成同一个
+fn text(b: &Bytes) -> Result<String, RespValue> {
+    String::from_utf8(b.to_vec())
+        .map_err(|_| RespValue::Error("ERR invalid UTF-8 in argument".into()))
+}
+
+// 合法的 UTF-8 存成字符串，否则存成二进制
+fn to_value(b: &Bytes) -> Value {
+    match std::str::from_utf8(b) {
+        Ok(s) => s.into(),
+        Err(_) => Value {
+            value: Some(value::Value::Binary(b.clone())),
+        },
+    }
+}
+
+fn render(reply: Reply, res: CommandResponse) -> RespValue {
+    match (res.status, &reply) {
+        (200, _) => {}
+        (404, Reply::Value) => return RespValue::Null,
+        (401, Reply::Auth) => {
+            return RespValue::Error(
+                "WRONGPASS invalid username-password pair or user is disabled.".into(),
+            );
+        }
+        (401, _) => return RespValue::Error("NOAUTH Authentication required.".into()),
+        (403, _) => return RespValue::Error(format!("NOPERM {}", res.message)),
+        _ => return RespValue::Error(format!("ERR {}", res.message)),
+    }
+
+    let old_values = || res.responses.iter().filter_map(|r| r.values.first());
+    match reply {
+        Reply::Value => res
+            .values
+            .first()
+            .map(Into::into)
+            .unwrap_or(RespValue::Null),
+        Reply::Values(fields) => RespValue::Array(
+            fields
+                .iter()
+                .map(|f| {
+                    res.pairs
+                        .iter()
+                        .find(|p| &p.key == f)
+                        .and_then(|p| p.value.as_ref())
+                        .map(Into::into)
+                        .unwrap_or(RespValue::Null)
+                })
+                .collect(),
+        ),
+        Reply::Map => RespValue::Map(
+            res.pairs
+                .iter()
+                .map(|p| {
+                    let v = p.value.as_ref().map(Into::into).unwrap_or(RespValue::Null);
+                    (p.key.as_str().into(), v)
+                })
+                .collect(),
+        ),
+        Reply::Exists => {
+            let exists = res.pairs.first().and_then(|p| p.value.clone());
+            RespValue::Integer((exists == Some(true.into())) as i64)
+        }
+        Reply::Added => RespValue::Integer(old_values().filter(|v| v.value.is_none()).count() as _),
+        Reply::Deleted => {
+            RespValue::Integer(old_values().filter(|v| v.value.is_some()).count() as _)
+        }
+        Reply::Ok | Reply::Auth => RespValue::ok(),
+    }
+}
+
+// 给 redis 客户端用的服务端，和 ProstServerStream 共用同一个 Service
+pub struct RespServerStream<S, Store = MemTable> {
+    inner: Framed<S, RespCodec>,
+    service: Service<Store>,
+    shutdown: CancellationToken,
+}
+
+impl<S, Store> RespServerStream<S, Store>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+    Store: Storage,
+{
+    pub fn new(stream: S, service: Service<Store>) -> Self {
+        Self {
+            inner: Framed::new(stream, RespCodec::default()),
+            service,
+            shutdown: CancellationToken::new(),
+        }
+    }
+
+    // 一个命令的参数加起来不能超过 max_frame，默认和 kv1 的 frame 一样
+    pub fn with_max_frame(mut self, max_frame: usize) -> Self {
+        self.inner.codec_mut().max_frame = max_frame;
+        self
+    }
+
+    // token 取消之后不再读新的命令，正在执行的命令回复之后关闭连接
+    pub fn with_shutdown(mut self, shutdown: CancellationToken) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    pub async fn process(mut self) -> Result<(), KvError> {
+        let _connection = ConnectionGuard::new("resp");
+        let mut session = self.service.session();
+        loop {
+            let args = tokio::select! {
+                args = self.inner.next() => args,
+                _ = self.shutdown.cancelled() => break,
+            };
+            let Some(args) = args else { break };
+            let args = args?;
+            let Some(name) = args.first() else {
+                continue;
+            };
+            let name = String::from_utf8_lossy(name).to_ascii_uppercase();
+            info!("Got a new RESP command: {}", name);
+
+            let reply = match name.as_str() {
+                "QUIT" => {
+                    self.inner.send(RespValue::ok()).await?;
+                    break;
+                }
+                "HELLO" => match hello(&mut session, &args[1..]).await {
+                    Ok((resp3, reply)) => {
+                        if let Some(resp3) = resp3 {
+                            self.inner.codec_mut().resp3 = resp3;
+                        }
+                        reply
+                    }
+                    Err(e) => e,
+                },
+                _ => match parse(&name, &args[1..]) {
+                    Ok(Request::Reply(reply)) => reply,
+                    Ok(Request::Kv(cmd, reply)) => {
+                        render(reply, collect_response(session.execute(cmd)).await)
+                    }
+                    Err(e) => e,
+                },
+            };
+            self.inner.send(reply).await?;
+            self.service.notify_sent();
+        }
+        Ok(())
+    }
+}
+
+// HELLO [protover [AUTH username password] [SETNAME name]]
+// 返回连接要切换到的协议版本（Some(true) 是 RESP3）和回复
+async fn hello<Store: Storage>(
+    session: &mut Session<Store>,
+    args: &[Bytes],
+) -> Result<(Option<bool>, RespValue), RespValue> {
+    let (version, mut rest) = match args {
+        [] => (None, args),
+        [v, rest @ ..] => match v.as_ref() {
+            b"2" => (Some(2), rest),
+            b"3" => (Some(3), rest),
+            _ => {
+                return Err(RespValue::Error(
+                    "NOPROTO unsupported protocol version".into(),
+                ));
+            }
+        },
+    };
+    while let [option, tail @ ..] = rest {
+        match (option.to_ascii_uppercase().as_slice(), tail) {
+            (b"AUTH", [user, pass, tail @ ..]) => {
+                let cmd = CommandRequest::new_auth(text(user)?, text(pass)?);
+                if let err @ RespValue::Error(_) =
+                    render(Reply::Auth, collect_response(session.execute(cmd)).await)
+                {
+                    return Err(err);
+                }
+                rest = tail;
+            }
+            (b"SETNAME", [_, tail @ ..]) => rest = tail,
+            _ => return Err(RespValue::Error("ERR syntax error in HELLO option".into())),
+        }
+    }
+
+    let proto = version.unwrap_or(2);
+    let reply = RespValue::Map(vec![
+        ("server".into(), "kv1".into()),
+        ("version".into(), env!("CARGO_PKG_VERSION").into()),
+        ("proto".into(), RespValue::Integer(proto)),
+        ("id".into(), RespValue::Integer(0)),
+        ("mode".into(), "standalone".into()),
+        ("role".into(), "master".into()),
+        ("modules".into(), RespValue::Array(vec![])),
+    ]);
+    Ok((version.map(|v| v == 3), reply))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Acl, Permission, ServiceInner, User};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+    #[test]
+    fn resp_codec_should_decode_commands() {
+        let mut codec = RespCodec::default();
+        let mut buf = BytesMut::from(&b"*3\r\n$4\r\nHGET\r\n$2\r\nt1\r\n$2\r\nk"[..]);
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        buf.extend_from_slice(b"1\r\nPING  hello\r\n");
+        let args = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(args, vec!["HGET", "t1", "k1"]);
+        let args = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(args, vec!["PING", "hello"]);
+        assert!(buf.is_empty());
+
+        let mut buf = BytesMut::from(&b"*1\r\n+OK\r\n"[..]);
+        assert!(codec.decode(&mut buf).is_err());
+        let mut buf = BytesMut::from(&b"*1\r\n$3\r\nabcd\r\n"[..]);
+        assert!(codec.decode(&mut buf).is_err());
+    }
+
+    #[test]
+    fn resp_codec_should_limit_frame_size() {
+        let mut codec = RespCodec::with_max_frame(1024);
+        // 声明的长度很大时，不会按声明的长度分配内存
+        let mut buf = BytesMut::from(&b"*1\r\n$1000\r\n"[..]);
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        let mut buf = BytesMut::from(&b"*1\r\n$1000000\r\n"[..]);
+        assert!(codec.decode(&mut buf).is_err());
+
+        let mut codec = RespCodec::new();
+        let mut buf = BytesMut::from(&b"*1\r\n$60000000\r\n"[..]);
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        assert!(buf.capacity() <= 2 * MAX_RESERVE);
+    }
+
+    #[test]
+    fn resp_parse_should_reject_invalid_utf8() {
+        let args = [Bytes::from_static(b"t1"), Bytes::from_static(b"\xff")];
+        assert!(matches!(parse("HGET", &args), Err(RespValue::Error(_))));
+        // value 可以是二进制
+        let args = [
+            Bytes::from_static(b"t1"),
+            Bytes::from_static(b"k1"),
+            Bytes::from_static(b"\xff"),
+        ];
+        assert!(parse("HSET", &args).is_ok());
+    }
+
+    #[test]
+    fn resp_value_should_encode_for_both_versions() {
+        let v = RespValue::Array(vec![
+            RespValue::Null,
+            RespValue::Integer(1),
+            RespValue::Map(vec![("k".into(), "v".into())]),
+        ]);
+        let mut buf = BytesMut::new();
+        v.encode(&mut buf, false);
+        assert_eq!(&buf[..], b"*3\r\n$-1\r\n:1\r\n*2\r\n$1\r\nk\r\n$1\r\nv\r\n");
+        let mut buf = BytesMut::new();
+        v.encode(&mut buf, true);
+        assert_eq!(&buf[..], b"*3\r\n_\r\n:1\r\n%1\r\n$1\r\nk\r\n$1\r\nv\r\n");
+    }
+
+    #[test]
+    fn resp_value_should_render_timestamps() {
+        use std::time::{Duration, UNIX_EPOCH};
+        let cases = [
+            (UNIX_EPOCH + Duration::from_millis(1500), "1.500000000"),
+            (UNIX_EPOCH - Duration::from_millis(1500), "-1.500000000"),
+            (UNIX_EPOCH - Duration::from_millis(500), "-0.500000000"),
+            (UNIX_EPOCH - Duration::from_secs(2), "-2.000000000"),
+        ];
+        for (t, expected) in cases {
+            assert_eq!(RespValue::from(&Value::from(t)), expected.into());
+        }
+    }
+
+    #[tokio::test]
+    async fn resp_hash_commands_should_work() {
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        let mut client = start(service);
+
+        assert_eq!(call(&mut client, &["PING"]).await, "+PONG\r\n");
+        let res = call(&mut client, &["HSET", "h", "f1", "v1", "f2", "v2"]).await;
+        assert_eq!(res, ":2\r\n");
+        let res = call(&mut client, &["HSET", "h", "f1", "v0", "f3", "10"]).await;
+        assert_eq!(res, ":1\r\n");
+        assert_eq!(
+            call(&mut client, &["HGET", "h", "f1"]).await,
+            "$2\r\nv0\r\n"
+        );
+        assert_eq!(call(&mut client, &["HGET", "h", "nope"]).await, "$-1\r\n");
+        let res = call(&mut client, &["HMGET", "h", "f2", "nope", "f3"]).await;
+        assert_eq!(res, "*3\r\n$2\r\nv2\r\n$-1\r\n$2\r\n10\r\n");
+        let res = call(&mut client, &["HMSET", "h", "f4", "v4"]).await;
+        assert_eq!(res, "+OK\r\n");
+        assert_eq!(call(&mut client, &["HEXISTS", "h", "f4"]).await, ":1\r\n");
+        assert_eq!(call(&mut client, &["HEXISTS", "h", "nope"]).await, ":0\r\n");
+        let res = call(&mut client, &["HDEL", "h", "f1", "f3", "f4", "nope"]).await;
+        assert_eq!(res, ":3\r\n");
+
+        let res = call(&mut client, &["HGETALL", "h"]).await;
+        assert_eq!(res, "*2\r\n$2\r\nf2\r\n$2\r\nv2\r\n");
+        // 切换到 RESP3 之后 HGETALL 返回 map，不存在的值是 null
+        let res = call(&mut client, &["HELLO", "3"]).await;
+        assert!(res.starts_with("%7\r\n$6\r\nserver\r\n$3\r\nkv1\r\n"));
+        let res = call(&mut client, &["HGETALL", "h"]).await;
+        assert_eq!(res, "%1\r\n$2\r\nf2\r\n$2\r\nv2\r\n");
+        assert_eq!(call(&mut client, &["HGET", "h", "nope"]).await, "_\r\n");
+
+        let res = call(&mut client, &["HGET", "h"]).await;
+        assert_eq!(res, "-ERR wrong number of arguments for 'hget' command\r\n");
+        let res = call(&mut client, &["FLUSHALL"]).await;
+        assert_eq!(res, "-ERR unknown command 'flushall'\r\n");
+    }
+
+    #[tokio::test]
+    async fn resp_auth_should_use_acl() {
+        let acl = Acl::new().user(
+            User::new("alice")
+                .password("secret")
+                .grant("h", Permission::Read),
+        );
+        let service: Service = ServiceInner::new(MemTable::new()).acl(acl).into();
+        let mut client = start(service.clone());
+
+        let res = call(&mut client, &["HGET", "h", "f1"]).await;
+        assert_eq!(res, "-NOAUTH Authentication required.\r\n");
+        let res = call(&mut client, &["AUTH", "alice", "wrong"]).await;
+        assert!(res.starts_with("-WRONGPASS"));
+        assert_eq!(
+            call(&mut client, &["AUTH", "alice", "secret"]).await,
+            "+OK\r\n"
+        );
+        assert_eq!(call(&mut client, &["HGET", "h", "f1"]).await, "$-1\r\n");
+        let res = call(&mut client, &["HSET", "h", "f1", "v1"]).await;
+        assert!(res.starts_with("-NOPERM"));
+
+        // HELLO 也可以带上认证信息
+        let mut client = start(service);
+        let res = call(&mut client, &["HELLO", "3", "AUTH", "alice", "wrong"]).await;
+        assert!(res.starts_with("-WRONGPASS"));
+        let res = call(&mut client, &["HELLO", "3", "AUTH", "alice", "secret"]).await;
+        assert!(res.starts_with("%7\r\n"));
+        assert_eq!(call(&mut client, &["HGET", "h", "f1"]).await, "_\r\n");
+    }
+
+    fn start(service: Service) -> DuplexStream {
+        let (client, server) = tokio::io::duplex(4096);
+        tokio::spawn(RespServerStream::new(server, service).process());
+        client
+    }
+
+    // 发送命令，读取一个回复；测试里的回复都不会超过一次读取的大小
+    async fn call(client: &mut DuplexStream, args: &[&str]) -> String {
+        let mut buf = BytesMut::new();
+        let args = args.iter().map(|&arg| arg.into()).collect();
+        RespValue::Array(args).encode(&mut buf, false);
+        client.write_all(&buf).await.unwrap();
+        let mut res = vec![0; 4096];
+        let n = client.read(&mut res).await.unwrap();
+        String::from_utf8_lossy(&res[..n]).into_owned()
+    }
+}
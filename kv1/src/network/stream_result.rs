@@ -0,0 +1,45 @@
+use crate::{CommandResponse, KvError};
+use futures::{Stream, StreamExt};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+// 客户端的流式结果，第一个响应里是订阅 id，之后是不断推送过来的数据
+pub struct StreamResult {
+    pub id: u32,
+    inner: Pin<Box<dyn Stream<Item = Result<CommandResponse, KvError>> + Send>>,
+}
+
+impl StreamResult {
+    pub async fn new<T>(mut stream: T) -> Result<Self, KvError>
+    where
+        T: Stream<Item = Result<CommandResponse, KvError>> + Send + Unpin + 'static,
+    {
+        let id = match stream.next().await {
+            Some(Ok(res)) if res.status == 200 => match res.values.first() {
+                Some(v) => i64::try_from(v.clone())? as u32,
+                None => return Err(KvError::Internal("Invalid stream".into())),
+            },
+            Some(Ok(res)) => {
+                return Err(KvError::Internal(format!(
+                    "{}: {}",
+                    res.status, res.message
+                )));
+            }
+            Some(Err(e)) => return Err(e),
+            None => return Err(KvError::Internal("Didn't get any response".into())),
+        };
+
+        Ok(Self {
+            id,
+            inner: Box::pin(stream),
+        })
+    }
+}
+
+impl Stream for StreamResult {
+    type Item = Result<CommandResponse, KvError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.as_mut().poll_next(cx)
+    }
+}
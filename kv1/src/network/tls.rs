@@ -0,0 +1,301 @@
+use crate::KvError;
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::rustls::crypto::{CryptoProvider, ring};
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tokio_rustls::{TlsAcceptor, TlsConnector, client, server};
+
+// 服务端 TLS，client_ca 不为空时要求客户端也出示证书 (mTLS)
+#[derive(Clone)]
+pub struct TlsServerAcceptor {
+    inner: Arc<ServerConfig>,
+}
+
+// 客户端 TLS，domain 要和服务端证书里的域名一致
+#[derive(Clone)]
+pub struct TlsClientConnector {
+    config: Arc<ClientConfig>,
+    domain: Arc<String>,
+}
+
+impl TlsServerAcceptor {
+    // cert / key / client_ca 都是 PEM 格式的内容
+    pub fn new(cert: &str, key: &str, client_ca: Option<&str>) -> Result<Self, KvError> {
+        let certs = load_certs(cert)?;
+        let key = load_key(key)?;
+        let provider = crypto_provider();
+
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(tls_error)?;
+        let builder = match client_ca {
+            Some(ca) => {
+                let roots = load_roots(ca)?;
+                let verifier =
+                    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                        .build()
+                        .map_err(tls_error)?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let config = builder.with_single_cert(certs, key).map_err(tls_error)?;
+
+        Ok(Self {
+            inner: Arc::new(config),
+        })
+    }
+
+    pub fn from_files(
+        cert: impl AsRef<Path>,
+        key: impl AsRef<Path>,
+        client_ca: Option<impl AsRef<Path>>,
+    ) -> Result<Self, KvError> {
+        let client_ca = client_ca.map(fs::read_to_string).transpose()?;
+        Self::new(
+            &fs::read_to_string(cert)?,
+            &fs::read_to_string(key)?,
+            client_ca.as_deref(),
+        )
+    }
+
+    pub async fn accept<S>(&self, stream: S) -> Result<server::TlsStream<S>, KvError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        let acceptor = TlsAcceptor::from(self.inner.clone());
+        Ok(acceptor.accept(stream).await?)
+    }
+}
+
+impl TlsClientConnector {
+    // identity 是客户端自己的 (cert, key)，服务端开启 mTLS 时需要
+    pub fn new(
+        domain: impl Into<String>,
+        identity: Option<(&str, &str)>,
+        server_ca: &str,
+    ) -> Result<Self, KvError> {
+        let roots = load_roots(server_ca)?;
+        let builder = ClientConfig::builder_with_provider(crypto_provider())
+            .with_safe_default_protocol_versions()
+            .map_err(tls_error)?
+            .with_root_certificates(roots);
+        let config = match identity {
+            Some((cert, key)) => builder
+                .with_client_auth_cert(load_certs(cert)?, load_key(key)?)
+                .map_err(tls_error)?,
+            None => builder.with_no_client_auth(),
+        };
+
+        Ok(Self {
+            config: Arc::new(config),
+            domain: Arc::new(domain.into()),
+        })
+    }
+
+    pub fn from_files(
+        domain: impl Into<String>,
+        identity: Option<(impl AsRef<Path>, impl AsRef<Path>)>,
+        server_ca: impl AsRef<Path>,
+    ) -> Result<Self, KvError> {
+        let identity = identity
+            .map(|(cert, key)| {
+                Ok::<_, KvError>((fs::read_to_string(cert)?, fs::read_to_string(key)?))
+            })
+            .transpose()?;
+        Self::new(
+            domain,
+            identity
+                .as_ref()
+                .map(|(cert, key)| (cert.as_str(), key.as_str())),
+            &fs::read_to_string(server_ca)?,
+        )
+    }
+
+    pub async fn connect<S>(&self, stream: S) -> Result<client::TlsStream<S>, KvError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        let domain = ServerName::try_from(self.domain.as_str().to_owned())
+            .map_err(|_| KvError::TlsError(format!("invalid domain: {}", self.domain)))?;
+        let connector = TlsConnector::from(self.config.clone());
+        Ok(connector.connect(domain, stream).await?)
+    }
+}
+
+fn crypto_provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+fn tls_error(e: impl ToString) -> KvError {
+    KvError::TlsError(e.to_string())
+}
+
+fn load_certs(pem: &str) -> Result<Vec<CertificateDer<'static>>, KvError> {
+    let certs = rustls_pemfile::certs(&mut Cursor::new(pem))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| KvError::CertificateParseError("certificate"))?;
+    if certs.is_empty() {
+        return Err(KvError::CertificateParseError("certificate"));
+    }
+    Ok(certs)
+}
+
+fn load_key(pem: &str) -> Result<PrivateKeyDer<'static>, KvError> {
+    match rustls_pemfile::private_key(&mut Cursor::new(pem)) {
+        Ok(Some(key)) => Ok(key),
+        _ => Err(KvError::CertificateParseError("private key")),
+    }
+}
+
+fn load_roots(pem: &str) -> Result<RootCertStore, KvError> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(pem)? {
+        roots.add(cert).map_err(tls_error)?;
+    }
+    Ok(roots)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        CommandRequest, MemTable, ProstClientStream, ProstServerStream, Service, ServiceInner,
+        Value, assert_res_ok,
+    };
+    use rcgen::{
+        BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+    };
+    use std::net::SocketAddr;
+    use tokio::net::{TcpListener, TcpStream};
+
+    const DOMAIN: &str = "kvserver.acme.inc";
+
+    // 测试时生成的 CA、服务端证书和客户端证书，都是 PEM 格式
+    struct TestCerts {
+        ca: String,
+        server: (String, String),
+        client: (String, String),
+    }
+
+    fn generate_certs() -> TestCerts {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "Acme CA");
+        let ca = params.self_signed(&ca_key).unwrap();
+
+        let issue = |name: &str, usage: ExtendedKeyUsagePurpose| {
+            let key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(vec![name.to_string()]).unwrap();
+            params.distinguished_name.push(DnType::CommonName, name);
+            params.extended_key_usages = vec![usage];
+            let cert = params.signed_by(&key, &ca, &ca_key).unwrap();
+            (cert.pem(), key.serialize_pem())
+        };
+
+        TestCerts {
+            server: issue(DOMAIN, ExtendedKeyUsagePurpose::ServerAuth),
+            client: issue("awesome-device-id", ExtendedKeyUsagePurpose::ClientAuth),
+            ca: ca.pem(),
+        }
+    }
+
+    #[tokio::test]
+    async fn tls_should_work() -> anyhow::Result<()> {
+        let certs = generate_certs();
+        let acceptor = TlsServerAcceptor::new(&certs.server.0, &certs.server.1, None)?;
+        let addr = start_server(acceptor).await?;
+
+        let connector = TlsClientConnector::new(DOMAIN, None, &certs.ca)?;
+        let stream = connector.connect(TcpStream::connect(addr).await?).await?;
+        let mut client = ProstClientStream::new(stream);
+
+        let res = client
+            .execute(CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .await?;
+        assert_res_ok(res, &[Value::default()], &[]);
+        let res = client.execute(CommandRequest::new_hget("t1", "k1")).await?;
+        assert_res_ok(res, &["v1".into()], &[]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tls_with_client_cert_should_work() -> anyhow::Result<()> {
+        let certs = generate_certs();
+        let acceptor = TlsServerAcceptor::new(&certs.server.0, &certs.server.1, Some(&certs.ca))?;
+        let addr = start_server(acceptor).await?;
+
+        let identity = Some((certs.client.0.as_str(), certs.client.1.as_str()));
+        let connector = TlsClientConnector::new(DOMAIN, identity, &certs.ca)?;
+        let stream = connector.connect(TcpStream::connect(addr).await?).await?;
+        let mut client = ProstClientStream::new(stream);
+
+        let res = client.execute(CommandRequest::new_hget("t1", "k1")).await?;
+        assert_eq!(res.status, 404);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tls_without_client_cert_should_be_rejected() -> anyhow::Result<()> {
+        let certs = generate_certs();
+        let acceptor = TlsServerAcceptor::new(&certs.server.0, &certs.server.1, Some(&certs.ca))?;
+        let addr = start_server(acceptor).await?;
+
+        let connector = TlsClientConnector::new(DOMAIN, None, &certs.ca)?;
+        // TLS 1.3 下客户端握手可能先完成，服务端拒绝后第一次请求会失败
+        let result = match connector.connect(TcpStream::connect(addr).await?).await {
+            Ok(stream) => ProstClientStream::new(stream)
+                .execute(CommandRequest::new_hget("t1", "k1"))
+                .await
+                .map(|_| ()),
+            Err(e) => Err(e),
+        };
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tls_with_wrong_domain_should_fail() -> anyhow::Result<()> {
+        let certs = generate_certs();
+        let acceptor = TlsServerAcceptor::new(&certs.server.0, &certs.server.1, None)?;
+        let addr = start_server(acceptor).await?;
+
+        let connector = TlsClientConnector::new("kvserver1.acme.inc", None, &certs.ca)?;
+        let result = connector.connect(TcpStream::connect(addr).await?).await;
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_pem_should_fail() {
+        let result = TlsServerAcceptor::new("not a cert", "not a key", None);
+        assert!(matches!(result, Err(KvError::CertificateParseError(_))));
+    }
+
+    async fn start_server(acceptor: TlsServerAcceptor) -> anyhow::Result<SocketAddr> {
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let acceptor = acceptor.clone();
+                let service = service.clone();
+                tokio::spawn(async move {
+                    if let Ok(stream) = acceptor.accept(stream).await {
+                        let _ = ProstServerStream::new(stream, service).process().await;
+                    }
+                });
+            }
+        });
+        Ok(addr)
+    }
+}
@@ -1,13 +1,25 @@
 // This file is @generated by prost-build.
 /// 来自客户端的命令请求
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
 #[derive(PartialOrd)]
+#[serde(default)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct CommandRequest {
-    #[prost(oneof = "command_request::RequestData", tags = "1, 2, 3, 4, 5, 6, 7, 8, 9")]
+    /// 请求的 id，响应里会带上同样的 id，用来在一个连接上同时发送多个请求
+    /// 为 0 时服务端按顺序逐个处理，响应的顺序和请求一致
+    #[prost(uint64, tag = "100")]
+    pub id: u64,
+    #[prost(
+        oneof = "command_request::RequestData",
+        tags = "1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25"
+    )]
     pub request_data: ::core::option::Option<command_request::RequestData>,
 }
 /// Nested message and enum types in `CommandRequest`.
 pub mod command_request {
+    #[derive(serde::Serialize, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
     #[derive(PartialOrd)]
     #[derive(Clone, PartialEq, ::prost::Oneof)]
     pub enum RequestData {
@@ -29,10 +41,45 @@ pub mod command_request {
         Hexist(super::Hexist),
         #[prost(message, tag = "9")]
         Hmexist(super::Hmexist),
+        #[prost(message, tag = "10")]
+        Subscribe(super::Subscribe),
+        #[prost(message, tag = "11")]
+        Unsubscribe(super::Unsubscribe),
+        #[prost(message, tag = "12")]
+        Publish(super::Publish),
+        #[prost(message, tag = "13")]
+        Hexpire(super::Hexpire),
+        #[prost(message, tag = "14")]
+        Httl(super::Httl),
+        #[prost(message, tag = "15")]
+        Hincrby(super::Hincrby),
+        #[prost(message, tag = "16")]
+        Hincrbyfloat(super::Hincrbyfloat),
+        #[prost(message, tag = "17")]
+        Hcas(super::Hcas),
+        #[prost(message, tag = "18")]
+        Transaction(super::Transaction),
+        #[prost(message, tag = "19")]
+        Hscan(super::Hscan),
+        #[prost(message, tag = "20")]
+        Tables(super::Tables),
+        #[prost(message, tag = "21")]
+        Hdrop(super::Hdrop),
+        #[prost(message, tag = "22")]
+        Hlen(super::Hlen),
+        #[prost(message, tag = "23")]
+        Auth(super::Auth),
+        #[prost(message, tag = "24")]
+        Replicate(super::Replicate),
+        #[prost(message, tag = "25")]
+        Negotiate(super::Negotiate),
     }
 }
 /// 服务器的响应
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
 #[derive(PartialOrd)]
+#[serde(default)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct CommandResponse {
     /// 状态码；复用 HTTP 2xx/4xx/5xx 状态码
@@ -47,9 +94,47 @@ pub struct CommandResponse {
     /// 成功返回的 kv pairs
     #[prost(message, repeated, tag = "4")]
     pub pairs: ::prost::alloc::vec::Vec<Kvpair>,
+    /// 事务里每个子命令的响应，顺序和请求一致
+    #[prost(message, repeated, tag = "5")]
+    pub responses: ::prost::alloc::vec::Vec<CommandResponse>,
+    /// 大的结果会拆成多个响应依次发送，除了最后一个，其它响应的 more 都是 true
+    #[prost(bool, tag = "6")]
+    pub more: bool,
+    /// Replicate 推送给 follower 的命令，follower 按顺序执行
+    #[prost(message, repeated, tag = "7")]
+    pub commands: ::prost::alloc::vec::Vec<CommandRequest>,
+    /// 对应的请求的 id
+    #[prost(uint64, tag = "8")]
+    pub id: u64,
+    /// status 不是 2xx 时，具体是哪一种错误
+    #[prost(message, optional, tag = "9")]
+    pub error: ::core::option::Option<ErrorDetail>,
+}
+/// 错误的详细信息，客户端可以用它还原出 KvError
+/// ENCODE_ERROR、DECODE_ERROR 和 SLED_ERROR 没法还原出原来的错误，会还原成 Internal
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(PartialOrd)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ErrorDetail {
+    #[prost(enumeration = "ErrorCode", tag = "1")]
+    pub code: i32,
+    /// 错误的参数，每种错误码的参数见 ErrorCode
+    #[prost(string, repeated, tag = "2")]
+    pub args: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// ConvertError 里没法转换的值
+    #[prost(message, optional, tag = "3")]
+    pub value: ::core::option::Option<Value>,
+    /// Rollback 里导致事务回滚的错误
+    #[prost(message, optional, boxed, tag = "4")]
+    pub cause: ::core::option::Option<::prost::alloc::boxed::Box<ErrorDetail>>,
 }
 /// 从 table 中获取一个 key，返回 value
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
 #[derive(PartialOrd)]
+#[serde(default)]
 #[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
 pub struct Hget {
     #[prost(string, tag = "1")]
@@ -58,14 +143,72 @@ pub struct Hget {
     pub key: ::prost::alloc::string::String,
 }
 /// 从 table 中获取所有的 Kvpair
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
 #[derive(PartialOrd)]
+#[serde(default)]
 #[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
 pub struct Hgetall {
     #[prost(string, tag = "1")]
     pub table: ::prost::alloc::string::String,
 }
+/// 列出所有的 table，名字按顺序放在 values 里
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(PartialOrd)]
+#[serde(default)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct Tables {}
+/// 删除整个 table
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(PartialOrd)]
+#[serde(default)]
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct Hdrop {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+}
+/// 返回 table 里 key 的个数
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(PartialOrd)]
+#[serde(default)]
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct Hlen {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+}
+/// 按 key 的顺序分页扫描 table，返回的 Kvpair 放在 pairs 里
+/// 后面还有数据时，values 里带着下一页的 cursor；扫描完了 values 为空
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(PartialOrd)]
+#[serde(default)]
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct Hscan {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    /// 只返回以 prefix 开头的 key
+    #[prost(string, tag = "2")]
+    pub prefix: ::prost::alloc::string::String,
+    /// 范围 [start, end)，end 为空表示没有上限
+    #[prost(string, tag = "3")]
+    pub start: ::prost::alloc::string::String,
+    #[prost(string, tag = "4")]
+    pub end: ::prost::alloc::string::String,
+    /// 每页最多返回多少个，0 表示用默认值
+    #[prost(uint32, tag = "5")]
+    pub limit: u32,
+    /// 上一页返回的 cursor，从它后面开始继续扫描
+    #[prost(string, tag = "6")]
+    pub cursor: ::prost::alloc::string::String,
+}
 /// 从 table 中获取一组 key，返回它们的 value
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
 #[derive(PartialOrd)]
+#[serde(default)]
 #[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
 pub struct Hmget {
     #[prost(string, tag = "1")]
@@ -74,14 +217,20 @@ pub struct Hmget {
     pub keys: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
 }
 /// 返回的值
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
 #[derive(PartialOrd)]
+#[serde(default)]
+#[serde(try_from = "serde_json::Value", into = "serde_json::Value")]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Value {
-    #[prost(oneof = "value::Value", tags = "1, 2, 3, 4, 5")]
+    #[prost(oneof = "value::Value", tags = "1, 2, 3, 4, 5, 6, 7, 8, 9")]
     pub value: ::core::option::Option<value::Value>,
 }
 /// Nested message and enum types in `Value`.
 pub mod value {
+    #[derive(serde::Serialize, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
     #[derive(PartialOrd)]
     #[derive(Clone, PartialEq, ::prost::Oneof)]
     pub enum Value {
@@ -95,10 +244,57 @@ pub mod value {
         Float(f64),
         #[prost(bool, tag = "5")]
         Bool(bool),
+        #[prost(message, tag = "6")]
+        List(super::ValueList),
+        /// key 是字符串的 map，value 可以继续嵌套，用来表示文档
+        #[prost(message, tag = "7")]
+        Map(super::ValueMap),
+        /// 明确存进去的空值，和没有设置 value 不一样
+        #[prost(enumeration = "super::NullValue", tag = "8")]
+        Null(i32),
+        #[prost(message, tag = "9")]
+        Timestamp(super::Timestamp),
     }
 }
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(PartialOrd)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ValueList {
+    #[prost(message, repeated, tag = "1")]
+    pub values: ::prost::alloc::vec::Vec<Value>,
+}
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(PartialOrd)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ValueMap {
+    #[prost(btree_map = "string, message", tag = "1")]
+    pub values: ::prost::alloc::collections::BTreeMap<
+        ::prost::alloc::string::String,
+        Value,
+    >,
+}
+/// 从 1970-01-01 00:00:00 UTC 开始的时间，和 google.protobuf.Timestamp 一样
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(PartialOrd)]
+#[serde(default)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct Timestamp {
+    #[prost(int64, tag = "1")]
+    pub seconds: i64,
+    /// 0 到 999,999,999
+    #[prost(int32, tag = "2")]
+    pub nanos: i32,
+}
 /// 返回的 kvpair
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
 #[derive(PartialOrd)]
+#[serde(default)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Kvpair {
     #[prost(string, tag = "1")]
@@ -108,26 +304,44 @@ pub struct Kvpair {
 }
 /// 往 table 里存一个 kvpair，
 /// 如果 table 不存在就创建这个 table
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
 #[derive(PartialOrd)]
+#[serde(default)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Hset {
     #[prost(string, tag = "1")]
     pub table: ::prost::alloc::string::String,
     #[prost(message, optional, tag = "2")]
     pub pair: ::core::option::Option<Kvpair>,
+    /// 过期时间，单位毫秒，0 表示永不过期
+    #[prost(uint64, tag = "3")]
+    pub ttl: u64,
+    /// 过期的时刻，1970 年以来的毫秒数，复制的时候用，不为 0 时忽略 ttl
+    #[prost(uint64, tag = "4")]
+    pub deadline: u64,
 }
 /// 往 table 中存一组 kvpair，
 /// 如果 table 不存在就创建这个 table
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
 #[derive(PartialOrd)]
+#[serde(default)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Hmset {
     #[prost(string, tag = "1")]
     pub table: ::prost::alloc::string::String,
     #[prost(message, repeated, tag = "2")]
     pub pairs: ::prost::alloc::vec::Vec<Kvpair>,
+    /// 过期时间，单位毫秒，对所有 kvpair 生效，0 表示永不过期
+    #[prost(uint64, tag = "3")]
+    pub ttl: u64,
 }
 /// 从 table 中删除一个 key，返回它之前的值
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
 #[derive(PartialOrd)]
+#[serde(default)]
 #[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
 pub struct Hdel {
     #[prost(string, tag = "1")]
@@ -136,7 +350,10 @@ pub struct Hdel {
     pub key: ::prost::alloc::string::String,
 }
 /// 从 table 中删除一组 key，返回它们之前的值
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
 #[derive(PartialOrd)]
+#[serde(default)]
 #[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
 pub struct Hmdel {
     #[prost(string, tag = "1")]
@@ -145,7 +362,10 @@ pub struct Hmdel {
     pub keys: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
 }
 /// 查看 key 是否存在
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
 #[derive(PartialOrd)]
+#[serde(default)]
 #[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
 pub struct Hexist {
     #[prost(string, tag = "1")]
@@ -154,7 +374,10 @@ pub struct Hexist {
     pub key: ::prost::alloc::string::String,
 }
 /// 查看一组 key 是否存在
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
 #[derive(PartialOrd)]
+#[serde(default)]
 #[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
 pub struct Hmexist {
     #[prost(string, tag = "1")]
@@ -162,3 +385,301 @@ pub struct Hmexist {
     #[prost(string, repeated, tag = "2")]
     pub keys: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
 }
+/// 设置 key 的过期时间，单位毫秒，0 表示取消过期
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(PartialOrd)]
+#[serde(default)]
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct Hexpire {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(uint64, tag = "3")]
+    pub ttl: u64,
+}
+/// 查看 key 剩余的过期时间，单位毫秒，-1 表示永不过期
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(PartialOrd)]
+#[serde(default)]
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct Httl {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+}
+/// 把 key 对应的整数加上 delta，返回新的值
+/// key 不存在时从 0 开始，delta 为负数时就是减
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(PartialOrd)]
+#[serde(default)]
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct Hincrby {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(int64, tag = "3")]
+    pub delta: i64,
+}
+/// 把 key 对应的数字加上浮点数 delta，返回新的值
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(PartialOrd)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hincrbyfloat {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(double, tag = "3")]
+    pub delta: f64,
+}
+/// 当 key 的值等于 expected 时把它换成 new_value，expected 为空表示 key 必须不存在
+/// 返回是否替换成功，以及 key 当前的值
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(PartialOrd)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hcas {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "3")]
+    pub expected: ::core::option::Option<Value>,
+    #[prost(message, optional, tag = "4")]
+    pub new_value: ::core::option::Option<Value>,
+}
+/// 原子地执行一组命令，要么全部成功，要么全部回滚
+/// 只支持针对单个 key 的读写命令，不支持 ttl
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(PartialOrd)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Transaction {
+    #[prost(message, repeated, tag = "1")]
+    pub commands: ::prost::alloc::vec::Vec<CommandRequest>,
+}
+/// 订阅某个主题，任何发布到这个主题的数据都会被收到
+/// 成功后，第一个返回的 CommandResponse 里带着这次订阅唯一的 id
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(PartialOrd)]
+#[serde(default)]
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct Subscribe {
+    #[prost(string, tag = "1")]
+    pub topic: ::prost::alloc::string::String,
+}
+/// 取消对某个主题的订阅
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(PartialOrd)]
+#[serde(default)]
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct Unsubscribe {
+    #[prost(string, tag = "1")]
+    pub topic: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "2")]
+    pub id: u32,
+}
+/// 发布数据到某个主题
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(PartialOrd)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Publish {
+    #[prost(string, tag = "1")]
+    pub topic: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag = "2")]
+    pub data: ::prost::alloc::vec::Vec<Value>,
+}
+/// 认证当前连接，用 token 或者用户名加密码
+/// 服务端配置了 ACL 时，连接要先认证才能执行其它命令
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(PartialOrd)]
+#[serde(default)]
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+#[prost(skip_debug)]
+pub struct Auth {
+    #[prost(string, tag = "1")]
+    pub token: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub username: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub password: ::prost::alloc::string::String,
+}
+/// follower 从 leader 复制数据：leader 先把现有的数据转换成命令发过来，
+/// 然后持续推送执行成功的写命令
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(PartialOrd)]
+#[serde(default)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct Replicate {}
+/// 协商连接的选项，服务端返回 200 时 values 里是选中的压缩算法
+/// 旧版本的服务端不认识这个命令，会返回错误，连接保持原样
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(PartialOrd)]
+#[serde(default)]
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct Negotiate {
+    /// 客户端支持的压缩算法，目前只有 gzip
+    #[prost(string, repeated, tag = "1")]
+    pub compression: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// MemTable 持久化用的记录，WAL 和快照都是一串 length-delimited 的 LogEntry
+/// 每条记录是某个 key 修改之后的完整状态，重放时直接覆盖
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(PartialOrd)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct LogEntry {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    /// 为空表示 key 被删除
+    #[prost(message, optional, tag = "3")]
+    pub value: ::core::option::Option<Value>,
+    /// 过期时间点的毫秒时间戳，0 表示不过期
+    #[prost(uint64, tag = "4")]
+    pub expire_at: u64,
+    /// 整个 table 被删除了，这时 key 和 value 都为空
+    #[prost(bool, tag = "5")]
+    pub dropped: bool,
+}
+/// 错误码，每个 KvError 对应一个，数值不会改变
+/// status 只是大致的分类，客户端应该根据错误码判断错误的类型
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum ErrorCode {
+    /// 没有错误
+    None = 0,
+    /// key 不存在，args 是 table 和 key，status 是 404
+    NotFound = 1,
+    /// 命令不合法，args 是错误信息，status 是 400
+    InvalidCommand = 2,
+    /// value 的类型不对，value 是原来的值，args 是要转换成的类型，status 是 500
+    ConvertError = 3,
+    /// 存储出错，args 是命令、table、key 和错误信息，status 是 500
+    StorageError = 4,
+    /// protobuf 编码失败，status 是 500
+    EncodeError = 5,
+    /// protobuf 解码失败，status 是 500
+    DecodeError = 6,
+    /// 服务端内部错误，args 是错误信息，status 是 500
+    Internal = 7,
+    /// sled 出错，status 是 500
+    SledError = 8,
+    /// 帧格式不对，args 是错误信息，status 是 500
+    FrameError = 9,
+    /// I/O 出错，args 是错误信息，status 是 500
+    IoError = 10,
+    /// 证书或私钥解析失败，args 是解析失败的内容，status 是 500
+    CertificateParseError = 11,
+    /// TLS 出错，args 是错误信息，status 是 500
+    TlsError = 12,
+    /// 事务回滚，cause 是导致回滚的错误，status 和 cause 的一样
+    Rollback = 13,
+    /// 没有认证或者认证失败，args 是错误信息，status 是 401
+    Unauthorized = 14,
+    /// 没有权限，args 是错误信息，status 是 403
+    PermissionDenied = 15,
+    /// 在只读的 follower 上执行写命令，status 是 403
+    ReadOnly = 16,
+    /// 配置不合法，args 是错误信息，status 是 500
+    ConfigError = 17,
+}
+impl ErrorCode {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            Self::None => "ERROR_CODE_NONE",
+            Self::NotFound => "ERROR_CODE_NOT_FOUND",
+            Self::InvalidCommand => "ERROR_CODE_INVALID_COMMAND",
+            Self::ConvertError => "ERROR_CODE_CONVERT_ERROR",
+            Self::StorageError => "ERROR_CODE_STORAGE_ERROR",
+            Self::EncodeError => "ERROR_CODE_ENCODE_ERROR",
+            Self::DecodeError => "ERROR_CODE_DECODE_ERROR",
+            Self::Internal => "ERROR_CODE_INTERNAL",
+            Self::SledError => "ERROR_CODE_SLED_ERROR",
+            Self::FrameError => "ERROR_CODE_FRAME_ERROR",
+            Self::IoError => "ERROR_CODE_IO_ERROR",
+            Self::CertificateParseError => "ERROR_CODE_CERTIFICATE_PARSE_ERROR",
+            Self::TlsError => "ERROR_CODE_TLS_ERROR",
+            Self::Rollback => "ERROR_CODE_ROLLBACK",
+            Self::Unauthorized => "ERROR_CODE_UNAUTHORIZED",
+            Self::PermissionDenied => "ERROR_CODE_PERMISSION_DENIED",
+            Self::ReadOnly => "ERROR_CODE_READ_ONLY",
+            Self::ConfigError => "ERROR_CODE_CONFIG_ERROR",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "ERROR_CODE_NONE" => Some(Self::None),
+            "ERROR_CODE_NOT_FOUND" => Some(Self::NotFound),
+            "ERROR_CODE_INVALID_COMMAND" => Some(Self::InvalidCommand),
+            "ERROR_CODE_CONVERT_ERROR" => Some(Self::ConvertError),
+            "ERROR_CODE_STORAGE_ERROR" => Some(Self::StorageError),
+            "ERROR_CODE_ENCODE_ERROR" => Some(Self::EncodeError),
+            "ERROR_CODE_DECODE_ERROR" => Some(Self::DecodeError),
+            "ERROR_CODE_INTERNAL" => Some(Self::Internal),
+            "ERROR_CODE_SLED_ERROR" => Some(Self::SledError),
+            "ERROR_CODE_FRAME_ERROR" => Some(Self::FrameError),
+            "ERROR_CODE_IO_ERROR" => Some(Self::IoError),
+            "ERROR_CODE_CERTIFICATE_PARSE_ERROR" => Some(Self::CertificateParseError),
+            "ERROR_CODE_TLS_ERROR" => Some(Self::TlsError),
+            "ERROR_CODE_ROLLBACK" => Some(Self::Rollback),
+            "ERROR_CODE_UNAUTHORIZED" => Some(Self::Unauthorized),
+            "ERROR_CODE_PERMISSION_DENIED" => Some(Self::PermissionDenied),
+            "ERROR_CODE_READ_ONLY" => Some(Self::ReadOnly),
+            "ERROR_CODE_CONFIG_ERROR" => Some(Self::ConfigError),
+            _ => None,
+        }
+    }
+}
+/// 只有一个取值，用来在 oneof 里表示 null
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum NullValue {
+    NullValue = 0,
+}
+impl NullValue {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            Self::NullValue => "NULL_VALUE",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "NULL_VALUE" => Some(Self::NullValue),
+            _ => None,
+        }
+    }
+}
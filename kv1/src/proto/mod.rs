@@ -1,8 +1,12 @@
 use crate::proto::abi::command_request::RequestData;
 pub use crate::proto::abi::{CommandRequest, Hset, Kvpair, Value, value};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
 use bytes::Bytes;
 use http::StatusCode;
 use prost::Message;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 mod abi;
 use crate::KvError;
@@ -15,7 +19,44 @@ impl CommandRequest {
             request_data: Some(RequestData::Hset(Hset {
                 table: table.into(),
                 pair: Some(Kvpair::new(key, value)),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hset_with_ttl(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        value: Value,
+        ttl: Duration,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Hset(Hset {
+                table: table.into(),
+                pair: Some(Kvpair::new(key, value)),
+                ttl: ttl.as_millis() as _,
+                ..Default::default()
             })),
+            ..Default::default()
+        }
+    }
+
+    // deadline 是过期的时刻，1970 年以来的毫秒数
+    pub fn new_hset_with_deadline(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        value: Value,
+        deadline: u64,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Hset(Hset {
+                table: table.into(),
+                pair: Some(Kvpair::new(key, value)),
+                deadline,
+                ..Default::default()
+            })),
+            ..Default::default()
         }
     }
 
@@ -24,7 +65,92 @@ impl CommandRequest {
             request_data: Some(RequestData::Hmset(Hmset {
                 table: table.into(),
                 pairs: items,
+                ttl: 0,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hmset_with_ttl(table: impl Into<String>, items: Vec<Kvpair>, ttl: Duration) -> Self {
+        Self {
+            request_data: Some(RequestData::Hmset(Hmset {
+                table: table.into(),
+                pairs: items,
+                ttl: ttl.as_millis() as _,
+            })),
+            ..Default::default()
+        }
+    }
+
+    // ttl 为 None 时取消过期
+    pub fn new_hexpire(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        ttl: Option<Duration>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Hexpire(Hexpire {
+                table: table.into(),
+                key: key.into(),
+                ttl: ttl.map(|v| v.as_millis() as _).unwrap_or_default(),
             })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_httl(table: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Httl(Httl {
+                table: table.into(),
+                key: key.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hincrby(table: impl Into<String>, key: impl Into<String>, delta: i64) -> Self {
+        Self {
+            request_data: Some(RequestData::Hincrby(Hincrby {
+                table: table.into(),
+                key: key.into(),
+                delta,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hincrbyfloat(table: impl Into<String>, key: impl Into<String>, delta: f64) -> Self {
+        Self {
+            request_data: Some(RequestData::Hincrbyfloat(Hincrbyfloat {
+                table: table.into(),
+                key: key.into(),
+                delta,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hcas(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        expected: Option<Value>,
+        new_value: Value,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Hcas(Hcas {
+                table: table.into(),
+                key: key.into(),
+                expected,
+                new_value: Some(new_value),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_transaction(commands: Vec<CommandRequest>) -> Self {
+        Self {
+            request_data: Some(RequestData::Transaction(Transaction { commands })),
+            ..Default::default()
         }
     }
 
@@ -34,6 +160,7 @@ impl CommandRequest {
                 table: table.into(),
                 key: key.into(),
             })),
+            ..Default::default()
         }
     }
 
@@ -47,6 +174,7 @@ impl CommandRequest {
                 table: table.into(),
                 keys: keys.into_iter().map(|v| v.into()).collect(),
             })),
+            ..Default::default()
         }
     }
 
@@ -56,6 +184,7 @@ impl CommandRequest {
                 table: table.into(),
                 key: key.into(),
             })),
+            ..Default::default()
         }
     }
 
@@ -65,6 +194,21 @@ impl CommandRequest {
                 table: table.into(),
                 key: key.into(),
             })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hmexist<T, K>(table: impl Into<String>, keys: T) -> Self
+    where
+        K: Into<String>,
+        T: IntoIterator<Item = K>,
+    {
+        Self {
+            request_data: Some(RequestData::Hmexist(Hmexist {
+                table: table.into(),
+                keys: keys.into_iter().map(|k| k.into()).collect(),
+            })),
+            ..Default::default()
         }
     }
 
@@ -78,6 +222,36 @@ impl CommandRequest {
                 table: table.into(),
                 keys: keys.into_iter().map(|k| k.into()).collect(),
             })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_subscribe(topic: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Subscribe(Subscribe {
+                topic: topic.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_unsubscribe(topic: impl Into<String>, id: u32) -> Self {
+        Self {
+            request_data: Some(RequestData::Unsubscribe(Unsubscribe {
+                topic: topic.into(),
+                id,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_publish(topic: impl Into<String>, data: Vec<Value>) -> Self {
+        Self {
+            request_data: Some(RequestData::Publish(Publish {
+                topic: topic.into(),
+                data,
+            })),
+            ..Default::default()
         }
     }
 
@@ -86,8 +260,115 @@ impl CommandRequest {
             request_data: Some(RequestData::Hgetall(Hgetall {
                 table: table.into(),
             })),
+            ..Default::default()
         }
     }
+
+    pub fn new_tables() -> Self {
+        Self {
+            request_data: Some(RequestData::Tables(Tables {})),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hdrop(table: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hdrop(Hdrop {
+                table: table.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hlen(table: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hlen(Hlen {
+                table: table.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_auth_token(token: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Auth(Auth {
+                token: token.into(),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_auth(username: impl Into<String>, password: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Auth(Auth {
+                username: username.into(),
+                password: password.into(),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_replicate() -> Self {
+        Self {
+            request_data: Some(RequestData::Replicate(Replicate {})),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_negotiate(compression: &[&str]) -> Self {
+        Self {
+            request_data: Some(RequestData::Negotiate(Negotiate {
+                compression: compression.iter().map(|v| v.to_string()).collect(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hscan(table: impl Into<String>, prefix: impl Into<String>, limit: u32) -> Self {
+        Self {
+            request_data: Some(RequestData::Hscan(Hscan {
+                table: table.into(),
+                prefix: prefix.into(),
+                limit,
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hscan_range(
+        table: impl Into<String>,
+        start: impl Into<String>,
+        end: impl Into<String>,
+        limit: u32,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Hscan(Hscan {
+                table: table.into(),
+                start: start.into(),
+                end: end.into(),
+                limit,
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+    }
+
+    // 带上 id 的请求可以不等前面的响应就发送，服务端会并发处理
+    pub fn with_id(mut self, id: u64) -> Self {
+        self.id = id;
+        self
+    }
+
+    // 带上上一页返回的 cursor，请求 Hscan 的下一页
+    pub fn with_cursor(mut self, cursor: impl Into<String>) -> Self {
+        if let Some(RequestData::Hscan(ref mut v)) = self.request_data {
+            v.cursor = cursor.into();
+        }
+        self
+    }
 }
 
 impl Kvpair {
@@ -99,6 +380,18 @@ impl Kvpair {
     }
 }
 
+// 请求会打到日志里，token 和密码不能出现在 Debug 的输出里
+impl std::fmt::Debug for Auth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let redact = |s: &str| if s.is_empty() { "" } else { "<redacted>" };
+        f.debug_struct("Auth")
+            .field("token", &redact(&self.token))
+            .field("username", &self.username)
+            .field("password", &redact(&self.password))
+            .finish()
+    }
+}
+
 impl From<String> for Value {
     fn from(s: String) -> Self {
         Self {
@@ -150,9 +443,14 @@ impl From<bool> for CommandResponse {
                 status: StatusCode::OK.as_u16() as _,
                 ..Default::default()
             },
+            // 和 KvError::NotFound 一样，只是没有 table 和 key
             false => Self {
-                status: StatusCode::INTERNAL_SERVER_ERROR.as_u16() as _,
+                status: StatusCode::NOT_FOUND.as_u16() as _,
                 message: "Not Found".into(),
+                error: Some(ErrorDetail {
+                    code: ErrorCode::NotFound as _,
+                    ..Default::default()
+                }),
                 ..Default::default()
             },
         }
@@ -161,19 +459,158 @@ impl From<bool> for CommandResponse {
 
 impl From<KvError> for CommandResponse {
     fn from(err: KvError) -> Self {
-        let mut result = Self {
-            status: StatusCode::INTERNAL_SERVER_ERROR.as_u16() as _,
+        Self {
+            status: status_of(&err).as_u16() as _,
             message: err.to_string(),
-            values: vec![],
-            pairs: vec![],
-        };
+            error: Some(err.into()),
+            ..Default::default()
+        }
+    }
+}
 
+// 每种错误对应的 status，见 abi.proto 里的 ErrorCode
+fn status_of(err: &KvError) -> StatusCode {
+    match err {
+        KvError::NotFound(..) => StatusCode::NOT_FOUND,
+        KvError::InvalidCommand(_) => StatusCode::BAD_REQUEST,
+        KvError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+        KvError::PermissionDenied(_) | KvError::ReadOnly => StatusCode::FORBIDDEN,
+        KvError::Rollback(cause) => status_of(cause),
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+impl From<KvError> for ErrorDetail {
+    fn from(err: KvError) -> Self {
+        let detail = |code: ErrorCode, args: Vec<String>| Self {
+            code: code as _,
+            args,
+            ..Default::default()
+        };
         match err {
-            KvError::NotFound(_, _) => result.status = StatusCode::NOT_FOUND.as_u16() as _,
-            KvError::InvalidCommand(_) => result.status = StatusCode::BAD_REQUEST.as_u16() as _,
-            _ => {}
+            KvError::NotFound(table, key) => detail(ErrorCode::NotFound, vec![table, key]),
+            KvError::InvalidCommand(msg) => detail(ErrorCode::InvalidCommand, vec![msg]),
+            KvError::ConvertError(value, to) => Self {
+                value: Some(value),
+                ..detail(ErrorCode::ConvertError, vec![to.into()])
+            },
+            KvError::StorageError(cmd, table, key, msg) => {
+                detail(ErrorCode::StorageError, vec![cmd, table, key, msg])
+            }
+            KvError::EncodeError(e) => detail(ErrorCode::EncodeError, vec![e.to_string()]),
+            KvError::DecodeError(e) => detail(ErrorCode::DecodeError, vec![e.to_string()]),
+            KvError::Internal(msg) => detail(ErrorCode::Internal, vec![msg]),
+            KvError::SledError(e) => detail(ErrorCode::SledError, vec![e.to_string()]),
+            KvError::FrameError(msg) => detail(ErrorCode::FrameError, vec![msg]),
+            KvError::IoError(msg) => detail(ErrorCode::IoError, vec![msg]),
+            KvError::CertificateParseError(what) => {
+                detail(ErrorCode::CertificateParseError, vec![what.into()])
+            }
+            KvError::TlsError(msg) => detail(ErrorCode::TlsError, vec![msg]),
+            KvError::Rollback(cause) => Self {
+                cause: Some(Box::new((*cause).into())),
+                ..detail(ErrorCode::Rollback, vec![])
+            },
+            KvError::Unauthorized(msg) => detail(ErrorCode::Unauthorized, vec![msg]),
+            KvError::PermissionDenied(msg) => detail(ErrorCode::PermissionDenied, vec![msg]),
+            KvError::ReadOnly => detail(ErrorCode::ReadOnly, vec![]),
+            KvError::ConfigError(msg) => detail(ErrorCode::ConfigError, vec![msg]),
+        }
+    }
+}
+
+impl From<ErrorDetail> for KvError {
+    fn from(detail: ErrorDetail) -> Self {
+        let code = detail.code();
+        let mut args = detail.args.into_iter();
+        let mut arg = || args.next().unwrap_or_default();
+        match code {
+            ErrorCode::NotFound => KvError::NotFound(arg(), arg()),
+            ErrorCode::InvalidCommand => KvError::InvalidCommand(arg()),
+            ErrorCode::ConvertError => KvError::ConvertError(
+                detail.value.unwrap_or_default(),
+                static_name(
+                    &arg(),
+                    &[
+                        "String",
+                        "Integer",
+                        "Float",
+                        "Binary",
+                        "Boolean",
+                        "List",
+                        "Map",
+                        "Timestamp",
+                    ],
+                ),
+            ),
+            ErrorCode::StorageError => KvError::StorageError(arg(), arg(), arg(), arg()),
+            ErrorCode::FrameError => KvError::FrameError(arg()),
+            ErrorCode::IoError => KvError::IoError(arg()),
+            ErrorCode::CertificateParseError => {
+                KvError::CertificateParseError(static_name(&arg(), &["certificate", "private key"]))
+            }
+            ErrorCode::TlsError => KvError::TlsError(arg()),
+            ErrorCode::Rollback => KvError::Rollback(Box::new(match detail.cause {
+                Some(cause) => (*cause).into(),
+                None => KvError::Internal("Transaction is rolled back".into()),
+            })),
+            ErrorCode::Unauthorized => KvError::Unauthorized(arg()),
+            ErrorCode::PermissionDenied => KvError::PermissionDenied(arg()),
+            ErrorCode::ReadOnly => KvError::ReadOnly,
+            ErrorCode::ConfigError => KvError::ConfigError(arg()),
+            // 原来的错误类型没法在客户端构造出来
+            ErrorCode::EncodeError
+            | ErrorCode::DecodeError
+            | ErrorCode::SledError
+            | ErrorCode::Internal
+            | ErrorCode::None => KvError::Internal(arg()),
+        }
+    }
+}
+
+// KvError 里有些字段是 &'static str，只能还原成已知的值
+fn static_name(name: &str, known: &[&'static str]) -> &'static str {
+    known
+        .iter()
+        .find(|v| **v == name)
+        .copied()
+        .unwrap_or("unknown")
+}
+
+impl CommandResponse {
+    // 客户端用它把出错的响应还原成 KvError
+    pub fn into_result(self) -> Result<Self, KvError> {
+        if self.status < 300 {
+            return Ok(self);
+        }
+        match self.error {
+            Some(detail) if detail.code() != ErrorCode::None => Err(detail.into()),
+            // 没有错误码的响应，比如旧版本的服务端或者 hook 修改过的
+            _ => Err(KvError::Internal(format!(
+                "{}: {}",
+                self.status, self.message
+            ))),
+        }
+    }
+}
+
+impl From<Vec<CommandRequest>> for CommandResponse {
+    fn from(v: Vec<CommandRequest>) -> Self {
+        Self {
+            status: StatusCode::OK.as_u16() as _,
+            commands: v,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<Vec<CommandResponse>> for CommandResponse {
+    fn from(v: Vec<CommandResponse>) -> Self {
+        Self {
+            status: StatusCode::OK.as_u16() as _,
+            responses: v,
+            ..Default::default()
         }
-        result
     }
 }
 
@@ -203,6 +640,152 @@ impl From<f64> for Value {
     }
 }
 
+impl From<Bytes> for Value {
+    fn from(b: Bytes) -> Self {
+        Self {
+            value: Some(value::Value::Binary(b)),
+        }
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(values: Vec<Value>) -> Self {
+        Self {
+            value: Some(value::Value::List(ValueList { values })),
+        }
+    }
+}
+
+impl From<BTreeMap<String, Value>> for Value {
+    fn from(values: BTreeMap<String, Value>) -> Self {
+        Self {
+            value: Some(value::Value::Map(ValueMap { values })),
+        }
+    }
+}
+
+// 1970 年之前的时间 seconds 是负数，nanos 还是正数
+impl From<SystemTime> for Value {
+    fn from(t: SystemTime) -> Self {
+        let (seconds, nanos) = match t.duration_since(UNIX_EPOCH) {
+            Ok(d) => (d.as_secs() as i64, d.subsec_nanos() as i32),
+            Err(e) => {
+                let d = e.duration();
+                match d.subsec_nanos() {
+                    0 => (-(d.as_secs() as i64), 0),
+                    n => (-(d.as_secs() as i64) - 1, (1_000_000_000 - n) as i32),
+                }
+            }
+        };
+        Self {
+            value: Some(value::Value::Timestamp(Timestamp { seconds, nanos })),
+        }
+    }
+}
+
+impl Value {
+    pub fn null() -> Self {
+        Self {
+            value: Some(value::Value::Null(NullValue::NullValue as _)),
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self.value, None | Some(value::Value::Null(_)))
+    }
+}
+
+// HTTP 网关里 value 直接用 JSON 的值表示
+// JSON 没有的类型用只有一个 key 的 object 表示：
+// 二进制是 {"$binary": "<base64>"}，时间戳是 {"$timestamp": {"seconds": 1, "nanos": 0}}
+// 没有设置的 value 和 null 都转换成 null，再转换回来是 null
+impl From<Value> for serde_json::Value {
+    fn from(v: Value) -> Self {
+        match v.value {
+            None | Some(value::Value::Null(_)) => Self::Null,
+            Some(value::Value::String(s)) => s.into(),
+            Some(value::Value::Binary(b)) => json!({ BINARY_KEY: BASE64.encode(b) }),
+            Some(value::Value::Integer(i)) => i.into(),
+            Some(value::Value::Float(f)) => f.into(),
+            Some(value::Value::Bool(b)) => b.into(),
+            Some(value::Value::List(l)) => {
+                Self::Array(l.values.into_iter().map(Into::into).collect())
+            }
+            Some(value::Value::Map(m)) => {
+                Self::Object(m.values.into_iter().map(|(k, v)| (k, v.into())).collect())
+            }
+            Some(value::Value::Timestamp(t)) => {
+                json!({ TIMESTAMP_KEY: { "seconds": t.seconds, "nanos": t.nanos } })
+            }
+        }
+    }
+}
+
+const BINARY_KEY: &str = "$binary";
+const TIMESTAMP_KEY: &str = "$timestamp";
+
+impl TryFrom<serde_json::Value> for Value {
+    type Error = KvError;
+
+    fn try_from(v: serde_json::Value) -> Result<Self, Self::Error> {
+        let invalid = |v: &serde_json::Value| {
+            KvError::InvalidCommand(format!("Cannot convert JSON {} to Value", v))
+        };
+        match v {
+            serde_json::Value::Null => Ok(Value::null()),
+            serde_json::Value::String(s) => Ok(s.into()),
+            serde_json::Value::Bool(b) => Ok(b.into()),
+            serde_json::Value::Number(n) => match n.as_i64() {
+                Some(i) => Ok(i.into()),
+                // 超出 i64 范围的整数也用 f64 表示
+                None => Ok(n.as_f64().unwrap_or_default().into()),
+            },
+            serde_json::Value::Array(a) => Ok(a
+                .into_iter()
+                .map(Value::try_from)
+                .collect::<Result<Vec<_>, _>>()?
+                .into()),
+            serde_json::Value::Object(o) if o.len() == 1 && o.contains_key(BINARY_KEY) => {
+                let b = o[BINARY_KEY]
+                    .as_str()
+                    .and_then(|s| BASE64.decode(s).ok())
+                    .ok_or_else(|| invalid(&o[BINARY_KEY]))?;
+                Ok(Bytes::from(b).into())
+            }
+            serde_json::Value::Object(o) if o.len() == 1 && o.contains_key(TIMESTAMP_KEY) => {
+                let t = &o[TIMESTAMP_KEY];
+                let seconds = t["seconds"].as_i64().ok_or_else(|| invalid(t))?;
+                let nanos = t["nanos"].as_i64().unwrap_or_default();
+                if !(0..1_000_000_000).contains(&nanos) {
+                    return Err(invalid(t));
+                }
+                Ok(Value {
+                    value: Some(value::Value::Timestamp(Timestamp {
+                        seconds,
+                        nanos: nanos as i32,
+                    })),
+                })
+            }
+            serde_json::Value::Object(o) => Ok(o
+                .into_iter()
+                .map(|(k, v)| Ok((k, Value::try_from(v)?)))
+                .collect::<Result<BTreeMap<_, _>, KvError>>()?
+                .into()),
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = KvError;
+
+    fn try_from(v: Value) -> Result<Self, Self::Error> {
+        match v.value {
+            Some(value::Value::String(s)) => Ok(s),
+            _ => Err(KvError::ConvertError(v, "String")),
+        }
+    }
+}
+
 impl TryFrom<Value> for i64 {
     type Error = KvError;
 
@@ -247,6 +830,49 @@ impl TryFrom<Value> for bool {
     }
 }
 
+impl TryFrom<Value> for Vec<Value> {
+    type Error = KvError;
+
+    fn try_from(v: Value) -> Result<Self, Self::Error> {
+        match v.value {
+            Some(value::Value::List(l)) => Ok(l.values),
+            _ => Err(KvError::ConvertError(v, "List")),
+        }
+    }
+}
+
+impl TryFrom<Value> for BTreeMap<String, Value> {
+    type Error = KvError;
+
+    fn try_from(v: Value) -> Result<Self, Self::Error> {
+        match v.value {
+            Some(value::Value::Map(m)) => Ok(m.values),
+            _ => Err(KvError::ConvertError(v, "Map")),
+        }
+    }
+}
+
+impl TryFrom<Value> for SystemTime {
+    type Error = KvError;
+
+    fn try_from(v: Value) -> Result<Self, Self::Error> {
+        let t = match &v.value {
+            Some(value::Value::Timestamp(t)) if (0..1_000_000_000).contains(&t.nanos) => t,
+            _ => return Err(KvError::ConvertError(v, "Timestamp")),
+        };
+        let nanos = Duration::from_nanos(t.nanos as u64);
+        let seconds = Duration::from_secs(t.seconds.unsigned_abs());
+        let time = match t.seconds >= 0 {
+            true => UNIX_EPOCH.checked_add(seconds),
+            false => UNIX_EPOCH.checked_sub(seconds),
+        };
+        match time.and_then(|time| time.checked_add(nanos)) {
+            Some(time) => Ok(time),
+            None => Err(KvError::ConvertError(v, "Timestamp")),
+        }
+    }
+}
+
 impl TryFrom<Value> for Vec<u8> {
     type Error = KvError;
     fn try_from(v: Value) -> Result<Self, Self::Error> {
@@ -264,3 +890,87 @@ impl TryFrom<&[u8]> for Value {
         Ok(msg)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auth_debug_should_not_contain_secrets() {
+        let cmd = format!("{:?}", CommandRequest::new_auth("alice", "secret-password"));
+        assert!(cmd.contains("alice"));
+        assert!(!cmd.contains("secret-password"));
+        let cmd = format!("{:?}", CommandRequest::new_auth_token("secret-token"));
+        assert!(!cmd.contains("secret-token"));
+    }
+
+    #[test]
+    fn json_should_round_trip_through_value() {
+        let doc = json!({
+            "name": "alice",
+            "age": 30,
+            "score": 9.5,
+            "active": true,
+            "nickname": null,
+            "tags": ["a", "b", [1, 2]],
+            "avatar": { "$binary": "AAEC" },
+            "created": { "$timestamp": { "seconds": 1700000000, "nanos": 5 } },
+            "profile": { "city": "Shanghai", "zip": [] },
+        });
+        let value = Value::try_from(doc.clone()).unwrap();
+        let map: BTreeMap<String, Value> = value.clone().try_into().unwrap();
+        assert_eq!(map["avatar"], Bytes::from_static(&[0, 1, 2]).into());
+        assert!(map["nickname"].is_null());
+        assert_eq!(serde_json::Value::from(value.clone()), doc);
+
+        // 编码成 protobuf 再解码也不会变
+        let buf: Vec<u8> = value.clone().try_into().unwrap();
+        assert_eq!(Value::try_from(buf.as_slice()).unwrap(), value);
+    }
+
+    #[test]
+    fn value_should_serialize_as_natural_json() {
+        // 和 REST 接口一样，CommandRequest 里的 value 直接用 JSON 的值表示
+        let pair = Kvpair::new("k1", Value::from(vec![Value::from(10), "a".into()]));
+        let json = serde_json::to_value(&pair).unwrap();
+        assert_eq!(json, json!({ "key": "k1", "value": [10, "a"] }));
+        assert_eq!(serde_json::from_value::<Kvpair>(json).unwrap(), pair);
+
+        let invalid = json!({ "key": "k1", "value": { "$binary": 1 } });
+        assert!(serde_json::from_value::<Kvpair>(invalid).is_err());
+    }
+
+    #[test]
+    fn value_should_convert_to_and_from_rich_types() {
+        let list: Value = vec![Value::from(1), Value::null(), "a".into()].into();
+        let values: Vec<Value> = list.try_into().unwrap();
+        assert_eq!(values.len(), 3);
+        assert!(values[1].is_null());
+        assert_eq!(
+            Vec::<Value>::try_from(Value::from(1)),
+            Err(KvError::ConvertError(1.into(), "List"))
+        );
+
+        for time in [
+            UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789),
+            UNIX_EPOCH - Duration::new(10, 1),
+            UNIX_EPOCH,
+        ] {
+            let value = Value::from(time);
+            assert_eq!(SystemTime::try_from(value.clone()), Ok(time));
+            let json = serde_json::Value::from(value.clone());
+            assert_eq!(Value::try_from(json), Ok(value));
+        }
+        let before = Value::from(UNIX_EPOCH - Duration::new(10, 1));
+        assert_eq!(
+            before.value,
+            Some(value::Value::Timestamp(Timestamp {
+                seconds: -11,
+                nanos: 999_999_999
+            }))
+        );
+
+        let invalid = json!({ "$timestamp": { "seconds": 1, "nanos": -1 } });
+        assert!(Value::try_from(invalid).is_err());
+    }
+}
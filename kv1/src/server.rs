@@ -0,0 +1,339 @@
+use anyhow::{Result, anyhow};
+use axum::extract::DefaultBodyLimit;
+use clap::Parser;
+use kv1::{
+    MemTable, ProstCodec, ProstServerStream, RespServerStream, ServerConfig, Service, ServiceInner,
+    SledDb, Storage, StorageConfig, TlsConfig, TlsServerAcceptor, WalOptions, admin, gateway,
+};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::{Level, info, warn};
+
+// 命令行的参数会覆盖配置文件里的
+#[derive(Debug, Parser)]
+#[command(name = "kvs", about = "The kv1 server")]
+struct Args {
+    #[arg(short, long, help = "Path of the TOML config file")]
+    config: Option<PathBuf>,
+    #[arg(long, help = "Address to listen on for kv1 clients")]
+    addr: Option<String>,
+    #[arg(long, value_parser = ["memory", "sled"], help = "Storage backend")]
+    storage: Option<String>,
+    #[arg(
+        long,
+        help = "Directory of the sled database, or of the WAL for memory"
+    )]
+    storage_path: Option<PathBuf>,
+    #[arg(long, help = "Log level: trace, debug, info, warn or error")]
+    log_level: Option<String>,
+    #[arg(long, help = "Maximum number of client connections")]
+    max_connections: Option<usize>,
+    #[arg(long, help = "Maximum size of a frame in bytes")]
+    max_frame: Option<usize>,
+    #[arg(long, help = "Path of the TLS certificate")]
+    tls_cert: Option<PathBuf>,
+    #[arg(long, help = "Path of the TLS private key")]
+    tls_key: Option<PathBuf>,
+}
+
+impl Args {
+    fn apply(self, config: &mut ServerConfig) -> Result<()> {
+        let general = &mut config.general;
+        if let Some(addr) = self.addr {
+            general.addr = addr;
+        }
+        if let Some(n) = self.max_connections {
+            general.max_connections = n;
+        }
+        if let Some(n) = self.max_frame {
+            general.max_frame = n;
+        }
+        if let Some(level) = self.log_level {
+            config.log.level = level;
+        }
+
+        // 配置文件里没有 TLS 时，证书和私钥都要在命令行里给出
+        config.tls = match (config.tls.take(), self.tls_cert, self.tls_key) {
+            (tls, None, None) => tls,
+            (Some(tls), cert, key) => Some(TlsConfig {
+                cert: cert.unwrap_or(tls.cert),
+                key: key.unwrap_or(tls.key),
+                ..tls
+            }),
+            (None, Some(cert), Some(key)) => Some(TlsConfig {
+                cert,
+                key,
+                ca: None,
+            }),
+            (None, _, _) => return Err(anyhow!("--tls-cert and --tls-key must be used together")),
+        };
+
+        // 只改了 path 或者只改了类型时，其它的沿用配置文件里的
+        let sled = match self.storage.as_deref() {
+            Some(storage) => storage == "sled",
+            None => matches!(config.storage, StorageConfig::Sled { .. }),
+        };
+        let old = match (&config.storage, sled) {
+            (StorageConfig::Sled { path }, true) => Some(path.clone()),
+            (StorageConfig::Memory { path }, false) => path.clone(),
+            _ => None,
+        };
+        let path = self.storage_path.or(old);
+        config.storage = match sled {
+            true => StorageConfig::Sled {
+                path: path.ok_or_else(|| anyhow!("--storage-path is required for sled"))?,
+            },
+            false => StorageConfig::Memory { path },
+        };
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let mut config = match &args.config {
+        Some(path) => ServerConfig::load(path)?,
+        None => ServerConfig::default(),
+    };
+    args.apply(&mut config)?;
+
+    let level: Level = config.log.level.parse()?;
+    tracing_subscriber::fmt().with_max_level(level).init();
+
+    match config.storage.clone() {
+        StorageConfig::Memory { path: None } => run(config, MemTable::new()).await,
+        StorageConfig::Memory { path: Some(path) } => {
+            run(config, MemTable::open(WalOptions::new(path))?).await
+        }
+        StorageConfig::Sled { path } => run(config, SledDb::try_new(path)?).await,
+    }
+}
+
+async fn run<Store: Storage>(config: ServerConfig, store: Store) -> Result<()> {
+    let service: Service<Store> = ServiceInner::new(store)
+        .table_metrics(config.general.table_metrics)
+        .into();
+    service.spawn_expiration_sweeper(Duration::from_secs(1));
+
+    let general = &config.general;
+    // 收到 SIGTERM 之后取消，所有的监听和连接都会停下来
+    let shutdown = CancellationToken::new();
+    // 所有的连接和 HTTP 服务，退出之前要等它们结束
+    let tracker = TaskTracker::new();
+    // kv1 和 RESP 的连接共用一个上限
+    let permits = Arc::new(Semaphore::new(general.max_connections));
+
+    // redis 的客户端连接另一个端口，和 kv1 的客户端共用同一个 Service
+    if let Some(addr) = &general.resp_addr {
+        let listener = TcpListener::bind(addr).await?;
+        info!("Start listening RESP on {}", addr);
+        let (service, shutdown) = (service.clone(), shutdown.clone());
+        let (tracker, permits) = (tracker.clone(), permits.clone());
+        let max_frame = general.max_frame;
+        tokio::spawn(async move {
+            while let Some((stream, addr, permit)) = accept(&listener, &permits, &shutdown).await {
+                info!("RESP client {:?} connected", addr);
+                let stream = RespServerStream::new(stream, service.clone())
+                    .with_max_frame(max_frame)
+                    .with_shutdown(shutdown.clone());
+                tracker.spawn(async move {
+                    if let Err(e) = stream.process().await {
+                        warn!("Failed to process RESP client {:?}: {:?}", addr, e);
+                    }
+                    drop(permit);
+                });
+            }
+        });
+    }
+
+    // HTTP 网关和管理接口
+    let routers = [
+        // 请求体也不能超过 max_frame
+        (
+            "HTTP",
+            &general.http_addr,
+            gateway(service.clone()).layer(DefaultBodyLimit::max(general.max_frame)),
+        ),
+        ("admin", &general.admin_addr, admin(service.clone())),
+    ];
+    for (name, addr, app) in routers {
+        let Some(addr) = addr else { continue };
+        let listener = TcpListener::bind(addr).await?;
+        info!("Start listening {} on {}", name, addr);
+        let shutdown = shutdown.clone();
+        tracker.spawn(async move {
+            let server =
+                axum::serve(listener, app).with_graceful_shutdown(shutdown.cancelled_owned());
+            if let Err(e) = server.await {
+                warn!("{} server stopped: {:?}", name, e);
+            }
+        });
+    }
+
+    let acceptor = match &config.tls {
+        Some(tls) => Some(TlsServerAcceptor::from_files(
+            &tls.cert,
+            &tls.key,
+            tls.ca.as_ref(),
+        )?),
+        None => None,
+    };
+
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            match wait_for_signal().await {
+                Ok(()) => info!("Shutting down"),
+                Err(e) => warn!("Failed to listen for signals: {:?}", e),
+            }
+            shutdown.cancel();
+        }
+    });
+
+    let listener = TcpListener::bind(&general.addr).await?;
+    info!("Start listening on {}", general.addr);
+    while let Some((stream, addr, permit)) = accept(&listener, &permits, &shutdown).await {
+        info!("Client {:?} connected", addr);
+        let (service, shutdown) = (service.clone(), shutdown.clone());
+        let (acceptor, max_frame) = (acceptor.clone(), general.max_frame);
+        tracker.spawn(async move {
+            let result = match acceptor {
+                Some(acceptor) => match acceptor.accept(stream).await {
+                    Ok(stream) => serve(stream, service, max_frame, shutdown).await,
+                    Err(e) => Err(e),
+                },
+                None => serve(stream, service, max_frame, shutdown).await,
+            };
+            if let Err(e) = result {
+                warn!("Failed to process client {:?}: {:?}", addr, e);
+            }
+            info!("Client {:?} disconnected", addr);
+            drop(permit);
+        });
+    }
+
+    // 不再接受新的连接，等已有的连接把收到的请求处理完
+    tracker.close();
+    let timeout = Duration::from_secs(general.shutdown_timeout);
+    if tokio::time::timeout(timeout, tracker.wait()).await.is_err() {
+        warn!(
+            "{} connections are not closed in {:?}",
+            tracker.len(),
+            timeout
+        );
+    }
+    info!("Server stopped");
+    Ok(())
+}
+
+// 连接数达到上限时先不 accept，等有连接断开
+// 返回 None 表示要关闭了
+async fn accept(
+    listener: &TcpListener,
+    permits: &Arc<Semaphore>,
+    shutdown: &CancellationToken,
+) -> Option<(
+    tokio::net::TcpStream,
+    std::net::SocketAddr,
+    OwnedSemaphorePermit,
+)> {
+    loop {
+        let permit = tokio::select! {
+            permit = permits.clone().acquire_owned() => permit.ok()?,
+            _ = shutdown.cancelled() => return None,
+        };
+        tokio::select! {
+            res = listener.accept() => match res {
+                Ok((stream, addr)) => return Some((stream, addr, permit)),
+                Err(e) => warn!("Failed to accept client: {:?}", e),
+            },
+            _ = shutdown.cancelled() => return None,
+        }
+    }
+}
+
+async fn serve<S, Store>(
+    stream: S,
+    service: Service<Store>,
+    max_frame: usize,
+    shutdown: CancellationToken,
+) -> Result<(), kv1::KvError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+    Store: Storage,
+{
+    ProstServerStream::with_codec(stream, service, ProstCodec::with_max_frame(max_frame))
+        .with_shutdown(shutdown)
+        .process()
+        .await
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() -> std::io::Result<()> {
+    use tokio::signal::unix::{SignalKind, signal};
+    let mut term = signal(SignalKind::terminate())?;
+    tokio::select! {
+        _ = term.recv() => Ok(()),
+        res = tokio::signal::ctrl_c() => res,
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() -> std::io::Result<()> {
+    tokio::signal::ctrl_c().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn args_should_override_config() {
+        let mut config: ServerConfig = "[storage]\ntype = \"sled\"\npath = \"/tmp/kvs\""
+            .parse()
+            .unwrap();
+        let args = Args::parse_from(["kvs", "--addr", "0.0.0.0:9527", "--max-frame", "1024"]);
+        args.apply(&mut config).unwrap();
+        assert_eq!(config.general.addr, "0.0.0.0:9527");
+        assert_eq!(config.general.max_frame, 1024);
+        assert_eq!(
+            config.storage,
+            StorageConfig::Sled {
+                path: "/tmp/kvs".into()
+            }
+        );
+
+        // 配置文件里没有 TLS 时，证书和私钥要一起给出
+        let args = Args::parse_from(["kvs", "--tls-cert", "server.cert"]);
+        assert!(args.apply(&mut config).is_err());
+        let args = Args::parse_from(["kvs", "--tls-cert", "a.cert", "--tls-key", "a.key"]);
+        args.apply(&mut config).unwrap();
+        let args = Args::parse_from(["kvs", "--tls-cert", "b.cert"]);
+        args.apply(&mut config).unwrap();
+        let tls = config.tls.clone().unwrap();
+        assert_eq!((tls.cert, tls.key), ("b.cert".into(), "a.key".into()));
+
+        let args = Args::parse_from(["kvs", "--storage", "memory"]);
+        args.apply(&mut config).unwrap();
+        assert_eq!(config.storage, StorageConfig::Memory { path: None });
+
+        // 换成 sled 时一定要有 path
+        let args = Args::parse_from(["kvs", "--storage", "sled"]);
+        assert!(args.apply(&mut config).is_err());
+        let args = Args::parse_from(["kvs", "--storage", "sled", "--storage-path", "/data"]);
+        args.apply(&mut config).unwrap();
+        assert_eq!(
+            config.storage,
+            StorageConfig::Sled {
+                path: "/data".into()
+            }
+        );
+    }
+}
@@ -0,0 +1,253 @@
+use crate::command_request::RequestData;
+use crate::{Auth, CommandRequest, KvError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Permission {
+    fn allows(self, need: Permission) -> bool {
+        self == Permission::ReadWrite || self == need
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct User {
+    name: String,
+    password: Option<String>,
+    token: Option<String>,
+    // table 的模式和对应的权限，模式是完整的 table 名，或者以 * 结尾匹配前缀
+    grants: Vec<(String, Permission)>,
+}
+
+impl User {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            password: None,
+            token: None,
+            grants: Vec::new(),
+        }
+    }
+
+    pub fn password(mut self, password: impl Into<String>) -> Self {
+        self.password = Some(password.into());
+        self
+    }
+
+    pub fn token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    pub fn grant(mut self, pattern: impl Into<String>, permission: Permission) -> Self {
+        self.grants.push((pattern.into(), permission));
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn allows(&self, table: &str, need: Permission) -> bool {
+        self.grants
+            .iter()
+            .any(|(pattern, permission)| permission.allows(need) && pattern_matches(pattern, table))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Acl {
+    users: Vec<User>,
+}
+
+impl Acl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn user(mut self, user: User) -> Self {
+        self.users.push(user);
+        self
+    }
+
+    // 有 token 时用 token 认证，否则用用户名和密码
+    pub(crate) fn authenticate(&self, auth: &Auth) -> Result<&User, KvError> {
+        let user = if !auth.token.is_empty() {
+            self.users.iter().find(|u| {
+                u.token
+                    .as_deref()
+                    .is_some_and(|t| secure_eq(t, &auth.token))
+            })
+        } else {
+            self.users.iter().find(|u| {
+                u.name == auth.username
+                    && u.password
+                        .as_deref()
+                        .is_some_and(|p| secure_eq(p, &auth.password))
+            })
+        };
+        user.ok_or_else(|| KvError::Unauthorized("invalid credentials".into()))
+    }
+
+    // 检查 user 能不能执行这个命令，user 为 None 表示连接还没有认证
+    pub(crate) fn check(&self, user: Option<&User>, cmd: &CommandRequest) -> Result<(), KvError> {
+        let Some(user) = user else {
+            return Err(KvError::Unauthorized("authentication required".into()));
+        };
+        for (table, need) in accesses(cmd) {
+            if !user.allows(table, need) {
+                return Err(KvError::PermissionDenied(format!(
+                    "user {} has no {:?} permission on table {}",
+                    user.name, need, table
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+// 模式以 * 结尾时匹配前缀，所以只有 * 这个模式能匹配 *，也就是所有 table
+fn pattern_matches(pattern: &str, table: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => table.starts_with(prefix),
+        None => pattern == table,
+    }
+}
+
+// 命令要访问的 table 和需要的权限
+// 订阅和发布不针对 table，认证过就可以执行
+fn accesses(cmd: &CommandRequest) -> Vec<(&str, Permission)> {
+    let Some(data) = cmd.request_data.as_ref() else {
+        return Vec::new();
+    };
+    let (table, need) = match data {
+        RequestData::Hget(v) => (&v.table, Permission::Read),
+        RequestData::Hgetall(v) => (&v.table, Permission::Read),
+        RequestData::Hmget(v) => (&v.table, Permission::Read),
+        RequestData::Hexist(v) => (&v.table, Permission::Read),
+        RequestData::Hmexist(v) => (&v.table, Permission::Read),
+        RequestData::Httl(v) => (&v.table, Permission::Read),
+        RequestData::Hscan(v) => (&v.table, Permission::Read),
+        RequestData::Hlen(v) => (&v.table, Permission::Read),
+        RequestData::Hset(v) => (&v.table, Permission::Write),
+        RequestData::Hmset(v) => (&v.table, Permission::Write),
+        RequestData::Hdel(v) => (&v.table, Permission::Write),
+        RequestData::Hmdel(v) => (&v.table, Permission::Write),
+        RequestData::Hexpire(v) => (&v.table, Permission::Write),
+        RequestData::Hincrby(v) => (&v.table, Permission::Write),
+        RequestData::Hincrbyfloat(v) => (&v.table, Permission::Write),
+        RequestData::Hcas(v) => (&v.table, Permission::Write),
+        RequestData::Hdrop(v) => (&v.table, Permission::Write),
+        // 列出所有 table 和复制数据都需要能读所有的 table
+        RequestData::Tables(_) | RequestData::Replicate(_) => {
+            return vec![("*", Permission::Read)];
+        }
+        RequestData::Transaction(v) => return v.commands.iter().flat_map(accesses).collect(),
+        RequestData::Subscribe(_)
+        | RequestData::Unsubscribe(_)
+        | RequestData::Publish(_)
+        | RequestData::Auth(_)
+        | RequestData::Negotiate(_) => return Vec::new(),
+    };
+    vec![(table.as_str(), need)]
+}
+
+// 会修改数据的命令
+pub(crate) fn is_write(cmd: &CommandRequest) -> bool {
+    accesses(cmd)
+        .iter()
+        .any(|(_, need)| *need == Permission::Write)
+}
+
+// 比较的时间只和长度有关，不会因为前面的字符匹配得多而变长
+fn secure_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn acl_should_authenticate_with_token_or_password() {
+        let acl = acl();
+        let auth = |token: &str, username: &str, password: &str| Auth {
+            token: token.into(),
+            username: username.into(),
+            password: password.into(),
+        };
+
+        let user = acl.authenticate(&auth("", "alice", "secret")).unwrap();
+        assert_eq!(user.name(), "alice");
+        let user = acl.authenticate(&auth("t0ken", "", "")).unwrap();
+        assert_eq!(user.name(), "bot");
+
+        let err = Some(KvError::Unauthorized("invalid credentials".into()));
+        assert_eq!(acl.authenticate(&auth("", "alice", "wrong")).err(), err);
+        // 没有设置密码的用户不能用密码认证
+        assert_eq!(acl.authenticate(&auth("", "bot", "")).err(), err);
+        assert_eq!(acl.authenticate(&auth("t0ke", "", "")).err(), err);
+    }
+
+    #[test]
+    fn acl_should_check_table_permissions() {
+        let acl = acl();
+        let alice = acl.users[0].clone();
+        let bot = acl.users[1].clone();
+
+        let cmd = CommandRequest::new_hget("orders", "k1");
+        assert!(matches!(
+            acl.check(None, &cmd),
+            Err(KvError::Unauthorized(_))
+        ));
+        assert!(acl.check(Some(&alice), &cmd).is_ok());
+        assert!(acl.check(Some(&bot), &cmd).is_ok());
+
+        // bot 只能读 orders 开头的 table，只能写 metrics
+        let cmd = CommandRequest::new_hset("orders_2024", "k1", "v1".into());
+        assert!(acl.check(Some(&alice), &cmd).is_ok());
+        assert!(matches!(
+            acl.check(Some(&bot), &cmd),
+            Err(KvError::PermissionDenied(_))
+        ));
+        let cmd = CommandRequest::new_hincrby("metrics", "count", 1);
+        assert!(acl.check(Some(&bot), &cmd).is_ok());
+        let cmd = CommandRequest::new_hget("metrics", "count");
+        assert!(acl.check(Some(&bot), &cmd).is_err());
+
+        // 事务里的每个命令都要检查
+        let cmd = CommandRequest::new_transaction(vec![
+            CommandRequest::new_hget("orders", "k1"),
+            CommandRequest::new_hset("users", "k1", "v1".into()),
+        ]);
+        assert!(acl.check(Some(&alice), &cmd).is_ok());
+        assert!(acl.check(Some(&bot), &cmd).is_err());
+
+        let cmd = CommandRequest::new_tables();
+        assert!(acl.check(Some(&alice), &cmd).is_ok());
+        assert!(acl.check(Some(&bot), &cmd).is_err());
+    }
+
+    fn acl() -> Acl {
+        Acl::new()
+            .user(
+                User::new("alice")
+                    .password("secret")
+                    .grant("*", Permission::ReadWrite),
+            )
+            .user(
+                User::new("bot")
+                    .token("t0ken")
+                    .grant("orders*", Permission::Read)
+                    .grant("metrics", Permission::Write),
+            )
+    }
+}
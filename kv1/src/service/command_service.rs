@@ -1,15 +1,39 @@
 use crate::command_request::RequestData;
-use crate::service::notify::{Notify, NotifyMut};
+use crate::metrics;
+use crate::service::auth::is_write;
+use crate::service::notify::{Notify, NotifyAsync, NotifyMut};
+use crate::service::replication::{Leader, snapshot};
+use crate::storage::now_ms;
+use crate::{Acl, Broadcaster, StreamingResponse, User, dispatch_stream};
 #[allow(unused_imports)]
 use crate::{
-    CommandRequest, CommandResponse, CommandService, Hdel, Hexist, Hget, Hgetall, Hmdel, Hmget,
-    Hmset, Hset, KvError, Kvpair, MemTable, Storage, Value,
+    CommandRequest, CommandResponse, CommandService, Hcas, Hdel, Hdrop, Hexist, Hexpire, Hget,
+    Hgetall, Hincrby, Hincrbyfloat, Hlen, Hmdel, Hmexist, Hmget, Hmset, Hscan, Hset, Httl, KvError,
+    Kvpair, MemTable, Storage, Tables, Value,
 };
+use futures::future::BoxFuture;
+use futures::{FutureExt, Stream, StreamExt, stream};
+use std::ops::Bound;
 use std::sync::Arc;
-use tracing::debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+// Hscan 没有指定 limit 时每页返回的个数
+const DEFAULT_SCAN_LIMIT: usize = 100;
+// Hgetall 的结果每个响应里最多放多少个 Kvpair
+pub(crate) const STREAM_CHUNK_SIZE: usize = 256;
+
+// 每个 Session 一个 id，订阅只能被同一个 Session 取消
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+
+fn next_session_id() -> u64 {
+    NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed)
+}
 
 impl CommandService for Hget {
-    fn execute(self, store: &impl Storage) -> CommandResponse {
+    fn execute(self, store: &dyn Storage) -> CommandResponse {
         match store.get(&self.table, &self.key) {
             Ok(Some(v)) => v.into(),
             Ok(None) => KvError::NotFound(self.table, self.key).into(),
@@ -19,7 +43,7 @@ impl CommandService for Hget {
 }
 
 impl CommandService for Hexist {
-    fn execute(self, store: &impl Storage) -> CommandResponse {
+    fn execute(self, store: &dyn Storage) -> CommandResponse {
         match store.contains(&self.table, &self.key) {
             Ok(v) => v.into(),
             Err(e) => e.into(),
@@ -27,8 +51,17 @@ impl CommandService for Hexist {
     }
 }
 
+impl CommandService for Hmexist {
+    fn execute(self, store: &dyn Storage) -> CommandResponse {
+        match store.mcontains(&self.table, &self.keys) {
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
 impl CommandService for Hmget {
-    fn execute(self, store: &impl Storage) -> CommandResponse {
+    fn execute(self, store: &dyn Storage) -> CommandResponse {
         match store.mget(&self.table, &self.keys) {
             Ok(v) => v.into(),
             Err(e) => e.into(),
@@ -37,7 +70,7 @@ impl CommandService for Hmget {
 }
 
 impl CommandService for Hgetall {
-    fn execute(self, store: &impl Storage) -> CommandResponse {
+    fn execute(self, store: &dyn Storage) -> CommandResponse {
         match store.get_all(&self.table) {
             Ok(v) => v.into(),
             Err(e) => e.into(),
@@ -45,30 +78,154 @@ impl CommandService for Hgetall {
     }
 }
 
+impl CommandService for Hscan {
+    fn execute(self, store: &dyn Storage) -> CommandResponse {
+        let limit = match self.limit {
+            0 => DEFAULT_SCAN_LIMIT,
+            n => n as usize,
+        };
+        // cursor 是上一页最后一个 key，这一页从它后面开始
+        let start = match self.cursor.is_empty() || self.cursor < self.start {
+            true => Bound::Included(self.start.as_str()),
+            false => Bound::Excluded(self.cursor.as_str()),
+        };
+        let end = match self.end.is_empty() {
+            true => Bound::Unbounded,
+            false => Bound::Excluded(self.end.as_str()),
+        };
+
+        // 多取一个，用来判断后面还有没有数据
+        let mut pairs = match store.scan(&self.table, &self.prefix, start, end, limit + 1) {
+            Ok(v) => v,
+            Err(e) => return e.into(),
+        };
+        let cursor = match pairs.len() > limit {
+            true => {
+                pairs.truncate(limit);
+                pairs.last().map(|v| v.key.clone())
+            }
+            false => None,
+        };
+        let mut res: CommandResponse = pairs.into();
+        res.values.extend(cursor.map(Value::from));
+        res
+    }
+}
+
+impl CommandService for Tables {
+    fn execute(self, store: &dyn Storage) -> CommandResponse {
+        match store.tables() {
+            Ok(v) => v.into_iter().map(Value::from).collect::<Vec<_>>().into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hdrop {
+    fn execute(self, store: &dyn Storage) -> CommandResponse {
+        match store.drop_table(&self.table) {
+            Ok(true) => true.into(),
+            Ok(false) => KvError::NotFound(self.table, String::new()).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hlen {
+    fn execute(self, store: &dyn Storage) -> CommandResponse {
+        match store.len(&self.table) {
+            Ok(v) => Value::from(v as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
 impl CommandService for Hset {
-    fn execute(self, store: &impl Storage) -> CommandResponse {
-        match self.pair {
-            Some(v) => match store.set(&self.table, v.key, v.value.unwrap_or_default()) {
-                Ok(Some(v)) => v.into(),
-                Ok(None) => Value::default().into(),
-                Err(e) => e.into(),
-            },
-            None => Value::default().into(),
+    fn execute(self, store: &dyn Storage) -> CommandResponse {
+        let Some(v) = self.pair else {
+            return Value::default().into();
+        };
+        let ttl = match (self.deadline, self.ttl) {
+            (0, 0) => None,
+            (0, ttl) => Some(Duration::from_millis(ttl)),
+            // 已经过了 deadline 的也设置上，让它马上过期
+            (deadline, _) => Some(Duration::from_millis(
+                deadline.saturating_sub(now_ms()).max(1),
+            )),
+        };
+        match store.set(&self.table, v.key, v.value.unwrap_or_default(), ttl) {
+            Ok(Some(v)) => v.into(),
+            Ok(None) => Value::default().into(),
+            Err(e) => e.into(),
         }
     }
 }
 
 impl CommandService for Hmset {
-    fn execute(self, store: &impl Storage) -> CommandResponse {
-        match store.mset(&self.table, self.pairs) {
+    fn execute(self, store: &dyn Storage) -> CommandResponse {
+        let ttl = (self.ttl > 0).then(|| Duration::from_millis(self.ttl));
+        match store.mset(&self.table, self.pairs, ttl) {
             Ok(v) => v.into(),
             Err(e) => e.into(),
         }
     }
 }
 
+impl CommandService for Hexpire {
+    fn execute(self, store: &dyn Storage) -> CommandResponse {
+        let ttl = (self.ttl > 0).then(|| Duration::from_millis(self.ttl));
+        match store.expire(&self.table, &self.key, ttl) {
+            Ok(true) => true.into(),
+            Ok(false) => KvError::NotFound(self.table, self.key).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Httl {
+    fn execute(self, store: &dyn Storage) -> CommandResponse {
+        match store.ttl(&self.table, &self.key) {
+            Ok(Some(ttl)) => Value::from(ttl.as_millis() as i64).into(),
+            // 永不过期
+            Ok(None) => Value::from(-1).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hincrby {
+    fn execute(self, store: &dyn Storage) -> CommandResponse {
+        match store.incr(&self.table, &self.key, self.delta) {
+            Ok(v) => Value::from(v).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hincrbyfloat {
+    fn execute(self, store: &dyn Storage) -> CommandResponse {
+        match store.incr_float(&self.table, &self.key, self.delta) {
+            Ok(v) => Value::from(v).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hcas {
+    fn execute(self, store: &dyn Storage) -> CommandResponse {
+        let Some(new_value) = self.new_value else {
+            return KvError::InvalidCommand("Hcas requires a new value".into()).into();
+        };
+        match store.compare_and_swap(&self.table, &self.key, self.expected, new_value) {
+            // 第一个值表示是否替换成功，第二个值是 key 当前的值
+            Ok((swapped, current)) => vec![swapped.into(), current.unwrap_or_default()].into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
 impl CommandService for Hdel {
-    fn execute(self, store: &impl Storage) -> CommandResponse {
+    fn execute(self, store: &dyn Storage) -> CommandResponse {
         match store.del(&self.table, &self.key) {
             Ok(Some(v)) => v.into(),
             Ok(None) => Value::default().into(),
@@ -78,7 +235,7 @@ impl CommandService for Hdel {
 }
 
 impl CommandService for Hmdel {
-    fn execute(self, store: &impl Storage) -> CommandResponse {
+    fn execute(self, store: &dyn Storage) -> CommandResponse {
         match store.mdel(&self.table, &self.keys) {
             Ok(v) => v.into(),
             Err(e) => e.into(),
@@ -87,9 +244,9 @@ impl CommandService for Hmdel {
 }
 
 #[cfg(test)]
+#[allow(clippy::items_after_test_module)]
 mod tests {
     use super::*;
-    use crate::command_request::RequestData;
     use crate::storage::MemTable;
     use crate::{CommandRequest, Kvpair, Value};
 
@@ -157,14 +314,80 @@ mod tests {
         assert_res_ok(res, &[], pairs);
     }
 
-    fn dispatch(cmd: CommandRequest, store: &impl Storage) -> CommandResponse {
-        match cmd.request_data.unwrap() {
-            RequestData::Hget(v) => v.execute(store),
-            RequestData::Hgetall(v) => v.execute(store),
-            RequestData::Hset(v) => v.execute(store),
-            RequestData::Hmget(v) => v.execute(store),
-            _ => todo!(),
-        }
+    #[test]
+    fn h_m_exist_should_work() {
+        let store = MemTable::new();
+        dispatch(CommandRequest::new_hset("score", "u1", 10.into()), &store);
+        dispatch(CommandRequest::new_hset("score", "u3", 30.into()), &store);
+
+        let cmd = CommandRequest::new_hmexist("score", ["u1", "u2", "u3"]);
+        let res = dispatch(cmd, &store);
+        let pairs = &[
+            Kvpair::new("u1", true.into()),
+            Kvpair::new("u2", false.into()),
+            Kvpair::new("u3", true.into()),
+        ];
+        assert_res_ok(res, &[], pairs);
+    }
+
+    #[test]
+    fn h_incr_by_should_work() {
+        let store = MemTable::new();
+        let res = dispatch(CommandRequest::new_hincrby("score", "u1", 10), &store);
+        assert_res_ok(res, &[10.into()], &[]);
+        let res = dispatch(CommandRequest::new_hincrby("score", "u1", -3), &store);
+        assert_res_ok(res, &[7.into()], &[]);
+        let res = dispatch(CommandRequest::new_hincrbyfloat("score", "u1", 0.5), &store);
+        assert_res_ok(res, &[7.5.into()], &[]);
+
+        dispatch(
+            CommandRequest::new_hset("score", "u2", "ten".into()),
+            &store,
+        );
+        let res = dispatch(CommandRequest::new_hincrby("score", "u2", 1), &store);
+        assert_res_error(res, 500, "Cannot convert value");
+    }
+
+    #[test]
+    fn h_cas_should_work() {
+        let store = MemTable::new();
+        let cmd = CommandRequest::new_hcas("t1", "k1", None, "v1".into());
+        let res = dispatch(cmd.clone(), &store);
+        assert_res_ok(res, &[true.into(), "v1".into()], &[]);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[false.into(), "v1".into()], &[]);
+
+        let cmd = CommandRequest::new_hcas("t1", "k1", Some("v1".into()), "v2".into());
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[true.into(), "v2".into()], &[]);
+
+        let cmd = CommandRequest::new_hcas("t1", "k2", Some("v1".into()), "v2".into());
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[false.into(), Value::default()], &[]);
+    }
+
+    #[test]
+    fn table_commands_should_work() {
+        let store = MemTable::new();
+        dispatch(CommandRequest::new_hset("t1", "k1", 1.into()), &store);
+        dispatch(CommandRequest::new_hset("t1", "k2", 2.into()), &store);
+        dispatch(CommandRequest::new_hset("t2", "k1", 1.into()), &store);
+        // 读不存在的 table 不会创建它
+        dispatch(CommandRequest::new_hget("t3", "k1"), &store);
+
+        let res = dispatch(CommandRequest::new_tables(), &store);
+        assert_res_ok(res, &["t1".into(), "t2".into()], &[]);
+        let res = dispatch(CommandRequest::new_hlen("t1"), &store);
+        assert_res_ok(res, &[2.into()], &[]);
+
+        let res = dispatch(CommandRequest::new_hdrop("t1"), &store);
+        assert_res_ok(res, &[], &[]);
+        let res = dispatch(CommandRequest::new_hdrop("t1"), &store);
+        assert_res_error(res, 404, "Not found");
+        let res = dispatch(CommandRequest::new_hlen("t1"), &store);
+        assert_res_ok(res, &[0.into()], &[]);
+        let res = dispatch(CommandRequest::new_tables(), &store);
+        assert_res_ok(res, &["t2".into()], &[]);
     }
 }
 
@@ -180,42 +403,124 @@ impl<Store> Clone for Service<Store> {
     }
 }
 
+// hook 可以是闭包，这样就能带上自己的状态，比如 metrics 或者审计日志
+type Hook<Arg> = Box<dyn Fn(&Arg) + Send + Sync>;
+type HookMut<Arg> = Box<dyn Fn(&mut Arg) + Send + Sync>;
+// 异步的 hook 拿到的是参数的一份拷贝，返回的 future 会被依次 await
+type AsyncHook<Arg> = Box<dyn Fn(Arg) -> BoxFuture<'static, ()> + Send + Sync>;
+// 在 dispatch 之前检查请求，返回 Err 时拒绝执行，这个 Err 会作为响应返回
+type Guard = Box<dyn Fn(&CommandRequest) -> Result<(), KvError> + Send + Sync>;
+
 pub struct ServiceInner<Store> {
     store: Store,
-    on_received: Vec<fn(&CommandRequest)>,
-    on_executed: Vec<fn(&CommandResponse)>,
-    on_before_send: Vec<fn(&mut CommandResponse)>,
-    on_after_send: Vec<fn()>,
+    broadcaster: Arc<Broadcaster>,
+    on_received: Vec<Hook<CommandRequest>>,
+    on_received_async: Vec<AsyncHook<CommandRequest>>,
+    on_before_dispatch: Vec<Guard>,
+    on_executed: Vec<Hook<CommandResponse>>,
+    on_executed_async: Vec<AsyncHook<CommandResponse>>,
+    on_before_send: Vec<HookMut<CommandResponse>>,
+    on_after_send: Vec<Box<dyn Fn() + Send + Sync>>,
+    acl: Option<Acl>,
+    leader: Option<Leader>,
+    read_only: bool,
+    table_metrics: usize,
 }
 
 impl<Store: Storage> ServiceInner<Store> {
     pub fn new(st: Store) -> Self {
         Self {
             store: st,
+            broadcaster: Default::default(),
             on_received: Vec::new(),
+            on_received_async: Vec::new(),
+            on_before_dispatch: Vec::new(),
             on_executed: Vec::new(),
+            on_executed_async: Vec::new(),
             on_before_send: Vec::new(),
             on_after_send: Vec::new(),
+            acl: None,
+            leader: None,
+            read_only: false,
+            table_metrics: 0,
         }
     }
 
-    pub fn fn_received(mut self, f: fn(&CommandRequest)) -> Self {
-        self.on_received.push(f);
+    // 作为 leader，允许 follower 通过 Replicate 复制数据
+    // 所有的写命令会在同一个锁里执行，保证 follower 执行的顺序和 leader 一样
+    pub fn leader(mut self) -> Self {
+        self.leader = Some(Leader::default());
+        self
+    }
+
+    // follower 的数据只能通过复制修改，客户端的写命令都会被拒绝
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    // 导出指标时，给前 n 个 table 导出 kv_keys
+    // 统计 key 的个数要扫描整个 table，table 多的时候标签也会很多，所以默认不导出
+    pub fn table_metrics(mut self, n: usize) -> Self {
+        self.table_metrics = n;
+        self
+    }
+
+    // 配置了 ACL 之后，连接要先执行 Auth 才能执行其它命令
+    pub fn acl(mut self, acl: Acl) -> Self {
+        self.acl = Some(acl);
+        self
+    }
+
+    pub fn fn_received(mut self, f: impl Fn(&CommandRequest) + Send + Sync + 'static) -> Self {
+        self.on_received.push(Box::new(f));
+        self
+    }
+
+    pub fn fn_received_async<F, Fut>(mut self, f: F) -> Self
+    where
+        F: Fn(CommandRequest) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.on_received_async
+            .push(Box::new(move |arg| f(arg).boxed()));
+        self
+    }
+
+    pub fn fn_before_dispatch(
+        mut self,
+        f: impl Fn(&CommandRequest) -> Result<(), KvError> + Send + Sync + 'static,
+    ) -> Self {
+        self.on_before_dispatch.push(Box::new(f));
         self
     }
 
-    pub fn fn_executed(mut self, f: fn(&CommandResponse)) -> Self {
-        self.on_executed.push(f);
+    pub fn fn_executed(mut self, f: impl Fn(&CommandResponse) + Send + Sync + 'static) -> Self {
+        self.on_executed.push(Box::new(f));
         self
     }
 
-    pub fn fn_before_send(mut self, f: fn(&mut CommandResponse)) -> Self {
-        self.on_before_send.push(f);
+    pub fn fn_executed_async<F, Fut>(mut self, f: F) -> Self
+    where
+        F: Fn(CommandResponse) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.on_executed_async
+            .push(Box::new(move |arg| f(arg).boxed()));
         self
     }
 
-    pub fn fn_after_send(mut self, f: fn()) -> Self {
-        self.on_after_send.push(f);
+    pub fn fn_before_send(
+        mut self,
+        f: impl Fn(&mut CommandResponse) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_before_send.push(Box::new(f));
+        self
+    }
+
+    // 响应写到连接之后才会调用，由网络层触发
+    pub fn fn_after_send(mut self, f: impl Fn() + Send + Sync + 'static) -> Self {
+        self.on_after_send.push(Box::new(f));
         self
     }
 }
@@ -229,22 +534,227 @@ impl<Store: Storage> From<ServiceInner<Store>> for Service<Store> {
 }
 
 impl<Store: Storage> Service<Store> {
-    pub fn execute(&self, cmd: CommandRequest) -> CommandResponse {
+    // 一个请求可能有多个响应：订阅会不断推送数据，Hgetall 的结果会分成多个响应
+    // 这里没有连接的认证状态，配置了 ACL 时要通过 Session 执行
+    // 每个请求都当作一个新的连接，这里订阅的主题只能通过 drop 取消
+    pub fn execute(&self, cmd: CommandRequest) -> StreamingResponse {
+        self.execute_as(cmd, None, next_session_id())
+    }
+
+    // 每个连接一个 Session，记录这个连接认证过的用户
+    pub fn session(&self) -> Session<Store> {
+        Session {
+            service: self.clone(),
+            user: None,
+            id: next_session_id(),
+        }
+    }
+
+    // Prometheus 文本格式的指标
+    pub fn metrics(&self) -> Result<String, KvError> {
+        metrics::encode(&self.inner.store, self.inner.table_metrics)
+    }
+
+    fn execute_as(
+        &self,
+        cmd: CommandRequest,
+        user: Option<&User>,
+        owner: u64,
+    ) -> StreamingResponse {
         debug!("Got request: {:?}", cmd);
+        let mut finished = Some(metrics::command_started(&cmd));
         self.inner.on_received.notify(&cmd);
-        let mut res = dispatch(cmd, &self.inner.store);
-        debug!("Executed response: {:?}", res);
-        self.inner.on_executed.notify(&res);
-        self.inner.on_before_send.notify(&mut res);
-        if !self.inner.on_before_send.is_empty() {
-            debug!("Modified response: {:?}", res);
+        let responses = if self.inner.on_received_async.is_empty() {
+            self.dispatch(cmd, user, owner)
+        } else {
+            // 有异步的 hook 时，等它们都执行完再执行命令
+            let service = self.clone();
+            let user = user.cloned();
+            Box::pin(
+                stream::once(async move {
+                    service.inner.on_received_async.notify(&cmd).await;
+                    service.dispatch(cmd, user.as_ref(), owner)
+                })
+                .flatten(),
+            )
+        };
+        Box::pin(responses.inspect(move |res| {
+            metrics::record_response(res);
+            if let Some(f) = finished.take() {
+                f()
+            }
+        }))
+    }
+
+    // 每个响应写到连接之后，网络层要调用这个方法
+    pub fn notify_sent(&self) {
+        for f in &self.inner.on_after_send {
+            f()
         }
+    }
 
-        res
+    fn dispatch(&self, cmd: CommandRequest, user: Option<&User>, owner: u64) -> StreamingResponse {
+        let checked = match &self.inner.acl {
+            Some(acl) => acl.check(user, &cmd),
+            None => Ok(()),
+        }
+        .and_then(|_| match self.inner.read_only && is_write(&cmd) {
+            true => Err(KvError::ReadOnly),
+            false => Ok(()),
+        });
+        if let Err(e) = checked.and_then(|_| {
+            self.inner
+                .on_before_dispatch
+                .iter()
+                .try_for_each(|f| f(&cmd))
+        }) {
+            warn!("Request {:?} is rejected: {:?}", cmd, e);
+            return self.respond(stream::once(async move { e.into() }));
+        }
+        match cmd.request_data {
+            Some(
+                RequestData::Subscribe(_) | RequestData::Unsubscribe(_) | RequestData::Publish(_),
+            ) => dispatch_stream(cmd, Arc::clone(&self.inner.broadcaster), owner),
+            Some(RequestData::Hgetall(param)) => self.respond(hgetall_stream(param, self.clone())),
+            Some(RequestData::Replicate(_)) => match &self.inner.leader {
+                Some(leader) => {
+                    let service = self.clone();
+                    leader.replicate(move || snapshot(&service.inner.store))
+                }
+                None => {
+                    let res = KvError::InvalidCommand("Replication is not enabled".into()).into();
+                    self.respond(stream::once(async move { res }))
+                }
+            },
+            _ => {
+                let res = match &self.inner.leader {
+                    Some(leader) if is_write(&cmd) => leader.execute(cmd, &self.inner.store),
+                    _ => dispatch(cmd, &self.inner.store),
+                };
+                self.respond(stream::once(async move { res }))
+            }
+        }
+    }
+
+    // 每个响应都要经过 executed 和 before_send 的 hook
+    fn respond(
+        &self,
+        responses: impl Stream<Item = CommandResponse> + Send + 'static,
+    ) -> StreamingResponse {
+        let service = self.clone();
+        Box::pin(responses.then(move |mut res| {
+            let service = service.clone();
+            async move {
+                debug!("Executed response: {:?}", res);
+                service.inner.on_executed.notify(&res);
+                service.inner.on_executed_async.notify(&res).await;
+                service.inner.on_before_send.notify(&mut res);
+                if !service.inner.on_before_send.is_empty() {
+                    debug!("Modified response: {:?}", res);
+                }
+                Arc::new(res)
+            }
+        }))
+    }
+
+    // 后台定期清理已经过期的 key，读的时候也会惰性删除
+    pub fn spawn_expiration_sweeper(&self, interval: Duration) -> JoinHandle<()> {
+        let service = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                match service.inner.store.purge_expired() {
+                    Ok(0) => {}
+                    Ok(n) => debug!("Purged {} expired keys", n),
+                    Err(e) => warn!("Failed to purge expired keys: {:?}", e),
+                }
+            }
+        })
+    }
+}
+
+pub struct Session<Store = MemTable> {
+    service: Service<Store>,
+    user: Option<User>,
+    id: u64,
+}
+
+impl<Store> Clone for Session<Store> {
+    fn clone(&self) -> Self {
+        Self {
+            service: self.service.clone(),
+            user: self.user.clone(),
+            id: self.id,
+        }
+    }
+}
+
+impl<Store: Storage> Session<Store> {
+    pub fn execute(&mut self, cmd: CommandRequest) -> StreamingResponse {
+        let Some(RequestData::Auth(auth)) = &cmd.request_data else {
+            return self.service.execute_as(cmd, self.user.as_ref(), self.id);
+        };
+        // Auth 里有密码，不经过 received 的 hook
+        let res = match &self.service.inner.acl {
+            Some(acl) => acl.authenticate(auth).map(|user| {
+                info!("Connection is authenticated as {}", user.name());
+                self.user = Some(user.clone());
+            }),
+            None => Err(KvError::InvalidCommand(
+                "Authentication is not enabled".into(),
+            )),
+        };
+        let res = match res {
+            Ok(()) => true.into(),
+            Err(e) => e.into(),
+        };
+        metrics::record_response(&res);
+        self.service.respond(stream::once(async move { res }))
+    }
+
+    pub fn user(&self) -> Option<&User> {
+        self.user.as_ref()
     }
 }
 
-impl<Arg> Notify<Arg> for Vec<fn(&Arg)> {
+// 把 table 里的数据分成多个响应，每次按有序索引只读一页，不用把整个 table 读进内存
+// 页与页之间不是同一个快照，期间写入的 key 可能读到也可能读不到
+fn hgetall_stream<Store: Storage>(
+    param: Hgetall,
+    service: Service<Store>,
+) -> impl Stream<Item = CommandResponse> + Send + 'static {
+    stream::unfold(Some(Bound::Unbounded), move |start| {
+        let service = service.clone();
+        let table = param.table.clone();
+        async move {
+            let start: Bound<String> = start?;
+            // 多取一个，用来判断后面还有没有数据
+            let store = &service.inner.store;
+            let mut pairs = match store.scan(
+                &table,
+                "",
+                start.as_ref().map(String::as_str),
+                Bound::Unbounded,
+                STREAM_CHUNK_SIZE + 1,
+            ) {
+                Ok(v) => v,
+                Err(e) => return Some((e.into(), None)),
+            };
+            let more = pairs.len() > STREAM_CHUNK_SIZE;
+            pairs.truncate(STREAM_CHUNK_SIZE);
+            let next = match more {
+                true => pairs.last().map(|v| Bound::Excluded(v.key.clone())),
+                false => None,
+            };
+            let mut res: CommandResponse = pairs.into();
+            res.more = more;
+            Some((res, next))
+        }
+    })
+}
+
+impl<Arg> Notify<Arg> for Vec<Hook<Arg>> {
     #[inline]
     fn notify(&self, arg: &Arg) {
         for f in self {
@@ -253,7 +763,7 @@ impl<Arg> Notify<Arg> for Vec<fn(&Arg)> {
     }
 }
 
-impl<Arg> NotifyMut<Arg> for Vec<fn(&mut Arg)> {
+impl<Arg> NotifyMut<Arg> for Vec<HookMut<Arg>> {
     #[inline]
     fn notify(&self, arg: &mut Arg) {
         for f in self {
@@ -262,18 +772,51 @@ impl<Arg> NotifyMut<Arg> for Vec<fn(&mut Arg)> {
     }
 }
 
-pub fn dispatch(cmd: CommandRequest, store: &impl Storage) -> CommandResponse {
+impl<Arg: Clone> NotifyAsync<Arg> for Vec<AsyncHook<Arg>> {
+    fn notify(&self, arg: &Arg) -> impl Future<Output = ()> + Send {
+        let futures: Vec<_> = self.iter().map(|f| f(arg.clone())).collect();
+        async move {
+            for fut in futures {
+                fut.await
+            }
+        }
+    }
+}
+
+pub fn dispatch(cmd: CommandRequest, store: &dyn Storage) -> CommandResponse {
     match cmd.request_data {
         Some(RequestData::Hget(param)) => param.execute(store),
         Some(RequestData::Hexist(param)) => param.execute(store),
+        Some(RequestData::Hmexist(param)) => param.execute(store),
         Some(RequestData::Hmget(param)) => param.execute(store),
         Some(RequestData::Hgetall(param)) => param.execute(store),
+        Some(RequestData::Hscan(param)) => param.execute(store),
+        Some(RequestData::Tables(param)) => param.execute(store),
+        Some(RequestData::Hdrop(param)) => param.execute(store),
+        Some(RequestData::Hlen(param)) => param.execute(store),
         Some(RequestData::Hset(param)) => param.execute(store),
         Some(RequestData::Hmset(param)) => param.execute(store),
         Some(RequestData::Hdel(param)) => param.execute(store),
         Some(RequestData::Hmdel(param)) => param.execute(store),
+        Some(RequestData::Hexpire(param)) => param.execute(store),
+        Some(RequestData::Httl(param)) => param.execute(store),
+        Some(RequestData::Hincrby(param)) => param.execute(store),
+        Some(RequestData::Hincrbyfloat(param)) => param.execute(store),
+        Some(RequestData::Hcas(param)) => param.execute(store),
+        Some(RequestData::Transaction(param)) => param.execute(store),
+        Some(RequestData::Auth(_)) => {
+            KvError::InvalidCommand("Auth must be executed on a connection".into()).into()
+        }
+        Some(RequestData::Replicate(_)) => {
+            KvError::InvalidCommand("Replicate must be executed as a stream".into()).into()
+        }
+        Some(RequestData::Negotiate(_)) => {
+            KvError::InvalidCommand("Negotiate must be executed on a connection".into()).into()
+        }
+        Some(RequestData::Subscribe(_) | RequestData::Unsubscribe(_) | RequestData::Publish(_)) => {
+            KvError::InvalidCommand("Topic command must be executed as a stream".into()).into()
+        }
         None => KvError::InvalidCommand("Request has no data".into()).into(),
-        _ => KvError::Internal("Not implemented".into()).into(),
     }
 }
 
@@ -1,20 +1,36 @@
+mod auth;
 mod command_service;
 mod notify;
+mod replication;
+mod topic;
+mod topic_service;
+mod transaction_service;
 
 use crate::*;
-pub use command_service::{Service, ServiceInner};
+pub use auth::{Acl, Permission, User};
+pub(crate) use command_service::dispatch;
+pub use command_service::{Service, ServiceInner, Session};
+#[cfg(test)]
+pub use command_service::{assert_res_error, assert_res_ok};
+pub use topic::{Broadcaster, Subscription, Topic};
+pub use topic_service::{StreamingResponse, TopicService, dispatch_stream};
+pub use transaction_service::{TransactionService, dispatch_tx};
 
 pub trait CommandService {
-    fn execute(self, store: &impl Storage) -> CommandResponse;
+    fn execute(self, store: &dyn Storage) -> CommandResponse;
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::service::command_service::*;
-    use crate::{MemTable, Value};
+    use crate::command_request::RequestData;
+    use crate::{MemTable, SledDb, Value};
+    use futures::executor::block_on_stream;
     use http::StatusCode;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
     use std::thread::spawn;
+    use std::time::Duration;
     use tracing::info;
 
     #[test]
@@ -24,19 +40,19 @@ mod tests {
         let cloned = service.clone();
 
         let handle = spawn(move || {
-            let res = cloned.execute(CommandRequest::new_hset("t1", "k1", "v1".into()));
+            let res = execute(&cloned, CommandRequest::new_hset("t1", "k1", "v1".into()));
             assert_res_ok(res, &[Value::default()], &[]);
         });
         handle.join().unwrap();
 
-        let res = service.execute(CommandRequest::new_hget("t1", "k1"));
+        let res = execute(&service, CommandRequest::new_hget("t1", "k1"));
         assert_res_ok(res, &["v1".into()], &[]);
 
-        let res = service.execute(CommandRequest::new_hexist("t1", "k1"));
+        let res = execute(&service, CommandRequest::new_hexist("t1", "k1"));
         assert_res_ok(res, &[], &[]);
 
-        let res = service.execute(CommandRequest::new_hexist("t1", "k2"));
-        assert_res_error(res, 500, "Not Found");
+        let res = execute(&service, CommandRequest::new_hexist("t1", "k2"));
+        assert_res_error(res, 404, "Not Found");
     }
 
     #[test]
@@ -46,16 +62,19 @@ mod tests {
         let cloned = service.clone();
 
         let handle = spawn(move || {
-            let res = cloned.execute(CommandRequest::new_hset("t1", "k1", "v1".into()));
+            let res = execute(&cloned, CommandRequest::new_hset("t1", "k1", "v1".into()));
             assert_res_ok(res, &[Value::default()], &[]);
-            let res = cloned.execute(CommandRequest::new_hset("t1", "k2", "v2".into()));
+            let res = execute(&cloned, CommandRequest::new_hset("t1", "k2", "v2".into()));
             assert_res_ok(res, &[Value::default()], &[]);
-            let res = cloned.execute(CommandRequest::new_hset("t1", "k3", "v3".into()));
+            let res = execute(&cloned, CommandRequest::new_hset("t1", "k3", "v3".into()));
             assert_res_ok(res, &[Value::default()], &[]);
         });
         handle.join().unwrap();
 
-        let res = service.execute(CommandRequest::new_hmget("t1", ["k1", "k2", "k3"]));
+        let res = execute(
+            &service,
+            CommandRequest::new_hmget("t1", ["k1", "k2", "k3"]),
+        );
         assert_res_ok(
             res,
             &[],
@@ -74,19 +93,25 @@ mod tests {
         let cloned = service.clone();
 
         let handle = spawn(move || {
-            let res = cloned.execute(CommandRequest::new_hmset(
-                "t1",
-                vec![
-                    Kvpair::new("k1", "v1".into()),
-                    Kvpair::new("k2", "v2".into()),
-                    Kvpair::new("k3", "v3".into()),
-                ],
-            ));
+            let res = execute(
+                &cloned,
+                CommandRequest::new_hmset(
+                    "t1",
+                    vec![
+                        Kvpair::new("k1", "v1".into()),
+                        Kvpair::new("k2", "v2".into()),
+                        Kvpair::new("k3", "v3".into()),
+                    ],
+                ),
+            );
             assert_res_ok(res, &[], &[]);
         });
         handle.join().unwrap();
 
-        let res = service.execute(CommandRequest::new_hmget("t1", ["k1", "k2", "k3"]));
+        let res = execute(
+            &service,
+            CommandRequest::new_hmget("t1", ["k1", "k2", "k3"]),
+        );
         assert_res_ok(
             res,
             &[],
@@ -105,22 +130,28 @@ mod tests {
         let cloned = service.clone();
 
         let handle = spawn(move || {
-            let res = cloned.execute(CommandRequest::new_hmset(
-                "t2",
-                vec![
-                    Kvpair::new("k1", "v1".into()),
-                    Kvpair::new("k2", "v2".into()),
-                    Kvpair::new("k3", "v3".into()),
-                ],
-            ));
+            let res = execute(
+                &cloned,
+                CommandRequest::new_hmset(
+                    "t2",
+                    vec![
+                        Kvpair::new("k1", "v1".into()),
+                        Kvpair::new("k2", "v2".into()),
+                        Kvpair::new("k3", "v3".into()),
+                    ],
+                ),
+            );
             assert_res_ok(res, &[], &[]);
         });
         handle.join().unwrap();
 
-        let res = service.execute(CommandRequest::new_hdel("t2", "k1"));
+        let res = execute(&service, CommandRequest::new_hdel("t2", "k1"));
         assert_res_ok(res, &["v1".into()], &[]);
 
-        let res = service.execute(CommandRequest::new_hmget("t2", ["k1", "k2", "k3"]));
+        let res = execute(
+            &service,
+            CommandRequest::new_hmget("t2", ["k1", "k2", "k3"]),
+        );
         assert_res_ok(
             res,
             &[],
@@ -138,32 +169,35 @@ mod tests {
         let cloned = service.clone();
 
         let handle = spawn(move || {
-            let res = cloned.execute(CommandRequest::new_hmset(
-                "t2",
-                vec![
-                    Kvpair::new("k1", "v1".into()),
-                    Kvpair::new("k2", "v2".into()),
-                    Kvpair::new("k3", "v3".into()),
-                    Kvpair::new("k4", "v4".into()),
-                    Kvpair::new("k5", "v5".into()),
-                    Kvpair::new("k6", "v6".into()),
-                ],
-            ));
+            let res = execute(
+                &cloned,
+                CommandRequest::new_hmset(
+                    "t2",
+                    vec![
+                        Kvpair::new("k1", "v1".into()),
+                        Kvpair::new("k2", "v2".into()),
+                        Kvpair::new("k3", "v3".into()),
+                        Kvpair::new("k4", "v4".into()),
+                        Kvpair::new("k5", "v5".into()),
+                        Kvpair::new("k6", "v6".into()),
+                    ],
+                ),
+            );
             assert_res_ok(res, &[], &[]);
         });
         handle.join().unwrap();
 
         let cloned = service.clone();
         let handle = spawn(move || {
-            let res = cloned.execute(CommandRequest::new_hmdel("t2", ["k1", "k2", "k3"]));
+            let res = execute(&cloned, CommandRequest::new_hmdel("t2", ["k1", "k2", "k3"]));
             assert_res_ok(res, &[], &[]);
         });
         handle.join().unwrap();
 
-        let res = service.execute(CommandRequest::new_hmget(
-            "t2",
-            ["k1", "k2", "k3", "k4", "k5", "k6"],
-        ));
+        let res = execute(
+            &service,
+            CommandRequest::new_hmget("t2", ["k1", "k2", "k3", "k4", "k5", "k6"]),
+        );
         assert_res_ok(
             res,
             &[],
@@ -175,6 +209,229 @@ mod tests {
         )
     }
 
+    #[test]
+    fn service_with_sleddb_h_m_commands_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let service: Service<SledDb> = ServiceInner::new(SledDb::new(dir)).into();
+
+        let res = execute(
+            &service,
+            CommandRequest::new_hmset(
+                "t1",
+                vec![
+                    Kvpair::new("k1", "v1".into()),
+                    Kvpair::new("k2", "v2".into()),
+                    Kvpair::new("k3", "v3".into()),
+                ],
+            ),
+        );
+        assert_res_ok(res, &[], &[]);
+
+        let res = execute(
+            &service,
+            CommandRequest::new_hmdel("t1", ["k1", "k3", "k4"]),
+        );
+        assert_res_ok(res, &[], &[]);
+
+        let res = execute(
+            &service,
+            CommandRequest::new_hmget("t1", ["k1", "k2", "k3"]),
+        );
+        assert_res_ok(res, &[], &[Kvpair::new("k2", "v2".into())]);
+    }
+
+    #[test]
+    fn service_hgetall_should_return_chunks() {
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        let n = command_service::STREAM_CHUNK_SIZE * 2 + 10;
+        let pairs = (0..n)
+            .map(|i| Kvpair::new(format!("k{}", i), (i as i64).into()))
+            .collect();
+        execute(&service, CommandRequest::new_hmset("t1", pairs));
+
+        let responses: Vec<_> =
+            block_on_stream(service.execute(CommandRequest::new_hgetall("t1"))).collect();
+        let more: Vec<_> = responses.iter().map(|res| res.more).collect();
+        assert_eq!(more, [true, true, false]);
+        let mut keys: Vec<_> = responses
+            .iter()
+            .flat_map(|res| res.pairs.iter().map(|pair| pair.key.clone()))
+            .collect();
+        keys.sort();
+        keys.dedup();
+        assert_eq!(keys.len(), n);
+
+        // 每次只读一页，读完第一页后写入的 key 在后面的页里能读到
+        let mut stream = block_on_stream(service.execute(CommandRequest::new_hgetall("t1")));
+        assert!(stream.next().unwrap().more);
+        execute(&service, CommandRequest::new_hset("t1", "zz", 1.into()));
+        let count: usize = stream.map(|res| res.pairs.len()).sum();
+        assert_eq!(count + command_service::STREAM_CHUNK_SIZE, n + 1);
+
+        // 正好是整页时，最后一页的 more 是 false
+        let pairs = (0..command_service::STREAM_CHUNK_SIZE)
+            .map(|i| Kvpair::new(format!("k{}", i), (i as i64).into()))
+            .collect();
+        execute(&service, CommandRequest::new_hmset("t3", pairs));
+        let responses: Vec<_> =
+            block_on_stream(service.execute(CommandRequest::new_hgetall("t3"))).collect();
+        let more: Vec<_> = responses.iter().map(|res| res.more).collect();
+        assert_eq!(more, [false]);
+
+        // 不存在的 table 只返回一个空的响应
+        let res = execute(&service, CommandRequest::new_hgetall("t2"));
+        assert_res_ok(res, &[], &[]);
+    }
+
+    #[test]
+    fn service_h_scan_should_work() {
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        test_h_scan(service);
+
+        let dir = tempfile::tempdir().unwrap();
+        let service: Service<SledDb> = ServiceInner::new(SledDb::new(dir)).into();
+        test_h_scan(service);
+    }
+
+    #[test]
+    fn service_h_m_exist_should_work() {
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        test_h_m_exist(service);
+
+        let dir = tempfile::tempdir().unwrap();
+        let service: Service<SledDb> = ServiceInner::new(SledDb::new(dir)).into();
+        test_h_m_exist(service);
+    }
+
+    #[test]
+    fn service_with_dyn_storage_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        // 模拟从配置里读到 backend 的名字，运行时才决定用哪个 Storage
+        for backend in ["memory", "sled"] {
+            let store: Arc<dyn Storage> = match backend {
+                "memory" => Arc::new(MemTable::new()),
+                _ => Arc::new(SledDb::new(dir.path())),
+            };
+            let service: Service<Arc<dyn Storage>> = ServiceInner::new(store).into();
+            test_h_m_exist(service);
+        }
+    }
+
+    fn test_h_scan<Store: Storage>(service: Service<Store>) {
+        for i in 0..10i64 {
+            execute(
+                &service,
+                CommandRequest::new_hset("t1", format!("k{}", i), i.into()),
+            );
+        }
+        execute(&service, CommandRequest::new_hset("t1", "other", 0.into()));
+
+        // 每页 4 个，用 cursor 一直翻到最后一页
+        let mut cmd = CommandRequest::new_hscan("t1", "k", 4);
+        let mut keys = Vec::new();
+        let mut pages = 0;
+        loop {
+            let res = execute(&service, cmd.clone());
+            assert_eq!(res.status, 200);
+            keys.extend(res.pairs.into_iter().map(|v| v.key));
+            pages += 1;
+            match res.values.into_iter().next() {
+                Some(cursor) => cmd = cmd.with_cursor(String::try_from(cursor).unwrap()),
+                None => break,
+            }
+        }
+        assert_eq!(pages, 3);
+        let expected: Vec<_> = (0..10).map(|i| format!("k{}", i)).collect();
+        assert_eq!(keys, expected);
+
+        let res = execute(
+            &service,
+            CommandRequest::new_hscan_range("t1", "k3", "k5", 0),
+        );
+        assert_res_ok(
+            res,
+            &[],
+            &[Kvpair::new("k3", 3.into()), Kvpair::new("k4", 4.into())],
+        );
+    }
+
+    fn test_h_m_exist<Store: Storage>(service: Service<Store>) {
+        let res = execute(
+            &service,
+            CommandRequest::new_hmset(
+                "t1",
+                vec![
+                    Kvpair::new("k1", "v1".into()),
+                    Kvpair::new("k2", "v2".into()),
+                ],
+            ),
+        );
+        assert_res_ok(res, &[], &[]);
+
+        let res = execute(
+            &service,
+            CommandRequest::new_hmexist("t1", ["k1", "k3", "k2"]),
+        );
+        assert_res_ok(
+            res,
+            &[],
+            &[
+                Kvpair::new("k1", true.into()),
+                Kvpair::new("k2", true.into()),
+                Kvpair::new("k3", false.into()),
+            ],
+        );
+    }
+
+    #[test]
+    fn service_ttl_should_work() {
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        test_ttl(service);
+
+        let dir = tempfile::tempdir().unwrap();
+        let service: Service<SledDb> = ServiceInner::new(SledDb::new(dir)).into();
+        test_ttl(service);
+    }
+
+    fn test_ttl<Store: Storage>(service: Service<Store>) {
+        let ttl = Duration::from_millis(50);
+        let res = execute(
+            &service,
+            CommandRequest::new_hset_with_ttl("t1", "k1", "v1".into(), ttl),
+        );
+        assert_res_ok(res, &[Value::default()], &[]);
+        let res = execute(
+            &service,
+            CommandRequest::new_hmset_with_ttl("t1", vec![Kvpair::new("k2", "v2".into())], ttl),
+        );
+        assert_res_ok(res, &[], &[]);
+        let res = execute(&service, CommandRequest::new_hset("t1", "k3", "v3".into()));
+        assert_res_ok(res, &[Value::default()], &[]);
+
+        let res = execute(&service, CommandRequest::new_httl("t1", "k1"));
+        let remaining: i64 = res.values[0].clone().try_into().unwrap();
+        assert!(remaining > 0 && remaining <= 50);
+        let res = execute(&service, CommandRequest::new_httl("t1", "k3"));
+        assert_res_ok(res, &[(-1).into()], &[]);
+
+        // k2 取消过期，k3 设置过期
+        let res = execute(&service, CommandRequest::new_hexpire("t1", "k2", None));
+        assert_res_ok(res, &[], &[]);
+        let res = execute(&service, CommandRequest::new_hexpire("t1", "k3", Some(ttl)));
+        assert_res_ok(res, &[], &[]);
+        let res = execute(&service, CommandRequest::new_hexpire("t1", "k4", Some(ttl)));
+        assert_res_error(res, 404, "Not found");
+
+        std::thread::sleep(Duration::from_millis(80));
+        let res = execute(
+            &service,
+            CommandRequest::new_hmget("t1", ["k1", "k2", "k3"]),
+        );
+        assert_res_ok(res, &[], &[Kvpair::new("k2", "v2".into())]);
+        let res = execute(&service, CommandRequest::new_httl("t1", "k1"));
+        assert_res_error(res, 404, "Not found");
+    }
+
     #[test]
     fn event_registration_should_work() {
         fn b(cmd: &CommandRequest) {
@@ -201,9 +458,144 @@ mod tests {
             .fn_after_send(e)
             .into();
 
-        let res = service.execute(CommandRequest::new_hset("t1", "k1", "v1".into()));
-        assert_eq!(res.status, StatusCode::CREATED.as_u16() as _);
+        let res = execute(&service, CommandRequest::new_hset("t1", "k1", "v1".into()));
+        assert_eq!(res.status, StatusCode::CREATED.as_u16() as u32);
         assert_eq!(res.message, "");
         assert_eq!(res.values, vec![Value::default()]);
     }
+
+    #[test]
+    fn service_should_work_with_closure_hooks() {
+        let received = Arc::new(AtomicUsize::new(0));
+        let executed = Arc::new(AtomicUsize::new(0));
+        let (r, e) = (received.clone(), executed.clone());
+
+        let service: Service = ServiceInner::new(MemTable::new())
+            .fn_received(move |_| {
+                r.fetch_add(1, Ordering::SeqCst);
+            })
+            .fn_executed(move |res| {
+                if res.status != 200 {
+                    e.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+            // 不允许写 readonly 这个 table
+            .fn_before_dispatch(|cmd| match &cmd.request_data {
+                Some(RequestData::Hset(v)) if v.table == "readonly" => Err(
+                    KvError::InvalidCommand("table readonly is read only".into()),
+                ),
+                _ => Ok(()),
+            })
+            .into();
+
+        let res = execute(
+            &service,
+            CommandRequest::new_hset("readonly", "k1", "v1".into()),
+        );
+        assert_res_error(res, 400, "read only");
+        let res = execute(&service, CommandRequest::new_hget("readonly", "k1"));
+        assert_res_error(res, 404, "Not found");
+        let res = execute(&service, CommandRequest::new_hset("t1", "k1", "v1".into()));
+        assert_res_ok(res, &[Value::default()], &[]);
+
+        assert_eq!(received.load(Ordering::SeqCst), 3);
+        assert_eq!(executed.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn service_should_work_with_async_hooks() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let (l1, l2) = (log.clone(), log.clone());
+
+        let service: Service = ServiceInner::new(MemTable::new())
+            .fn_received_async(move |cmd| {
+                let log = l1.clone();
+                async move { log.lock().unwrap().push(format!("{:?}", cmd.request_data)) }
+            })
+            .fn_executed_async(move |res| {
+                let log = l2.clone();
+                async move { log.lock().unwrap().push(res.status.to_string()) }
+            })
+            .into();
+
+        let res = execute(&service, CommandRequest::new_hget("t1", "k1"));
+        assert_res_error(res, 404, "Not found");
+        let log = log.lock().unwrap();
+        assert_eq!(log.len(), 2);
+        assert!(log[0].contains("Hget"));
+        assert_eq!(log[1], "404");
+    }
+
+    #[test]
+    fn session_should_enforce_acl() {
+        let acl = Acl::new()
+            .user(
+                User::new("admin")
+                    .token("t0ken")
+                    .grant("*", Permission::ReadWrite),
+            )
+            .user(
+                User::new("reader")
+                    .password("pw")
+                    .grant("t1", Permission::Read),
+            );
+        let service: Service = ServiceInner::new(MemTable::new()).acl(acl).into();
+
+        // 没有认证的 Service::execute 会被拒绝
+        let res = execute(&service, CommandRequest::new_hget("t1", "k1"));
+        assert_res_error(res, 401, "authentication required");
+
+        let mut admin = service.session();
+        let res = session_execute(&mut admin, CommandRequest::new_auth_token("t0ken"));
+        assert_res_ok(res, &[], &[]);
+        assert_eq!(admin.user().map(|u| u.name()), Some("admin"));
+        let res = session_execute(
+            &mut admin,
+            CommandRequest::new_hset("t1", "k1", "v1".into()),
+        );
+        assert_res_ok(res, &[Value::default()], &[]);
+
+        let mut reader = service.session();
+        let res = session_execute(&mut reader, CommandRequest::new_auth("reader", "pw"));
+        assert_res_ok(res, &[], &[]);
+        let res = session_execute(&mut reader, CommandRequest::new_hget("t1", "k1"));
+        assert_res_ok(res, &["v1".into()], &[]);
+        let res = session_execute(&mut reader, CommandRequest::new_hdel("t1", "k1"));
+        assert_res_error(res, 403, "Permission denied");
+        let res = session_execute(&mut reader, CommandRequest::new_hget("t2", "k1"));
+        assert_res_error(res, 403, "Permission denied");
+
+        // 认证失败不会清掉之前认证过的用户
+        let res = session_execute(&mut reader, CommandRequest::new_auth("reader", "bad"));
+        assert_res_error(res, 401, "invalid credentials");
+        assert_eq!(reader.user().map(|u| u.name()), Some("reader"));
+    }
+
+    #[test]
+    fn auth_without_acl_should_be_rejected() {
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        let mut session = service.session();
+        let res = session_execute(&mut session, CommandRequest::new_auth("alice", "secret"));
+        assert_res_error(res, 400, "not enabled");
+        let res = execute(&service, CommandRequest::new_auth("alice", "secret"));
+        assert_res_error(res, 400, "connection");
+    }
+
+    // 只有一个响应的命令，直接取出这个响应
+    fn execute<Store: Storage>(service: &Service<Store>, cmd: CommandRequest) -> CommandResponse {
+        let mut responses = block_on_stream(service.execute(cmd));
+        let res = responses.next().unwrap();
+        assert!(responses.next().is_none());
+        res.as_ref().clone()
+    }
+
+    fn session_execute<Store: Storage>(
+        session: &mut Session<Store>,
+        cmd: CommandRequest,
+    ) -> CommandResponse {
+        let mut responses = block_on_stream(session.execute(cmd));
+        let res = responses.next().unwrap();
+        assert!(responses.next().is_none());
+        res.as_ref().clone()
+    }
 }
@@ -1,8 +1,11 @@
-
 pub trait Notify<Arg> {
     fn notify(&self, arg: &Arg);
 }
 
 pub trait NotifyMut<Arg> {
     fn notify(&self, arg: &mut Arg);
-}
\ No newline at end of file
+}
+
+pub trait NotifyAsync<Arg> {
+    fn notify(&self, arg: &Arg) -> impl Future<Output = ()> + Send;
+}
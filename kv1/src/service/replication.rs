@@ -0,0 +1,329 @@
+use super::command_service::{STREAM_CHUNK_SIZE, dispatch};
+use crate::command_request::RequestData;
+use crate::storage::deadline_ms;
+use crate::{CommandRequest, CommandResponse, KvError, Storage, StreamingResponse};
+use futures::{StreamExt, stream};
+use parking_lot::Mutex;
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{info, warn};
+
+// follower 最多可以落后多少个命令，超过之后连接会被断开，follower 要重新加载快照
+const REPLICATION_BUFFER: usize = 4096;
+
+pub(crate) struct Leader {
+    // 写命令在锁里执行并发给 follower，保证 follower 收到的顺序和执行的顺序一致
+    lock: Mutex<()>,
+    // 发给 follower 的是写命令执行之后，受影响的 key 的状态
+    sender: broadcast::Sender<Arc<Vec<CommandRequest>>>,
+}
+
+impl Default for Leader {
+    fn default() -> Self {
+        Self {
+            lock: Mutex::new(()),
+            sender: broadcast::channel(REPLICATION_BUFFER).0,
+        }
+    }
+}
+
+impl Leader {
+    pub(crate) fn execute(&self, cmd: CommandRequest, store: &dyn Storage) -> CommandResponse {
+        let _guard = self.lock.lock();
+        let res = dispatch(cmd.clone(), store);
+        // 失败的命令没有修改数据，不用发给 follower；没有 follower 时不用读状态
+        if res.status >= 300 || self.sender.receiver_count() == 0 {
+            return res;
+        }
+        match changes(&cmd, store) {
+            // 没有 follower 时 send 会返回错误，直接忽略
+            Ok(changes) if !changes.is_empty() => {
+                let _ = self.sender.send(Arc::new(changes));
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Failed to read changes of {:?}: {:?}", cmd, e),
+        }
+        res
+    }
+
+    // 先发快照，再发之后的修改
+    // 先订阅再在后台扫描，扫描的时候不持有锁，不会阻塞写命令
+    // 扫描期间的修改可能已经在快照里了，因为发的是 key 的状态，follower 再执行一遍结果也一样
+    pub(crate) fn replicate(
+        &self,
+        scan: impl FnOnce() -> Result<Vec<CommandRequest>, KvError> + Send + 'static,
+    ) -> StreamingResponse {
+        let receiver = self.sender.subscribe();
+        let snapshot = stream::once(async move {
+            let snapshot = match tokio::task::spawn_blocking(scan).await {
+                Ok(Ok(v)) => v,
+                Ok(Err(e)) => return stream::iter(vec![e.into()]),
+                Err(e) => return stream::iter(vec![KvError::Internal(e.to_string()).into()]),
+            };
+            info!("Sending a snapshot of {} commands", snapshot.len());
+
+            // 快照是空的也要发一个响应，follower 收到第一个响应后会清空本地的数据
+            let mut responses: Vec<CommandResponse> =
+                snapshot.into_iter().map(|cmd| vec![cmd].into()).collect();
+            if responses.is_empty() {
+                responses.push(Vec::<CommandRequest>::new().into());
+            }
+            stream::iter(responses)
+        })
+        .flatten();
+
+        let changes = stream::unfold(Some(receiver), |receiver| async move {
+            let mut receiver = receiver?;
+            match receiver.recv().await {
+                Ok(changes) => Some((changes.as_ref().clone().into(), Some(receiver))),
+                Err(RecvError::Lagged(n)) => {
+                    warn!("Follower lagged behind by {} commands", n);
+                    let res =
+                        KvError::Internal(format!("Follower lagged behind by {} commands", n));
+                    Some((res.into(), None))
+                }
+                Err(RecvError::Closed) => None,
+            }
+        });
+        // 快照出错时 follower 收到错误就会断开，重新连接后再加载快照
+        Box::pin(snapshot.chain(changes).map(Arc::new))
+    }
+}
+
+// 写命令执行之后受影响的 key 现在的状态，follower 依次执行就能得到一样的数据
+// 事务里的修改放在一个事务里，follower 上也是原子的
+fn changes(cmd: &CommandRequest, store: &dyn Storage) -> Result<Vec<CommandRequest>, KvError> {
+    let changes = keys(cmd)
+        .into_iter()
+        .map(|key| match key {
+            Changed::Key(table, key) => key_state(store, table, key),
+            Changed::Table(table) => Ok(CommandRequest::new_hdrop(table)),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let atomic = changes.iter().all(|cmd| match &cmd.request_data {
+        Some(RequestData::Hset(v)) => v.deadline == 0,
+        Some(RequestData::Hmdel(_)) => true,
+        _ => false,
+    });
+    Ok(match changes.len() > 1 && atomic {
+        true => vec![CommandRequest::new_transaction(changes)],
+        false => changes,
+    })
+}
+
+enum Changed<'a> {
+    Key(&'a str, &'a str),
+    Table(&'a str),
+}
+
+// 写命令会修改的 key 或者 table
+fn keys(cmd: &CommandRequest) -> Vec<Changed<'_>> {
+    let Some(data) = cmd.request_data.as_ref() else {
+        return Vec::new();
+    };
+    match data {
+        RequestData::Hset(v) => v
+            .pair
+            .iter()
+            .map(|p| Changed::Key(&v.table, &p.key))
+            .collect(),
+        RequestData::Hmset(v) => v
+            .pairs
+            .iter()
+            .map(|p| Changed::Key(&v.table, &p.key))
+            .collect(),
+        RequestData::Hdel(v) => vec![Changed::Key(&v.table, &v.key)],
+        RequestData::Hmdel(v) => v.keys.iter().map(|k| Changed::Key(&v.table, k)).collect(),
+        RequestData::Hexpire(v) => vec![Changed::Key(&v.table, &v.key)],
+        RequestData::Hincrby(v) => vec![Changed::Key(&v.table, &v.key)],
+        RequestData::Hincrbyfloat(v) => vec![Changed::Key(&v.table, &v.key)],
+        RequestData::Hcas(v) => vec![Changed::Key(&v.table, &v.key)],
+        RequestData::Hdrop(v) => vec![Changed::Table(&v.table)],
+        RequestData::Transaction(v) => v.commands.iter().flat_map(keys).collect(),
+        _ => Vec::new(),
+    }
+}
+
+// 把 key 设置成现在的值和过期时刻，不存在就删掉
+fn key_state(store: &dyn Storage, table: &str, key: &str) -> Result<CommandRequest, KvError> {
+    let deleted = || CommandRequest::new_hmdel(table, [key]);
+    let Some(value) = store.get(table, key)? else {
+        return Ok(deleted());
+    };
+    Ok(match store.ttl(table, key) {
+        Ok(None) => CommandRequest::new_hset(table, key, value),
+        Ok(Some(ttl)) => {
+            CommandRequest::new_hset_with_deadline(table, key, value, deadline_ms(ttl))
+        }
+        // 刚刚过期了
+        Err(KvError::NotFound(..)) => deleted(),
+        Err(e) => return Err(e),
+    })
+}
+
+// 把 store 里的数据转换成一组命令，在空的 store 上依次执行就能得到一样的数据
+pub(crate) fn snapshot(store: &dyn Storage) -> Result<Vec<CommandRequest>, KvError> {
+    let mut commands = Vec::new();
+    for table in store.tables()? {
+        let mut pairs = Vec::new();
+        for pair in store.get_iter(&table)? {
+            match store.ttl(&table, &pair.key) {
+                Ok(None) => pairs.push(pair),
+                // 发过期的时刻，follower 上的 key 和 leader 上的同时过期
+                Ok(Some(ttl)) => commands.push(CommandRequest::new_hset_with_deadline(
+                    &table,
+                    pair.key,
+                    pair.value.unwrap_or_default(),
+                    deadline_ms(ttl),
+                )),
+                // 刚刚过期了
+                Err(KvError::NotFound(..)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        commands.extend(
+            pairs
+                .chunks(STREAM_CHUNK_SIZE)
+                .map(|chunk| CommandRequest::new_hmset(&table, chunk.to_vec())),
+        );
+    }
+    Ok(commands)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Kvpair, MemTable, Value};
+    use std::time::Duration;
+
+    #[test]
+    fn snapshot_should_restore_data() {
+        let store = MemTable::new();
+        for i in 0..300 {
+            store.set("t1", format!("k{}", i), i.into(), None).unwrap();
+        }
+        store.set("t2", "k1".into(), "v1".into(), None).unwrap();
+        store
+            .expire("t2", "k1", Some(Duration::from_secs(60)))
+            .unwrap();
+
+        let commands = snapshot(&store).unwrap();
+        // t1 分成两个 Hmset，t2 里有 ttl 的 key 单独用一个 Hset
+        assert_eq!(commands.len(), 3);
+
+        let restored = MemTable::new();
+        for cmd in commands {
+            assert_eq!(dispatch(cmd, &restored).status, 200);
+        }
+        assert_eq!(restored.len("t1"), Ok(300));
+        assert_eq!(restored.get("t1", "k299"), Ok(Some(299.into())));
+        assert_eq!(restored.get("t2", "k1"), Ok(Some("v1".into())));
+        assert!(restored.ttl("t2", "k1").unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn leader_should_stream_successful_writes() {
+        let store = Arc::new(MemTable::new());
+        let leader = Leader::default();
+        leader.execute(CommandRequest::new_hset("t1", "k1", "v1".into()), &store);
+
+        let snapshot_store = store.clone();
+        let mut responses = leader.replicate(move || snapshot(&snapshot_store));
+        let res = responses.next().await.unwrap();
+        assert_eq!(res.commands.len(), 1);
+
+        let cmd = CommandRequest::new_hset("t1", "k1", "v2".into());
+        leader.execute(cmd.clone(), &store);
+        // 对字符串做 incr 会失败，不会发给 follower
+        let res = leader.execute(CommandRequest::new_hincrby("t1", "k1", 1), &store);
+        assert_ne!(res.status, 200);
+        // 发的是执行之后的值，而不是命令本身
+        leader.execute(CommandRequest::new_hincrby("t1", "n", 3), &store);
+        leader.execute(CommandRequest::new_hdel("t1", "k1"), &store);
+
+        assert_eq!(responses.next().await.unwrap().commands, vec![cmd]);
+        assert_eq!(
+            responses.next().await.unwrap().commands,
+            vec![CommandRequest::new_hset("t1", "n", 3.into())]
+        );
+        assert_eq!(
+            responses.next().await.unwrap().commands,
+            vec![CommandRequest::new_hmdel("t1", ["k1"])]
+        );
+        assert_eq!(store.get("t1", "k1"), Ok(None::<Value>));
+    }
+
+    #[tokio::test]
+    async fn writes_should_not_wait_for_snapshot() {
+        let store = Arc::new(MemTable::new());
+        let leader = Leader::default();
+        // 扫描一直等到写命令执行完
+        let (tx, rx) = std::sync::mpsc::channel();
+        let scan_store = store.clone();
+        let mut responses = leader.replicate(move || {
+            rx.recv().unwrap();
+            snapshot(&scan_store)
+        });
+        let first = tokio::spawn(async move { (responses.next().await, responses) });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        leader.execute(CommandRequest::new_hincrby("t1", "n", 1), &store);
+        tx.send(()).unwrap();
+
+        // 快照里已经有这次修改，之后还会再发一次，follower 执行两次结果一样
+        let (res, mut responses) = first.await.unwrap();
+        assert_eq!(
+            res.unwrap().commands,
+            vec![CommandRequest::new_hmset(
+                "t1",
+                vec![Kvpair::new("n", 1.into())]
+            )]
+        );
+        assert_eq!(
+            responses.next().await.unwrap().commands,
+            vec![CommandRequest::new_hset("t1", "n", 1.into())]
+        );
+    }
+
+    #[test]
+    fn changes_should_use_absolute_deadlines() {
+        let store = MemTable::new();
+        let cmd =
+            CommandRequest::new_hset_with_ttl("t1", "k1", "v1".into(), Duration::from_secs(60));
+        dispatch(cmd.clone(), &store);
+        let changes = changes(&cmd, &store).unwrap();
+        let Some(RequestData::Hset(hset)) = &changes[0].request_data else {
+            panic!("expect Hset, got {:?}", changes);
+        };
+        assert_eq!(hset.ttl, 0);
+        let expected = deadline_ms(Duration::from_secs(60));
+        assert!(hset.deadline <= expected && hset.deadline + 1000 > expected);
+
+        // follower 执行时按照 deadline 计算剩余的时间，已经过了 deadline 的马上过期
+        let follower = MemTable::new();
+        dispatch(changes[0].clone(), &follower);
+        assert!(follower.ttl("t1", "k1").unwrap().unwrap() <= Duration::from_secs(60));
+        let cmd = CommandRequest::new_hset_with_deadline("t1", "k2", "v2".into(), 1);
+        assert_eq!(dispatch(cmd, &follower).status, 200);
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(follower.get("t1", "k2"), Ok(None));
+    }
+
+    #[test]
+    fn transaction_changes_should_be_atomic() {
+        let store = MemTable::new();
+        store.set("t1", "k2".into(), "v2".into(), None).unwrap();
+        let cmd = CommandRequest::new_transaction(vec![
+            CommandRequest::new_hset("t1", "k1", "v1".into()),
+            CommandRequest::new_hdel("t1", "k2"),
+            CommandRequest::new_hget("t1", "k1"),
+        ]);
+        dispatch(cmd.clone(), &store);
+        assert_eq!(
+            changes(&cmd, &store).unwrap(),
+            vec![CommandRequest::new_transaction(vec![
+                CommandRequest::new_hset("t1", "k1", "v1".into()),
+                CommandRequest::new_hmdel("t1", ["k2"]),
+            ])]
+        );
+    }
+}
@@ -0,0 +1,229 @@
+use crate::{CommandResponse, KvError};
+use dashmap::{DashMap, DashSet};
+use futures::Stream;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::task::{Context, Poll};
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+// 每个订阅者的缓冲区大小，消费太慢时新消息会被丢弃
+const BROADCAST_CAPACITY: usize = 128;
+
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+
+fn get_next_subscription_id() -> u32 {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+// owner 是发起订阅的连接，只有同一个连接才能取消订阅
+pub trait Topic: Send + Sync + 'static {
+    fn subscribe(self, name: String, owner: u64) -> Subscription;
+    fn unsubscribe(self, name: String, id: u32, owner: u64) -> Result<u32, KvError>;
+    fn publish(self, name: String, value: Arc<CommandResponse>);
+}
+
+#[derive(Default, Debug)]
+pub struct Broadcaster {
+    // 所有的主题列表，每个主题下是订阅者的 id
+    topics: DashMap<String, DashSet<u32>>,
+    // 所有的订阅列表
+    subscriptions: DashMap<u32, Subscriber>,
+}
+
+#[derive(Debug)]
+struct Subscriber {
+    topic: String,
+    owner: u64,
+    tx: mpsc::Sender<Arc<CommandResponse>>,
+}
+
+impl Broadcaster {
+    pub fn topic_count(&self) -> usize {
+        self.topics.len()
+    }
+
+    pub fn subscription_count(&self) -> usize {
+        self.subscriptions.len()
+    }
+
+    // f 返回 true 时才移除这个订阅，同时把它从所在的主题里删掉
+    fn remove_subscription(&self, id: u32, f: impl FnOnce(&Subscriber) -> bool) -> Option<u32> {
+        let (id, sub) = self.subscriptions.remove_if(&id, |_, v| f(v))?;
+        let name = &sub.topic;
+        if let Some(v) = self.topics.get_mut(name) {
+            v.remove(&id);
+            if v.is_empty() {
+                info!("Topic: {:?} is deleted", name);
+                drop(v);
+                self.topics.remove_if(name, |_, v| v.is_empty());
+            }
+        }
+        debug!("Subscription {} is removed!", id);
+        Some(id)
+    }
+}
+
+impl Topic for Arc<Broadcaster> {
+    fn subscribe(self, name: String, owner: u64) -> Subscription {
+        let id = get_next_subscription_id();
+        let (tx, rx) = mpsc::channel(BROADCAST_CAPACITY);
+        let sub = Subscriber {
+            topic: name.clone(),
+            owner,
+            tx,
+        };
+        self.subscriptions.insert(id, sub);
+        self.topics.entry(name).or_default().insert(id);
+        debug!("Subscription {} is added", id);
+
+        Subscription {
+            id,
+            rx,
+            broadcaster: self,
+        }
+    }
+
+    fn unsubscribe(self, name: String, id: u32, owner: u64) -> Result<u32, KvError> {
+        // 不是这个连接在这个主题上的订阅，当作不存在
+        let removed = self.remove_subscription(id, |v| v.topic == name && v.owner == owner);
+        match removed {
+            Some(id) => Ok(id),
+            None => Err(KvError::NotFound(
+                format!("topic: {}", name),
+                format!("id: {}", id),
+            )),
+        }
+    }
+
+    fn publish(self, name: String, value: Arc<CommandResponse>) {
+        let Some(ids) = self.topics.get(&name).map(|v| v.clone()) else {
+            return;
+        };
+
+        for id in ids.iter() {
+            let id = *id;
+            let Some(tx) = self.subscriptions.get(&id).map(|v| v.tx.clone()) else {
+                continue;
+            };
+            match tx.try_send(value.clone()) {
+                Ok(()) => {}
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    warn!("Subscription {} is full, drop the message", id);
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => {
+                    // 订阅者已经断开
+                    warn!("Subscription {} is closed", id);
+                    self.remove_subscription(id, |_| true);
+                }
+            }
+        }
+    }
+}
+
+// 一个订阅，作为 Stream 不断返回发布到这个主题的数据
+// drop 时（比如连接断开）会自动从 Broadcaster 里移除
+pub struct Subscription {
+    id: u32,
+    rx: mpsc::Receiver<Arc<CommandResponse>>,
+    broadcaster: Arc<Broadcaster>,
+}
+
+impl Subscription {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+impl Stream for Subscription {
+    type Item = Arc<CommandResponse>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.broadcaster.remove_subscription(self.id, |_| true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Value, assert_res_ok};
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn pub_sub_should_work() {
+        let b = Arc::new(Broadcaster::default());
+        let lobby = "lobby".to_string();
+
+        let mut stream1 = b.clone().subscribe(lobby.clone(), 1);
+        let mut stream2 = b.clone().subscribe(lobby.clone(), 2);
+        assert_ne!(stream1.id(), stream2.id());
+        assert_eq!(b.subscription_count(), 2);
+
+        let v: Value = "hello".into();
+        b.clone()
+            .publish(lobby.clone(), Arc::new(vec![v.clone()].into()));
+
+        let res1 = stream1.next().await.unwrap();
+        let res2 = stream2.next().await.unwrap();
+        assert_eq!(res1, res2);
+        assert_res_ok(res1.as_ref().clone(), std::slice::from_ref(&v), &[]);
+
+        // 取消订阅后，Sender 被 drop，stream 会结束
+        let id = stream1.id();
+        assert_eq!(b.clone().unsubscribe(lobby.clone(), id, 1), Ok(id));
+        assert!(stream1.next().await.is_none());
+
+        let v: Value = "world".into();
+        b.clone()
+            .publish(lobby.clone(), Arc::new(vec![v.clone()].into()));
+        let res2 = stream2.next().await.unwrap();
+        assert_res_ok(res2.as_ref().clone(), &[v], &[]);
+    }
+
+    #[test]
+    fn unsubscribe_unknown_id_should_fail() {
+        let b = Arc::new(Broadcaster::default());
+        let result = b.unsubscribe("lobby".into(), 9999, 1);
+        assert!(matches!(result, Err(KvError::NotFound(_, _))));
+    }
+
+    #[test]
+    fn unsubscribe_should_check_topic_and_owner() {
+        let b = Arc::new(Broadcaster::default());
+        let stream1 = b.clone().subscribe("lobby".into(), 1);
+        let id = stream1.id();
+
+        // 主题不对，或者不是同一个连接，都不能取消
+        let result = b.clone().unsubscribe("other".into(), id, 1);
+        assert!(matches!(result, Err(KvError::NotFound(_, _))));
+        let result = b.clone().unsubscribe("lobby".into(), id, 2);
+        assert!(matches!(result, Err(KvError::NotFound(_, _))));
+        assert_eq!(b.subscription_count(), 1);
+        assert_eq!(b.topic_count(), 1);
+
+        assert_eq!(b.clone().unsubscribe("lobby".into(), id, 1), Ok(id));
+        assert_eq!(b.subscription_count(), 0);
+        assert_eq!(b.topic_count(), 0);
+    }
+
+    #[test]
+    fn drop_subscription_should_clean_up() {
+        let b = Arc::new(Broadcaster::default());
+        let stream1 = b.clone().subscribe("lobby".into(), 1);
+        let stream2 = b.clone().subscribe("lobby".into(), 1);
+        assert_eq!(b.topic_count(), 1);
+
+        drop(stream1);
+        assert_eq!(b.subscription_count(), 1);
+        drop(stream2);
+        assert_eq!(b.subscription_count(), 0);
+        assert_eq!(b.topic_count(), 0);
+    }
+}
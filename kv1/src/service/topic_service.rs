@@ -0,0 +1,114 @@
+use crate::command_request::RequestData;
+use crate::{
+    CommandRequest, CommandResponse, KvError, Publish, Subscribe, Topic, Unsubscribe, Value,
+};
+use futures::{Stream, StreamExt, stream};
+use std::pin::Pin;
+use std::sync::Arc;
+
+// 一个请求可能对应多个响应，比如订阅会不断返回发布的数据
+pub type StreamingResponse = Pin<Box<dyn Stream<Item = Arc<CommandResponse>> + Send>>;
+
+// owner 是执行命令的连接
+pub trait TopicService {
+    fn execute(self, topic: impl Topic, owner: u64) -> StreamingResponse;
+}
+
+impl TopicService for Subscribe {
+    fn execute(self, topic: impl Topic, owner: u64) -> StreamingResponse {
+        let subscription = topic.subscribe(self.topic, owner);
+        // 第一个响应返回订阅 id，之后是发布到这个主题的数据
+        let id: Value = (subscription.id() as i64).into();
+        Box::pin(stream::once(async move { Arc::new(id.into()) }).chain(subscription))
+    }
+}
+
+impl TopicService for Unsubscribe {
+    fn execute(self, topic: impl Topic, owner: u64) -> StreamingResponse {
+        let res: CommandResponse = match topic.unsubscribe(self.topic, self.id, owner) {
+            Ok(_) => true.into(),
+            Err(e) => e.into(),
+        };
+        Box::pin(stream::once(async move { Arc::new(res) }))
+    }
+}
+
+impl TopicService for Publish {
+    fn execute(self, topic: impl Topic, _owner: u64) -> StreamingResponse {
+        topic.publish(self.topic, Arc::new(self.data.into()));
+        let res: CommandResponse = true.into();
+        Box::pin(stream::once(async move { Arc::new(res) }))
+    }
+}
+
+pub fn dispatch_stream(cmd: CommandRequest, topic: impl Topic, owner: u64) -> StreamingResponse {
+    match cmd.request_data {
+        Some(RequestData::Subscribe(param)) => param.execute(topic, owner),
+        Some(RequestData::Unsubscribe(param)) => param.execute(topic, owner),
+        Some(RequestData::Publish(param)) => param.execute(topic, owner),
+        _ => {
+            let res: CommandResponse = KvError::InvalidCommand("Not a topic command".into()).into();
+            Box::pin(stream::once(async move { Arc::new(res) }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Broadcaster, assert_res_error, assert_res_ok};
+
+    #[tokio::test]
+    async fn dispatch_publish_should_work() {
+        let topic = Arc::new(Broadcaster::default());
+        let cmd = CommandRequest::new_publish("lobby", vec!["hello".into()]);
+        let mut res = dispatch_stream(cmd, topic, 1);
+        let data = res.next().await.unwrap();
+        assert_res_ok(data.as_ref().clone(), &[], &[]);
+        assert!(res.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn dispatch_subscribe_should_work() {
+        let topic = Arc::new(Broadcaster::default());
+        let cmd = CommandRequest::new_subscribe("lobby");
+        let mut res = dispatch_stream(cmd, topic.clone(), 1);
+        let id = get_id(&mut res).await;
+        assert!(id > 0);
+
+        let cmd = CommandRequest::new_publish("lobby", vec![10.into(), "hello".into()]);
+        dispatch_stream(cmd, topic, 2).next().await.unwrap();
+
+        let data = res.next().await.unwrap();
+        assert_res_ok(data.as_ref().clone(), &[10.into(), "hello".into()], &[]);
+    }
+
+    #[tokio::test]
+    async fn dispatch_unsubscribe_should_work() {
+        let topic = Arc::new(Broadcaster::default());
+        let cmd = CommandRequest::new_subscribe("lobby");
+        let mut res = dispatch_stream(cmd, topic.clone(), 1);
+        let id = get_id(&mut res).await;
+
+        // 别的连接不能取消这个订阅
+        let cmd = CommandRequest::new_unsubscribe("lobby", id as _);
+        let mut res = dispatch_stream(cmd, topic.clone(), 2);
+        let data = res.next().await.unwrap();
+        assert_res_error(data.as_ref().clone(), 404, "Not found");
+
+        let cmd = CommandRequest::new_unsubscribe("lobby", id as _);
+        let mut res = dispatch_stream(cmd, topic.clone(), 1);
+        let data = res.next().await.unwrap();
+        assert_res_ok(data.as_ref().clone(), &[], &[]);
+
+        let cmd = CommandRequest::new_unsubscribe("lobby", id as _);
+        let mut res = dispatch_stream(cmd, topic, 1);
+        let data = res.next().await.unwrap();
+        assert_res_error(data.as_ref().clone(), 404, "Not found");
+    }
+
+    async fn get_id(res: &mut StreamingResponse) -> i64 {
+        let res = res.next().await.unwrap();
+        res.values[0].clone().try_into().unwrap()
+    }
+}
@@ -0,0 +1,310 @@
+use crate::command_request::RequestData;
+use crate::storage::{add_float, add_integer};
+use crate::{
+    CommandRequest, CommandResponse, CommandService, Hcas, Hdel, Hexist, Hget, Hincrby,
+    Hincrbyfloat, Hmdel, Hmexist, Hmget, Hmset, Hset, KvError, Kvpair, Storage, StorageTx,
+    Transaction, Value,
+};
+
+// 事务里的命令只能通过 StorageTx 读写，返回 Err 时整个事务回滚
+pub trait TransactionService {
+    fn execute_tx(self, tx: &mut dyn StorageTx) -> Result<CommandResponse, KvError>;
+}
+
+impl CommandService for Transaction {
+    fn execute(self, store: &dyn Storage) -> CommandResponse {
+        // 先确认所有命令都能在事务里执行，同时拿到要锁住的 table
+        let tables: Option<Vec<String>> = self.commands.iter().map(tx_table).collect();
+        let Some(tables) = tables else {
+            return KvError::InvalidCommand("Command is not supported in transaction".into())
+                .into();
+        };
+
+        let commands = self.commands;
+        let res = store.transaction(&tables, &|tx| {
+            commands
+                .iter()
+                .map(|cmd| dispatch_tx(cmd.clone(), tx))
+                .collect()
+        });
+        match res {
+            Ok(responses) => responses.into(),
+            Err(e) => KvError::Rollback(Box::new(e)).into(),
+        }
+    }
+}
+
+impl TransactionService for Hget {
+    fn execute_tx(self, tx: &mut dyn StorageTx) -> Result<CommandResponse, KvError> {
+        Ok(match tx.get(&self.table, &self.key)? {
+            Some(v) => v.into(),
+            None => KvError::NotFound(self.table, self.key).into(),
+        })
+    }
+}
+
+impl TransactionService for Hmget {
+    fn execute_tx(self, tx: &mut dyn StorageTx) -> Result<CommandResponse, KvError> {
+        let mut pairs = Vec::new();
+        for key in self.keys {
+            if let Some(v) = tx.get(&self.table, &key)? {
+                pairs.push(Kvpair::new(key, v));
+            }
+        }
+        Ok(pairs.into())
+    }
+}
+
+impl TransactionService for Hexist {
+    fn execute_tx(self, tx: &mut dyn StorageTx) -> Result<CommandResponse, KvError> {
+        Ok(tx.get(&self.table, &self.key)?.is_some().into())
+    }
+}
+
+impl TransactionService for Hmexist {
+    fn execute_tx(self, tx: &mut dyn StorageTx) -> Result<CommandResponse, KvError> {
+        let mut pairs = Vec::new();
+        for key in self.keys {
+            let exist = tx.get(&self.table, &key)?.is_some();
+            pairs.push(Kvpair::new(key, exist.into()));
+        }
+        Ok(pairs.into())
+    }
+}
+
+impl TransactionService for Hset {
+    fn execute_tx(self, tx: &mut dyn StorageTx) -> Result<CommandResponse, KvError> {
+        let Some(pair) = self.pair else {
+            return Ok(Value::default().into());
+        };
+        let old = tx.set(&self.table, &pair.key, pair.value.unwrap_or_default())?;
+        Ok(old.unwrap_or_default().into())
+    }
+}
+
+impl TransactionService for Hmset {
+    fn execute_tx(self, tx: &mut dyn StorageTx) -> Result<CommandResponse, KvError> {
+        for pair in self.pairs {
+            tx.set(&self.table, &pair.key, pair.value.unwrap_or_default())?;
+        }
+        Ok(true.into())
+    }
+}
+
+impl TransactionService for Hdel {
+    fn execute_tx(self, tx: &mut dyn StorageTx) -> Result<CommandResponse, KvError> {
+        let old = tx.del(&self.table, &self.key)?;
+        Ok(old.unwrap_or_default().into())
+    }
+}
+
+impl TransactionService for Hmdel {
+    fn execute_tx(self, tx: &mut dyn StorageTx) -> Result<CommandResponse, KvError> {
+        for key in self.keys {
+            tx.del(&self.table, &key)?;
+        }
+        Ok(true.into())
+    }
+}
+
+impl TransactionService for Hincrby {
+    fn execute_tx(self, tx: &mut dyn StorageTx) -> Result<CommandResponse, KvError> {
+        let current = tx.get(&self.table, &self.key)?.unwrap_or(0.into());
+        let v = add_integer(&current, self.delta)?;
+        tx.set(&self.table, &self.key, v.into())?;
+        Ok(Value::from(v).into())
+    }
+}
+
+impl TransactionService for Hincrbyfloat {
+    fn execute_tx(self, tx: &mut dyn StorageTx) -> Result<CommandResponse, KvError> {
+        let current = tx.get(&self.table, &self.key)?.unwrap_or(0.into());
+        let v = add_float(&current, self.delta)?;
+        tx.set(&self.table, &self.key, v.into())?;
+        Ok(Value::from(v).into())
+    }
+}
+
+impl TransactionService for Hcas {
+    fn execute_tx(self, tx: &mut dyn StorageTx) -> Result<CommandResponse, KvError> {
+        let Some(new_value) = self.new_value else {
+            return Err(KvError::InvalidCommand("Hcas requires a new value".into()));
+        };
+        let current = tx.get(&self.table, &self.key)?;
+        if current != self.expected {
+            return Ok(vec![false.into(), current.unwrap_or_default()].into());
+        }
+        tx.set(&self.table, &self.key, new_value.clone())?;
+        Ok(vec![true.into(), new_value].into())
+    }
+}
+
+pub fn dispatch_tx(
+    cmd: CommandRequest,
+    tx: &mut dyn StorageTx,
+) -> Result<CommandResponse, KvError> {
+    match cmd.request_data {
+        Some(RequestData::Hget(param)) => param.execute_tx(tx),
+        Some(RequestData::Hmget(param)) => param.execute_tx(tx),
+        Some(RequestData::Hexist(param)) => param.execute_tx(tx),
+        Some(RequestData::Hmexist(param)) => param.execute_tx(tx),
+        Some(RequestData::Hset(param)) => param.execute_tx(tx),
+        Some(RequestData::Hmset(param)) => param.execute_tx(tx),
+        Some(RequestData::Hdel(param)) => param.execute_tx(tx),
+        Some(RequestData::Hmdel(param)) => param.execute_tx(tx),
+        Some(RequestData::Hincrby(param)) => param.execute_tx(tx),
+        Some(RequestData::Hincrbyfloat(param)) => param.execute_tx(tx),
+        Some(RequestData::Hcas(param)) => param.execute_tx(tx),
+        _ => Err(KvError::InvalidCommand(
+            "Command is not supported in transaction".into(),
+        )),
+    }
+}
+
+// 能在事务里执行的命令返回它操作的 table，其它的返回 None
+fn tx_table(cmd: &CommandRequest) -> Option<String> {
+    let table = match cmd.request_data.as_ref()? {
+        RequestData::Hget(v) => &v.table,
+        RequestData::Hmget(v) => &v.table,
+        RequestData::Hexist(v) => &v.table,
+        RequestData::Hmexist(v) => &v.table,
+        // 事务里不支持设置过期时间
+        RequestData::Hset(v) if v.ttl == 0 && v.deadline == 0 => &v.table,
+        RequestData::Hmset(v) if v.ttl == 0 => &v.table,
+        RequestData::Hdel(v) => &v.table,
+        RequestData::Hmdel(v) => &v.table,
+        RequestData::Hincrby(v) => &v.table,
+        RequestData::Hincrbyfloat(v) => &v.table,
+        RequestData::Hcas(v) => &v.table,
+        _ => return None,
+    };
+    Some(table.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemTable, SledDb, assert_res_error, assert_res_ok};
+    use std::sync::Arc;
+    use std::thread::spawn;
+    use tempfile::tempdir;
+
+    #[test]
+    fn mem_table_transaction_should_work() {
+        test_transaction(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_transaction_should_work() {
+        let dir = tempdir().unwrap();
+        test_transaction(SledDb::new(dir));
+    }
+
+    #[test]
+    fn mem_table_transaction_should_be_isolated() {
+        test_isolation(Arc::new(MemTable::new()));
+    }
+
+    #[test]
+    fn sleddb_transaction_should_be_isolated() {
+        let dir = tempdir().unwrap();
+        test_isolation(Arc::new(SledDb::new(dir)));
+    }
+
+    #[test]
+    fn unsupported_command_should_be_rejected() {
+        let store = MemTable::new();
+        let cmd = Transaction {
+            commands: vec![
+                CommandRequest::new_hset("t1", "k1", "v1".into()),
+                CommandRequest::new_hgetall("t1"),
+            ],
+        };
+        assert_res_error(cmd.execute(&store), 400, "not supported");
+        assert_eq!(store.get("t1", "k1"), Ok(None));
+    }
+
+    fn test_transaction(store: impl Storage) {
+        store.set("t1", "k1".into(), 10.into(), None).unwrap();
+
+        let cmd = Transaction {
+            commands: vec![
+                CommandRequest::new_hincrby("t1", "k1", -3),
+                CommandRequest::new_hset("t2", "k1", "v1".into()),
+                CommandRequest::new_hget("t2", "k1"),
+                CommandRequest::new_hcas("t2", "k1", Some("v1".into()), "v2".into()),
+                CommandRequest::new_hdel("t1", "k2"),
+            ],
+        };
+        let res = cmd.execute(&store);
+        assert_eq!(res.status, 200);
+        let mut responses = res.responses.into_iter();
+        assert_res_ok(responses.next().unwrap(), &[7.into()], &[]);
+        assert_res_ok(responses.next().unwrap(), &[Value::default()], &[]);
+        assert_res_ok(responses.next().unwrap(), &["v1".into()], &[]);
+        assert_res_ok(responses.next().unwrap(), &[true.into(), "v2".into()], &[]);
+        assert_res_ok(responses.next().unwrap(), &[Value::default()], &[]);
+        assert_eq!(store.get("t1", "k1"), Ok(Some(7.into())));
+        assert_eq!(store.get("t2", "k1"), Ok(Some("v2".into())));
+
+        // 后面的命令出错，前面的写入都要回滚
+        let cmd = Transaction {
+            commands: vec![
+                CommandRequest::new_hset("t1", "k1", 100.into()),
+                CommandRequest::new_hdel("t2", "k1"),
+                CommandRequest::new_hincrby("t2", "k2", 1),
+                CommandRequest::new_hset("t2", "k2", "v2".into()),
+                CommandRequest::new_hincrby("t2", "k2", 1),
+            ],
+        };
+        assert_res_error(cmd.execute(&store), 500, "rolled back");
+        assert_eq!(store.get("t1", "k1"), Ok(Some(7.into())));
+        assert_eq!(store.get("t2", "k1"), Ok(Some("v2".into())));
+        assert_eq!(store.get("t2", "k2"), Ok(None));
+    }
+
+    // 并发的转账事务和读事务，读到的总数应该始终不变
+    fn test_isolation<S: Storage>(store: Arc<S>) {
+        store.set("a", "balance".into(), 1000.into(), None).unwrap();
+        store.set("b", "balance".into(), 0.into(), None).unwrap();
+
+        let writers: Vec<_> = (0..4)
+            .map(|_| {
+                let store = store.clone();
+                spawn(move || {
+                    for _ in 0..50 {
+                        let cmd = Transaction {
+                            commands: vec![
+                                CommandRequest::new_hincrby("a", "balance", -1),
+                                CommandRequest::new_hincrby("b", "balance", 1),
+                            ],
+                        };
+                        assert_eq!(cmd.execute(store.as_ref()).status, 200);
+                    }
+                })
+            })
+            .collect();
+
+        for _ in 0..50 {
+            let cmd = Transaction {
+                commands: vec![
+                    CommandRequest::new_hget("a", "balance"),
+                    CommandRequest::new_hget("b", "balance"),
+                ],
+            };
+            let res = cmd.execute(store.as_ref());
+            let sum: i64 = res
+                .responses
+                .into_iter()
+                .map(|v| i64::try_from(v.values[0].clone()).unwrap())
+                .sum();
+            assert_eq!(sum, 1000);
+        }
+
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert_eq!(store.get("a", "balance"), Ok(Some(800.into())));
+        assert_eq!(store.get("b", "balance"), Ok(Some(200.into())));
+    }
+}
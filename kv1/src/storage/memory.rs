@@ -1,9 +1,29 @@
-use crate::{KvError, Kvpair, Storage, Value};
-use dashmap::{DashMap, mapref::one::Ref};
+use crate::storage::wal::{Wal, WalOptions, spawn_sync};
+use crate::storage::{add_float, add_integer, before_end, deadline_ms, now_ms, scan_start};
+use crate::{CommandResponse, KvError, Kvpair, LogEntry, Storage, StorageTx, TransactionFn, Value};
+use dashmap::{
+    DashMap,
+    mapref::{entry::Entry, one::Ref},
+};
+use parking_lot::{Mutex, MutexGuard, RawRwLock, RwLock, lock_api::ArcRwLockReadGuard};
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Bound;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
 
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug)]
 pub struct MemTable {
     tables: DashMap<String, DashMap<String, Value>>,
+    // 每个 table 里设置了过期时间的 key，值是过期时间点的毫秒时间戳
+    expires: DashMap<String, DashMap<String, u64>>,
+    // 每个 table 里的 key 按顺序排好，用来做范围扫描
+    // 可能还留着已经删除的 key，扫描时会跳过
+    index: DashMap<String, BTreeSet<String>>,
+    // table 级别的锁，普通命令拿读锁，事务拿写锁
+    locks: DashMap<String, Arc<RwLock<()>>>,
+    // 可选的 WAL，开启后每次修改都会记录下来，重启时用来恢复数据
+    wal: Option<Arc<Mutex<Wal>>>,
 }
 
 impl MemTable {
@@ -12,6 +32,27 @@ impl MemTable {
         Self::default()
     }
 
+    // 从 WAL 和快照里恢复数据，之后的修改都会写到 WAL 里
+    pub fn open(options: WalOptions) -> Result<Self, KvError> {
+        let (wal, entries) = Wal::open(options)?;
+        let mut store = Self::default();
+        for entry in entries {
+            store.replay(entry);
+        }
+        let wal = Arc::new(Mutex::new(wal));
+        spawn_sync(&wal);
+        store.wal = Some(wal);
+        Ok(store)
+    }
+
+    // 生成快照并清空 WAL，没有开启 WAL 时什么也不做
+    pub fn compact(&self) -> Result<(), KvError> {
+        match self.lock_wal() {
+            Some(mut wal) => wal.compact(self.log_entries()),
+            None => Ok(()),
+        }
+    }
+
     #[allow(dead_code)]
     pub fn get_or_create_table(&self, name: &str) -> Ref<'_, String, DashMap<String, Value>> {
         match self.tables.get(name) {
@@ -22,24 +63,261 @@ impl MemTable {
             }
         }
     }
+
+    fn table_lock(&self, name: &str) -> Arc<RwLock<()>> {
+        match self.locks.get(name) {
+            Some(lock) => lock.clone(),
+            None => self.locks.entry(name.to_string()).or_default().clone(),
+        }
+    }
+
+    fn read_table(&self, name: &str) -> ArcRwLockReadGuard<RawRwLock, ()> {
+        self.table_lock(name).read_arc()
+    }
+
+    // 把 key 加到有序索引里。调用时不能持有 table 的引用
+    fn index_add(&self, table: &str, key: &str) {
+        let mut index = self.index.entry(table.to_string()).or_default();
+        if !index.contains(key) {
+            index.insert(key.to_string());
+        }
+    }
+
+    // 在索引的锁里确认 key 已经不在 table 里了才删掉，避免删掉并发写入的 key
+    fn index_remove(&self, table: &str, key: &str) {
+        if let Some(mut index) = self.index.get_mut(table)
+            && !self.contains_key(table, key)
+        {
+            index.remove(key);
+        }
+    }
+
+    // 开启 WAL 时，修改数据和写 WAL 要在同一把锁里完成，这样 WAL 的顺序和修改的顺序一致
+    fn lock_wal(&self) -> Option<MutexGuard<'_, Wal>> {
+        self.wal.as_ref().map(|wal| wal.lock())
+    }
+
+    // 执行 f 修改 table 里的 keys，然后把 keys 修改之后的状态写到 WAL
+    // 调用时不能持有 table 的引用
+    fn logged<K: AsRef<str>, T>(
+        &self,
+        table: &str,
+        keys: &[K],
+        f: impl FnOnce() -> Result<T, KvError>,
+    ) -> Result<T, KvError> {
+        let keys: Vec<_> = keys.iter().map(|key| (table, key.as_ref())).collect();
+        self.logged_keys(&keys, f)
+    }
+
+    // f 或者写 WAL 失败时，把 keys 恢复成修改之前的状态，保证内存里的数据和 WAL 一致
+    fn logged_keys<T>(
+        &self,
+        keys: &[(&str, &str)],
+        f: impl FnOnce() -> Result<T, KvError>,
+    ) -> Result<T, KvError> {
+        let Some(mut wal) = self.lock_wal() else {
+            return f();
+        };
+        let created: Vec<&str> = keys
+            .iter()
+            .map(|(table, _)| *table)
+            .filter(|table| !self.tables.contains_key(*table))
+            .collect();
+        let before: Vec<_> = keys
+            .iter()
+            .map(|(table, key)| self.log_entry(table, key))
+            .collect();
+
+        let res = f().and_then(|v| {
+            let entries: Vec<_> = keys
+                .iter()
+                .map(|(table, key)| self.log_entry(table, key))
+                .collect();
+            self.append(&mut wal, &entries).map(|_| v)
+        });
+        if res.is_err() {
+            for entry in before {
+                self.replay(entry);
+            }
+            // 这次才创建的 table 恢复之后是空的，也要删掉
+            for table in created {
+                self.tables.remove_if(table, |_, t| t.is_empty());
+            }
+        }
+        res
+    }
+
+    // 写入成功之后 WAL 里已经有了这些修改，做快照失败只记日志，下次写入时再试
+    fn append(&self, wal: &mut Wal, entries: &[LogEntry]) -> Result<(), KvError> {
+        wal.append(entries)?;
+        if wal.should_compact()
+            && let Err(e) = wal.compact(self.log_entries())
+        {
+            warn!("Failed to compact WAL: {:?}", e);
+        }
+        Ok(())
+    }
+
+    fn log_entry(&self, table: &str, key: &str) -> LogEntry {
+        let value = self
+            .tables
+            .get(table)
+            .and_then(|t| t.get(key).map(|v| v.value().clone()));
+        let expire_at = self
+            .expires
+            .get(table)
+            .and_then(|expires| expires.get(key).map(|v| *v));
+        LogEntry {
+            table: table.into(),
+            key: key.into(),
+            value,
+            expire_at: expire_at.unwrap_or_default(),
+            dropped: false,
+        }
+    }
+
+    // 所有的数据，用来生成快照
+    fn log_entries(&self) -> Vec<LogEntry> {
+        let now = now_ms();
+        let mut entries = Vec::new();
+        for table in self.tables.iter() {
+            let expires = self.expires.get(table.key());
+            for v in table.value().iter() {
+                let expire_at = expires
+                    .as_ref()
+                    .and_then(|expires| expires.get(v.key()).map(|v| *v))
+                    .unwrap_or_default();
+                if expire_at != 0 && expire_at <= now {
+                    continue;
+                }
+                entries.push(LogEntry {
+                    table: table.key().clone(),
+                    key: v.key().clone(),
+                    value: Some(v.value().clone()),
+                    expire_at,
+                    dropped: false,
+                });
+            }
+        }
+        entries
+    }
+
+    fn replay(&self, entry: LogEntry) {
+        let LogEntry {
+            table,
+            key,
+            value,
+            expire_at,
+            dropped,
+        } = entry;
+        if dropped {
+            self.tables.remove(&table);
+            self.expires.remove(&table);
+            self.index.remove(&table);
+            return;
+        }
+        self.persist(&table, &key);
+        let alive = expire_at == 0 || expire_at > now_ms();
+        match value {
+            Some(v) if alive => {
+                if expire_at > 0 {
+                    let expires = self.expires.entry(table.clone()).or_default();
+                    expires.insert(key.clone(), expire_at);
+                }
+                self.tables
+                    .entry(table.clone())
+                    .or_default()
+                    .insert(key.clone(), v);
+                self.index_add(&table, &key);
+            }
+            _ => {
+                if let Some(t) = self.tables.get(&table) {
+                    t.remove(&key);
+                }
+                self.index_remove(&table, &key);
+            }
+        }
+    }
+
+    // key 已经过期就删掉，返回 true。调用时不能持有 table 的引用
+    fn expire_if_needed(&self, table: &str, key: &str) -> bool {
+        let Some(expires) = self.expires.get(table) else {
+            return false;
+        };
+        let now = now_ms();
+        if expires.remove_if(key, |_, v| *v <= now).is_none() {
+            return false;
+        }
+        drop(expires);
+
+        if let Some(t) = self.tables.get(table) {
+            t.remove(key);
+        }
+        self.index_remove(table, key);
+        true
+    }
+
+    fn expire_table_if_needed(&self, table: &str) {
+        let keys: Vec<String> = match self.expires.get(table) {
+            Some(expires) => {
+                let now = now_ms();
+                expires
+                    .iter()
+                    .filter(|v| *v.value() <= now)
+                    .map(|v| v.key().clone())
+                    .collect()
+            }
+            None => return,
+        };
+        for key in keys {
+            self.expire_if_needed(table, &key);
+        }
+    }
+
+    fn contains_key(&self, table: &str, key: &str) -> bool {
+        self.tables.get(table).is_some_and(|t| t.contains_key(key))
+    }
+
+    fn persist(&self, table: &str, key: &str) {
+        if let Some(expires) = self.expires.get(table) {
+            expires.remove(key);
+        }
+    }
+
+    // 写入之前先设置过期时间，这样别的请求不会看到新的值却没有过期时间
+    fn set_deadline(&self, table: &str, key: &str, ttl: Option<Duration>) {
+        match ttl {
+            Some(ttl) => {
+                let expires = self.expires.entry(table.to_string()).or_default();
+                expires.insert(key.to_string(), deadline_ms(ttl));
+            }
+            None => self.persist(table, key),
+        }
+    }
 }
 
 impl Storage for MemTable {
     fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table.get(key).map(|v| v.value().clone()))
+        let _guard = self.read_table(table);
+        self.expire_if_needed(table, key);
+        Ok(self
+            .tables
+            .get(table)
+            .and_then(|t| t.get(key).map(|v| v.value().clone())))
     }
 
-    fn mget<T, K>(&self, table: &str, keys: T) -> Result<Vec<Kvpair>, KvError>
-    where
-        K: Into<String>,
-        T: IntoIterator<Item = K>,
-    {
-        let table = self.get_or_create_table(table);
+    fn mget(&self, table: &str, keys: &[String]) -> Result<Vec<Kvpair>, KvError> {
+        let _guard = self.read_table(table);
+        for key in keys {
+            self.expire_if_needed(table, key);
+        }
+        let Some(table) = self.tables.get(table) else {
+            return Ok(Vec::new());
+        };
         let mut res: Vec<Kvpair> = Vec::new();
         for key in keys {
             let cur = table
-                .get(key.into().as_str())
+                .get(key.as_str())
                 .map(|v| Kvpair::new(v.key(), v.value().clone()));
             if let Some(v) = cur {
                 res.push(v);
@@ -48,52 +326,119 @@ impl Storage for MemTable {
         Ok(res)
     }
 
-    fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table.insert(key, value))
+    fn set(
+        &self,
+        table: &str,
+        key: String,
+        value: Value,
+        ttl: Option<Duration>,
+    ) -> Result<Option<Value>, KvError> {
+        let _guard = self.read_table(table);
+        self.logged(table, &[&key], || {
+            self.expire_if_needed(table, &key);
+            self.set_deadline(table, &key, ttl);
+            let old = self.get_or_create_table(table).insert(key.clone(), value);
+            self.index_add(table, &key);
+            Ok(old)
+        })
     }
 
-    fn mset(&self, table: &str, items: Vec<Kvpair>) -> Result<bool, KvError> {
-        let table = self.get_or_create_table(table);
-        for Kvpair { key, value } in items {
-            let _ = table.insert(key, value.unwrap());
-        }
-        Ok(true)
+    fn mset(
+        &self,
+        table: &str,
+        items: Vec<Kvpair>,
+        ttl: Option<Duration>,
+    ) -> Result<bool, KvError> {
+        let _guard = self.read_table(table);
+        let keys: Vec<String> = items.iter().map(|v| v.key.clone()).collect();
+        self.logged(table, &keys, || {
+            for key in &keys {
+                self.set_deadline(table, key, ttl);
+            }
+            let t = self.get_or_create_table(table);
+            for Kvpair { key, value } in items {
+                let _ = t.insert(key, value.unwrap_or_default());
+            }
+            drop(t);
+            for key in &keys {
+                self.index_add(table, key);
+            }
+            Ok(true)
+        })
     }
 
     fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table.contains_key(key))
+        let _guard = self.read_table(table);
+        self.expire_if_needed(table, key);
+        Ok(self.contains_key(table, key))
+    }
+
+    fn mcontains(&self, table: &str, keys: &[String]) -> Result<Vec<Kvpair>, KvError> {
+        let _guard = self.read_table(table);
+        for key in keys {
+            self.expire_if_needed(table, key);
+        }
+        Ok(keys
+            .iter()
+            .map(|key| {
+                let exist = self.contains_key(table, key);
+                Kvpair::new(key, exist.into())
+            })
+            .collect())
     }
 
     fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table.remove(key).map(|(_k, v)| v))
+        let _guard = self.read_table(table);
+        self.logged(table, &[key], || {
+            self.expire_if_needed(table, key);
+            self.persist(table, key);
+            let old = self
+                .tables
+                .get(table)
+                .and_then(|t| t.remove(key).map(|(_k, v)| v));
+            self.index_remove(table, key);
+            Ok(old)
+        })
     }
 
-    fn mdel<T, K>(&self, table: &str, keys: T) -> Result<bool, KvError>
-    where
-        K: Into<String>,
-        T: IntoIterator<Item = K>,
-    {
-        let table = self.get_or_create_table(table);
-        for key in keys {
-            table.remove(&key.into()).unwrap();
-        }
-        Ok(true)
+    fn mdel(&self, table: &str, keys: &[String]) -> Result<bool, KvError> {
+        let _guard = self.read_table(table);
+        self.logged(table, keys, || {
+            for key in keys {
+                self.persist(table, key);
+            }
+            if let Some(t) = self.tables.get(table) {
+                for key in keys {
+                    t.remove(key);
+                }
+            }
+            for key in keys {
+                self.index_remove(table, key);
+            }
+            Ok(true)
+        })
     }
 
     fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
-        let table = self.get_or_create_table(table);
+        let _guard = self.read_table(table);
+        self.expire_table_if_needed(table);
+        let Some(table) = self.tables.get(table) else {
+            return Ok(Vec::new());
+        };
         Ok(table
             .iter()
             .map(|v| Kvpair::new(v.key(), v.value().clone()))
             .collect())
     }
 
-    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
+    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair> + Send>, KvError> {
+        let _guard = self.read_table(table);
+        self.expire_table_if_needed(table);
         // 查询出 dashMap
-        let table = self.get_or_create_table(table).clone();
+        let table = match self.tables.get(table) {
+            Some(table) => table.clone(),
+            None => DashMap::new(),
+        };
 
         // 返回迭代器
         // let res = table.into_iter().map(|v| v.into());
@@ -101,6 +446,285 @@ impl Storage for MemTable {
 
         Ok(Box::new(res))
     }
+
+    fn scan(
+        &self,
+        table: &str,
+        prefix: &str,
+        start: Bound<&str>,
+        end: Bound<&str>,
+        limit: usize,
+    ) -> Result<Vec<Kvpair>, KvError> {
+        let _guard = self.read_table(table);
+        let (Some(index), Some(t)) = (self.index.get(table), self.tables.get(table)) else {
+            return Ok(Vec::new());
+        };
+        // 持有索引的读锁时不能删除 key，过期的 key 直接跳过
+        let expires = self.expires.get(table);
+        let now = now_ms();
+        let expired = |key: &str| {
+            expires
+                .as_ref()
+                .and_then(|expires| expires.get(key).map(|v| *v <= now))
+                .unwrap_or(false)
+        };
+
+        let start = scan_start(prefix, start);
+        Ok(index
+            .range::<str, _>((start.as_ref().map(String::as_str), Bound::Unbounded))
+            .take_while(|key| key.starts_with(prefix) && before_end(key, end))
+            .filter(|key| !expired(key))
+            .filter_map(|key| t.get(key).map(|v| Kvpair::new(key, v.value().clone())))
+            .take(limit)
+            .collect())
+    }
+
+    fn tables(&self) -> Result<Vec<String>, KvError> {
+        let mut names: Vec<String> = self.tables.iter().map(|v| v.key().clone()).collect();
+        names.sort();
+        Ok(names)
+    }
+
+    fn drop_table(&self, table: &str) -> Result<bool, KvError> {
+        let _guard = self.table_lock(table).write_arc();
+        let wal = self.lock_wal();
+        let exists = self.tables.contains_key(table);
+        // 先写 WAL，写失败时 table 还在
+        if exists && let Some(mut wal) = wal {
+            let entry = LogEntry {
+                table: table.into(),
+                dropped: true,
+                ..Default::default()
+            };
+            self.append(&mut wal, &[entry])?;
+        }
+        self.expires.remove(table);
+        self.index.remove(table);
+        self.tables.remove(table);
+        Ok(exists)
+    }
+
+    fn len(&self, table: &str) -> Result<usize, KvError> {
+        let _guard = self.read_table(table);
+        self.expire_table_if_needed(table);
+        Ok(self.tables.get(table).map(|t| t.len()).unwrap_or_default())
+    }
+
+    fn incr(&self, table: &str, key: &str, delta: i64) -> Result<i64, KvError> {
+        let _guard = self.read_table(table);
+        self.logged(table, &[key], || {
+            self.expire_if_needed(table, key);
+            let t = self.get_or_create_table(table);
+            // entry 会锁住这个 key，读和写之间不会被其它写入插进来
+            let mut entry = t.entry(key.into()).or_insert_with(|| 0.into());
+            let v = add_integer(entry.value(), delta)?;
+            *entry = v.into();
+            drop(entry);
+            drop(t);
+            self.index_add(table, key);
+            Ok(v)
+        })
+    }
+
+    fn incr_float(&self, table: &str, key: &str, delta: f64) -> Result<f64, KvError> {
+        let _guard = self.read_table(table);
+        self.logged(table, &[key], || {
+            self.expire_if_needed(table, key);
+            let t = self.get_or_create_table(table);
+            let mut entry = t.entry(key.into()).or_insert_with(|| 0.into());
+            let v = add_float(entry.value(), delta)?;
+            *entry = v.into();
+            drop(entry);
+            drop(t);
+            self.index_add(table, key);
+            Ok(v)
+        })
+    }
+
+    fn compare_and_swap(
+        &self,
+        table: &str,
+        key: &str,
+        expected: Option<Value>,
+        new: Value,
+    ) -> Result<(bool, Option<Value>), KvError> {
+        let _guard = self.read_table(table);
+        self.logged(table, &[key], || {
+            self.expire_if_needed(table, key);
+            // 期望 key 已经存在时，table 不存在肯定失败，不用创建 table
+            if expected.is_some() && !self.tables.contains_key(table) {
+                return Ok((false, None));
+            }
+            let t = self.get_or_create_table(table);
+            let result = match (t.entry(key.into()), expected) {
+                (Entry::Occupied(mut entry), Some(expected)) if *entry.get() == expected => {
+                    entry.insert(new.clone());
+                    (true, Some(new))
+                }
+                (Entry::Vacant(entry), None) => {
+                    entry.insert(new.clone());
+                    (true, Some(new))
+                }
+                (Entry::Occupied(entry), _) => (false, Some(entry.get().clone())),
+                (Entry::Vacant(_), _) => (false, None),
+            };
+            drop(t);
+
+            // 和 set 一样，写入成功后清掉过期时间
+            if result.0 {
+                self.persist(table, key);
+                self.index_add(table, key);
+            }
+            Ok(result)
+        })
+    }
+
+    fn expire(&self, table: &str, key: &str, ttl: Option<Duration>) -> Result<bool, KvError> {
+        let _guard = self.read_table(table);
+        self.logged(table, &[key], || {
+            self.expire_if_needed(table, key);
+            if !self.contains_key(table, key) {
+                return Ok(false);
+            }
+            self.set_deadline(table, key, ttl);
+            Ok(true)
+        })
+    }
+
+    fn ttl(&self, table: &str, key: &str) -> Result<Option<Duration>, KvError> {
+        let _guard = self.read_table(table);
+        self.expire_if_needed(table, key);
+        if !self.contains_key(table, key) {
+            return Err(KvError::NotFound(table.into(), key.into()));
+        }
+        let deadline = self
+            .expires
+            .get(table)
+            .and_then(|expires| expires.get(key).map(|v| *v));
+        Ok(deadline.map(|v| Duration::from_millis(v.saturating_sub(now_ms()))))
+    }
+
+    fn purge_expired(&self) -> Result<usize, KvError> {
+        let now = now_ms();
+        let mut expired = Vec::new();
+        for expires in self.expires.iter() {
+            for v in expires.value().iter() {
+                if *v.value() <= now {
+                    expired.push((expires.key().clone(), v.key().clone()));
+                }
+            }
+        }
+
+        let mut count = 0;
+        for (table, key) in expired {
+            let _guard = self.read_table(&table);
+            if self.expire_if_needed(&table, &key) {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    fn transaction(
+        &self,
+        tables: &[String],
+        f: &TransactionFn<'_>,
+    ) -> Result<Vec<CommandResponse>, KvError> {
+        // 按名字排序后依次加写锁，避免两个事务互相等待
+        let mut tables = tables.to_vec();
+        tables.sort();
+        tables.dedup();
+        let _guards: Vec<_> = tables
+            .iter()
+            .map(|name| self.table_lock(name).write_arc())
+            .collect();
+
+        let mut tx = MemTx {
+            store: self,
+            tables: &tables,
+            writes: HashMap::new(),
+        };
+        let res = f(&mut tx)?;
+        tx.commit()?;
+        Ok(res)
+    }
+}
+
+// MemTable 的事务，写入先缓存起来，提交时再一起写到 table 里
+struct MemTx<'a> {
+    store: &'a MemTable,
+    tables: &'a [String],
+    // None 表示删除
+    writes: HashMap<(String, String), Option<Value>>,
+}
+
+impl MemTx<'_> {
+    fn check_table(&self, table: &str) -> Result<(), KvError> {
+        match self.tables.iter().any(|v| v == table) {
+            true => Ok(()),
+            false => Err(KvError::Internal(format!(
+                "table {} is not locked by the transaction",
+                table
+            ))),
+        }
+    }
+
+    // 事务里的修改一次写到 WAL 里
+    fn commit(self) -> Result<(), KvError> {
+        let store = self.store;
+        let keys: Vec<_> = self
+            .writes
+            .keys()
+            .map(|(table, key)| (table.as_str(), key.as_str()))
+            .collect();
+        store.logged_keys(&keys, || {
+            for ((table, key), value) in &self.writes {
+                store.persist(table, key);
+                match value {
+                    Some(v) => {
+                        store
+                            .get_or_create_table(table)
+                            .insert(key.clone(), v.clone());
+                        store.index_add(table, key);
+                    }
+                    None => {
+                        if let Some(t) = store.tables.get(table) {
+                            t.remove(key);
+                        }
+                        store.index_remove(table, key);
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+impl StorageTx for MemTx<'_> {
+    fn get(&mut self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        self.check_table(table)?;
+        if let Some(v) = self.writes.get(&(table.to_string(), key.to_string())) {
+            return Ok(v.clone());
+        }
+        self.store.expire_if_needed(table, key);
+        Ok(self
+            .store
+            .tables
+            .get(table)
+            .and_then(|t| t.get(key).map(|v| v.value().clone())))
+    }
+
+    fn set(&mut self, table: &str, key: &str, value: Value) -> Result<Option<Value>, KvError> {
+        let old = self.get(table, key)?;
+        self.writes.insert((table.into(), key.into()), Some(value));
+        Ok(old)
+    }
+
+    fn del(&mut self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let old = self.get(table, key)?;
+        self.writes.insert((table.into(), key.into()), None);
+        Ok(old)
+    }
 }
 
 impl From<(String, Value)> for Kvpair {
@@ -129,3 +753,45 @@ where
         self.data.next().map(|v| v.into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn failed_wal_write_should_roll_back() {
+        let dir = tempdir().unwrap();
+        let options = WalOptions::new(dir.path());
+        let store = MemTable::open(options.clone()).unwrap();
+        store.set("t1", "k1".into(), "v1".into(), None).unwrap();
+        store
+            .expire("t1", "k1", Some(Duration::from_secs(60)))
+            .unwrap();
+
+        store.wal.as_ref().unwrap().lock().break_file();
+        assert!(store.set("t1", "k1".into(), "v2".into(), None).is_err());
+        assert!(store.incr("t1", "k2", 1).is_err());
+        assert!(store.del("t1", "k1").is_err());
+        assert!(store.set("t2", "k1".into(), "v1".into(), None).is_err());
+        assert!(store.drop_table("t1").is_err());
+
+        // 内存里的数据和 WAL 里的一样，都是写失败之前的状态
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+        assert!(store.ttl("t1", "k1").unwrap().is_some());
+        assert_eq!(store.get("t1", "k2"), Ok(None));
+        assert_eq!(store.tables(), Ok(vec!["t1".into()]));
+        assert_eq!(
+            store
+                .scan("t1", "", Bound::Unbounded, Bound::Unbounded, 10)
+                .unwrap()
+                .len(),
+            1
+        );
+        drop(store);
+
+        let store = MemTable::open(options).unwrap();
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+        assert_eq!(store.tables(), Ok(vec!["t1".into()]));
+    }
+}
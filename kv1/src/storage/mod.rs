@@ -1,60 +1,277 @@
 mod memory;
 mod sleddb;
+mod wal;
 
-use crate::{KvError, Kvpair, Value};
+use crate::{CommandResponse, KvError, Kvpair, Value, value};
 #[allow(unused_imports)]
 pub use memory::MemTable;
 pub use sleddb::SledDb;
+use std::ops::Bound;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+pub use wal::{FsyncPolicy, WalOptions};
 
-pub trait Storage {
+// Storage 要能作为 trait object 使用（比如 Arc<dyn Storage>），所以不能有泛型方法
+pub trait Storage: Send + Sync + 'static {
     fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError>;
 
-    fn mget<T, K>(&self, table: &str, keys: T) -> Result<Vec<Kvpair>, KvError>
-    where
-        K: Into<String>,
-        T: IntoIterator<Item = K>;
+    fn mget(&self, table: &str, keys: &[String]) -> Result<Vec<Kvpair>, KvError>;
 
-    fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, KvError>;
+    // 写入的同时设置过期时间，None 表示不过期；之前的过期时间会被清掉
+    fn set(
+        &self,
+        table: &str,
+        key: String,
+        value: Value,
+        ttl: Option<Duration>,
+    ) -> Result<Option<Value>, KvError>;
 
-    fn mset(&self, table: &str, items: Vec<Kvpair>) -> Result<bool, KvError>;
+    fn mset(&self, table: &str, items: Vec<Kvpair>, ttl: Option<Duration>)
+    -> Result<bool, KvError>;
 
     fn contains(&self, table: &str, key: &str) -> Result<bool, KvError>;
 
+    fn mcontains(&self, table: &str, keys: &[String]) -> Result<Vec<Kvpair>, KvError>;
+
     fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError>;
 
-    fn mdel<T, K>(&self, table: &str, keys: T) -> Result<bool, KvError>
-    where
-        K: Into<String>,
-        T: IntoIterator<Item = K>;
+    fn mdel(&self, table: &str, keys: &[String]) -> Result<bool, KvError>;
 
     fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError>;
 
-    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError>;
+    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair> + Send>, KvError>;
+
+    // 按 key 的顺序返回以 prefix 开头、在 start 和 end 之间的数据，最多 limit 个
+    fn scan(
+        &self,
+        table: &str,
+        prefix: &str,
+        start: Bound<&str>,
+        end: Bound<&str>,
+        limit: usize,
+    ) -> Result<Vec<Kvpair>, KvError>;
+
+    // 所有 table 的名字，按顺序排好
+    fn tables(&self) -> Result<Vec<String>, KvError>;
+
+    // 删除整个 table，table 不存在时返回 false
+    fn drop_table(&self, table: &str) -> Result<bool, KvError>;
+
+    // table 里没有过期的 key 的个数，table 不存在时返回 0
+    fn len(&self, table: &str) -> Result<usize, KvError>;
+
+    // 原子地把整数加上 delta，key 不存在时从 0 开始
+    fn incr(&self, table: &str, key: &str, delta: i64) -> Result<i64, KvError>;
+
+    // 原子地把数字加上浮点数 delta，整数会被转成浮点数
+    fn incr_float(&self, table: &str, key: &str, delta: f64) -> Result<f64, KvError>;
+
+    // 当前值等于 expected（None 表示不存在）时才写入 new，返回是否成功以及当前的值
+    fn compare_and_swap(
+        &self,
+        table: &str,
+        key: &str,
+        expected: Option<Value>,
+        new: Value,
+    ) -> Result<(bool, Option<Value>), KvError>;
+
+    // 设置过期时间，None 表示取消过期；key 不存在时返回 false
+    fn expire(&self, table: &str, key: &str, ttl: Option<Duration>) -> Result<bool, KvError>;
+
+    // 剩余的过期时间，None 表示永不过期；key 不存在时返回 NotFound
+    fn ttl(&self, table: &str, key: &str) -> Result<Option<Duration>, KvError>;
+
+    // 清理所有已过期的 key，返回清理的数量
+    fn purge_expired(&self) -> Result<usize, KvError>;
+
+    // 在一个事务里执行 f，f 返回错误时所有的修改都会回滚
+    // tables 是事务里会用到的所有 table；f 可能会因为冲突被执行多次
+    fn transaction(
+        &self,
+        tables: &[String],
+        f: &TransactionFn<'_>,
+    ) -> Result<Vec<CommandResponse>, KvError>;
 }
 
-// pub struct Service {
-//     pub store: Arc<Box<dyn Storage>>,
-// }
-//
-// impl Service {
-//     pub fn new(s: Box<dyn Storage>) -> Self {
-//         Self { store: Arc::new(s) }
-//     }
-// }
-//
-// impl Clone for Service {
-//     fn clone(&self) -> Self {
-//         Self {
-//             store: Arc::clone(&self.store),
-//         }
-//     }
-// }
+pub type TransactionFn<'a> =
+    dyn Fn(&mut dyn StorageTx) -> Result<Vec<CommandResponse>, KvError> + 'a;
+
+// 事务里能做的操作，写入在事务提交时才真正生效，读能读到事务里之前的写入
+pub trait StorageTx {
+    fn get(&mut self, table: &str, key: &str) -> Result<Option<Value>, KvError>;
+
+    // 和 Storage::set 一样会清掉过期时间，返回旧的值
+    fn set(&mut self, table: &str, key: &str, value: Value) -> Result<Option<Value>, KvError>;
+
+    fn del(&mut self, table: &str, key: &str) -> Result<Option<Value>, KvError>;
+}
+
+pub(crate) fn add_integer(v: &Value, delta: i64) -> Result<i64, KvError> {
+    let current: i64 = v.clone().try_into()?;
+    current
+        .checked_add(delta)
+        .ok_or_else(|| KvError::InvalidCommand(format!("{} + {} overflows", current, delta)))
+}
+
+pub(crate) fn add_float(v: &Value, delta: f64) -> Result<f64, KvError> {
+    let current = match v.value {
+        Some(value::Value::Integer(i)) => i as f64,
+        _ => f64::try_from(v.clone())?,
+    };
+    Ok(current + delta)
+}
+
+// 扫描的起点：prefix 和 start 里靠后的那个
+pub(crate) fn scan_start(prefix: &str, start: Bound<&str>) -> Bound<String> {
+    match start {
+        Bound::Included(s) | Bound::Excluded(s) if s >= prefix => start.map(|s| s.to_string()),
+        _ => Bound::Included(prefix.to_string()),
+    }
+}
+
+// key 还没有超过扫描的终点
+pub(crate) fn before_end(key: &str, end: Bound<&str>) -> bool {
+    match end {
+        Bound::Included(e) => key <= e,
+        Bound::Excluded(e) => key < e,
+        Bound::Unbounded => true,
+    }
+}
+
+// 过期时间点用毫秒时间戳表示，sled 里也要持久化，所以不用 Instant
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+pub(crate) fn deadline_ms(ttl: Duration) -> u64 {
+    now_ms().saturating_add(ttl.as_millis() as u64)
+}
+
+// 运行时才决定用哪个 backend 时，可以用 Arc<dyn Storage> 作为 Store
+impl<S: Storage + ?Sized> Storage for Arc<S> {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        (**self).get(table, key)
+    }
+
+    fn mget(&self, table: &str, keys: &[String]) -> Result<Vec<Kvpair>, KvError> {
+        (**self).mget(table, keys)
+    }
+
+    fn set(
+        &self,
+        table: &str,
+        key: String,
+        value: Value,
+        ttl: Option<Duration>,
+    ) -> Result<Option<Value>, KvError> {
+        (**self).set(table, key, value, ttl)
+    }
+
+    fn mset(
+        &self,
+        table: &str,
+        items: Vec<Kvpair>,
+        ttl: Option<Duration>,
+    ) -> Result<bool, KvError> {
+        (**self).mset(table, items, ttl)
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        (**self).contains(table, key)
+    }
+
+    fn mcontains(&self, table: &str, keys: &[String]) -> Result<Vec<Kvpair>, KvError> {
+        (**self).mcontains(table, keys)
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        (**self).del(table, key)
+    }
+
+    fn mdel(&self, table: &str, keys: &[String]) -> Result<bool, KvError> {
+        (**self).mdel(table, keys)
+    }
+
+    fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        (**self).get_all(table)
+    }
+
+    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair> + Send>, KvError> {
+        (**self).get_iter(table)
+    }
+
+    fn scan(
+        &self,
+        table: &str,
+        prefix: &str,
+        start: Bound<&str>,
+        end: Bound<&str>,
+        limit: usize,
+    ) -> Result<Vec<Kvpair>, KvError> {
+        (**self).scan(table, prefix, start, end, limit)
+    }
+
+    fn tables(&self) -> Result<Vec<String>, KvError> {
+        (**self).tables()
+    }
+
+    fn drop_table(&self, table: &str) -> Result<bool, KvError> {
+        (**self).drop_table(table)
+    }
+
+    fn len(&self, table: &str) -> Result<usize, KvError> {
+        (**self).len(table)
+    }
+
+    fn incr(&self, table: &str, key: &str, delta: i64) -> Result<i64, KvError> {
+        (**self).incr(table, key, delta)
+    }
+
+    fn incr_float(&self, table: &str, key: &str, delta: f64) -> Result<f64, KvError> {
+        (**self).incr_float(table, key, delta)
+    }
+
+    fn compare_and_swap(
+        &self,
+        table: &str,
+        key: &str,
+        expected: Option<Value>,
+        new: Value,
+    ) -> Result<(bool, Option<Value>), KvError> {
+        (**self).compare_and_swap(table, key, expected, new)
+    }
+
+    fn expire(&self, table: &str, key: &str, ttl: Option<Duration>) -> Result<bool, KvError> {
+        (**self).expire(table, key, ttl)
+    }
+
+    fn ttl(&self, table: &str, key: &str) -> Result<Option<Duration>, KvError> {
+        (**self).ttl(table, key)
+    }
+
+    fn purge_expired(&self) -> Result<usize, KvError> {
+        (**self).purge_expired()
+    }
+
+    fn transaction(
+        &self,
+        tables: &[String],
+        f: &TransactionFn<'_>,
+    ) -> Result<Vec<CommandResponse>, KvError> {
+        (**self).transaction(tables, f)
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::storage::memory::MemTable;
     use crate::storage::sleddb::SledDb;
+    use std::sync::Arc;
+    use std::thread::{sleep, spawn};
     use tempfile::tempdir;
 
     #[test]
@@ -87,6 +304,63 @@ mod tests {
         test_m_del(store);
     }
 
+    #[test]
+    fn mem_table_m_contains_should_work() {
+        let store = MemTable::new();
+        test_m_contains(store);
+    }
+
+    #[test]
+    fn mem_table_expire_should_work() {
+        let store = MemTable::new();
+        test_expire(store);
+    }
+
+    #[test]
+    fn mem_table_purge_expired_should_work() {
+        let store = MemTable::new();
+        test_purge_expired(store);
+    }
+
+    #[test]
+    fn mem_table_incr_should_work() {
+        let store = MemTable::new();
+        test_incr(store);
+    }
+
+    #[test]
+    fn mem_table_concurrent_incr_should_work() {
+        let store = Arc::new(MemTable::new());
+        test_concurrent_incr(store);
+    }
+
+    #[test]
+    fn mem_table_compare_and_swap_should_work() {
+        let store = MemTable::new();
+        test_compare_and_swap(store);
+    }
+
+    #[test]
+    fn mem_table_scan_should_work() {
+        let store = MemTable::new();
+        test_scan(store);
+    }
+
+    #[test]
+    fn mem_table_tables_should_work() {
+        let store = MemTable::new();
+        test_tables(store);
+    }
+
+    #[test]
+    fn mem_table_with_wal_should_work() {
+        let dir = tempdir().unwrap();
+        let store = MemTable::open(WalOptions::new(dir.path())).unwrap();
+        test_basic_interface(store);
+        let store = MemTable::open(WalOptions::new(dir.path())).unwrap();
+        test_concurrent_incr(Arc::new(store));
+    }
+
     #[test]
     fn mem_table_get_iter_should_work() {
         let store = MemTable::new();
@@ -94,10 +368,10 @@ mod tests {
     }
 
     fn test_basic_interface(store: impl Storage) {
-        let v = store.set("t1", "hello".into(), "world".into());
+        let v = store.set("t1", "hello".into(), "world".into(), None);
         assert!(v.unwrap().is_none());
 
-        let v = store.set("t1", "hello".into(), "world1".into());
+        let v = store.set("t1", "hello".into(), "world1".into(), None);
         assert_eq!(v, Ok(Some("world".into())));
 
         let v = store.get("t1", "hello");
@@ -119,8 +393,8 @@ mod tests {
     }
 
     fn test_get_iter(store: impl Storage) {
-        store.set("t2", "k1".into(), "v1".into()).unwrap();
-        store.set("t2", "k2".into(), "v2".into()).unwrap();
+        store.set("t2", "k1".into(), "v1".into(), None).unwrap();
+        store.set("t2", "k2".into(), "v2".into(), None).unwrap();
 
         let mut data: Vec<_> = store.get_iter("t2").unwrap().collect();
         data.sort_by(|a, b| a.partial_cmp(b).unwrap());
@@ -134,8 +408,8 @@ mod tests {
     }
 
     fn test_get_all(store: impl Storage) {
-        store.set("t2", "k1".into(), "v1".into()).unwrap();
-        store.set("t2", "k2".into(), "v2".into()).unwrap();
+        store.set("t2", "k1".into(), "v1".into(), None).unwrap();
+        store.set("t2", "k2".into(), "v2".into(), None).unwrap();
         let mut data = store.get_all("t2").unwrap();
         data.sort_by(|a, b| a.partial_cmp(b).unwrap());
         assert_eq!(
@@ -148,10 +422,10 @@ mod tests {
     }
 
     fn test_m_get(store: impl Storage) {
-        store.set("t2", "k1".into(), "v1".into()).unwrap();
-        store.set("t2", "k2".into(), "v2".into()).unwrap();
-        store.set("t2", "k3".into(), "v3".into()).unwrap();
-        let mut data = store.mget("t2", vec!["k1", "k2", "k3"]).unwrap();
+        store.set("t2", "k1".into(), "v1".into(), None).unwrap();
+        store.set("t2", "k2".into(), "v2".into(), None).unwrap();
+        store.set("t2", "k3".into(), "v3".into(), None).unwrap();
+        let mut data = store.mget("t2", &keys(&["k1", "k2", "k3"])).unwrap();
         data.sort_by(|a, b| a.partial_cmp(b).unwrap());
         assert_eq!(
             data,
@@ -162,7 +436,7 @@ mod tests {
             ]
         );
 
-        let mut data = store.mget("t2", vec!["k1", "k2", "k3", "k4"]).unwrap();
+        let mut data = store.mget("t2", &keys(&["k1", "k2", "k3", "k4"])).unwrap();
         data.sort_by(|a, b| a.partial_cmp(b).unwrap());
         assert_eq!(
             data,
@@ -183,10 +457,11 @@ mod tests {
                     Kvpair::new("k2", "v2".into()),
                     Kvpair::new("k3", "v3".into()),
                 ],
+                None,
             )
             .unwrap();
 
-        let mut data = store.mget("t1", vec!["k1", "k2", "k3"]).unwrap();
+        let mut data = store.mget("t1", &keys(&["k1", "k2", "k3"])).unwrap();
         data.sort_by(|a, b| a.partial_cmp(b).unwrap());
         assert_eq!(
             data,
@@ -209,13 +484,14 @@ mod tests {
                     Kvpair::new("k4", "v4".into()),
                     Kvpair::new("k5", "v5".into()),
                 ],
+                None,
             )
             .unwrap();
 
-        store.mdel("t1", ["k1", "k2"]).unwrap();
+        store.mdel("t1", &keys(&["k1", "k2"])).unwrap();
 
         let mut data = store
-            .mget("t1", vec!["k1", "k2", "k3", "k4", "k5"])
+            .mget("t1", &keys(&["k1", "k2", "k3", "k4", "k5"]))
             .unwrap();
         data.sort_by(|a, b| a.partial_cmp(b).unwrap());
         assert_eq!(
@@ -228,6 +504,301 @@ mod tests {
         );
     }
 
+    fn test_m_contains(store: impl Storage) {
+        store.set("t1", "k1".into(), "v1".into(), None).unwrap();
+        store.set("t1", "k3".into(), "v3".into(), None).unwrap();
+
+        let data = store.mcontains("t1", &keys(&["k1", "k2", "k3"])).unwrap();
+        assert_eq!(
+            data,
+            vec![
+                Kvpair::new("k1", true.into()),
+                Kvpair::new("k2", false.into()),
+                Kvpair::new("k3", true.into()),
+            ]
+        );
+
+        let data = store.mcontains("t2", &keys(&["k1"])).unwrap();
+        assert_eq!(data, vec![Kvpair::new("k1", false.into())]);
+    }
+
+    fn test_expire(store: impl Storage) {
+        store.set("t1", "k1".into(), "v1".into(), None).unwrap();
+        store.set("t1", "k2".into(), "v2".into(), None).unwrap();
+        assert_eq!(store.ttl("t1", "k1"), Ok(None));
+
+        let ttl = Duration::from_millis(50);
+        assert_eq!(store.expire("t1", "k1", Some(ttl)), Ok(true));
+        assert_eq!(store.expire("t1", "k3", Some(ttl)), Ok(false));
+        let remaining = store.ttl("t1", "k1").unwrap().unwrap();
+        assert!(remaining <= ttl);
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+
+        // k2 设置后又取消了过期
+        store.expire("t1", "k2", Some(ttl)).unwrap();
+        store.expire("t1", "k2", None).unwrap();
+        assert_eq!(store.ttl("t1", "k2"), Ok(None));
+
+        sleep(Duration::from_millis(80));
+        assert_eq!(store.get("t1", "k1"), Ok(None));
+        assert_eq!(store.contains("t1", "k1"), Ok(false));
+        assert!(matches!(
+            store.ttl("t1", "k1"),
+            Err(KvError::NotFound(_, _))
+        ));
+        assert_eq!(
+            store.get_all("t1"),
+            Ok(vec![Kvpair::new("k2", "v2".into())])
+        );
+        assert_eq!(store.expire("t1", "k1", Some(ttl)), Ok(false));
+
+        // 重新 set 会清掉之前的过期时间
+        store.set("t1", "k3".into(), "v3".into(), None).unwrap();
+        store
+            .expire("t1", "k3", Some(Duration::from_millis(10)))
+            .unwrap();
+        store.set("t1", "k3".into(), "v3".into(), None).unwrap();
+        sleep(Duration::from_millis(30));
+        assert_eq!(store.get("t1", "k3"), Ok(Some("v3".into())));
+        assert_eq!(store.ttl("t1", "k3"), Ok(None));
+
+        // 写入时直接带上过期时间
+        let ttl = Some(Duration::from_millis(10));
+        store.set("t1", "k4".into(), "v4".into(), ttl).unwrap();
+        let pairs = vec![Kvpair::new("k5", 5.into()), Kvpair::new("k6", 6.into())];
+        store.mset("t1", pairs, ttl).unwrap();
+        assert!(store.ttl("t1", "k4").unwrap().is_some());
+        assert!(store.ttl("t1", "k6").unwrap().is_some());
+        sleep(Duration::from_millis(30));
+        let keys = keys(&["k4", "k5", "k6"]);
+        assert_eq!(store.mget("t1", &keys), Ok(vec![]));
+        // 已经过期的旧值不返回
+        assert_eq!(store.set("t1", "k4".into(), "v4".into(), None), Ok(None));
+    }
+
+    fn test_purge_expired(store: impl Storage) {
+        store
+            .mset(
+                "t1",
+                vec![
+                    Kvpair::new("k1", "v1".into()),
+                    Kvpair::new("k2", "v2".into()),
+                    Kvpair::new("k3", "v3".into()),
+                ],
+                None,
+            )
+            .unwrap();
+        let ttl = Some(Duration::from_millis(10));
+        store.expire("t1", "k1", ttl).unwrap();
+        store.expire("t1", "k2", ttl).unwrap();
+        store
+            .expire("t1", "k3", Some(Duration::from_secs(60)))
+            .unwrap();
+
+        sleep(Duration::from_millis(30));
+        assert_eq!(store.purge_expired(), Ok(2));
+        assert_eq!(store.purge_expired(), Ok(0));
+
+        let data: Vec<_> = store.get_iter("t1").unwrap().collect();
+        assert_eq!(data, vec![Kvpair::new("k3", "v3".into())]);
+    }
+
+    fn test_incr(store: impl Storage) {
+        assert_eq!(store.incr("t1", "counter", 5), Ok(5));
+        assert_eq!(store.incr("t1", "counter", -2), Ok(3));
+        assert_eq!(store.get("t1", "counter"), Ok(Some(3.into())));
+
+        assert_eq!(store.incr_float("t1", "f", 1.5), Ok(1.5));
+        assert_eq!(store.incr_float("t1", "f", 0.25), Ok(1.75));
+        // 整数可以按浮点数累加，反过来不行
+        assert_eq!(store.incr_float("t1", "counter", 0.5), Ok(3.5));
+        assert_eq!(store.get("t1", "counter"), Ok(Some(3.5.into())));
+        assert!(matches!(
+            store.incr("t1", "counter", 1),
+            Err(KvError::ConvertError(_, _))
+        ));
+
+        store.set("t1", "name".into(), "tyr".into(), None).unwrap();
+        assert!(matches!(
+            store.incr("t1", "name", 1),
+            Err(KvError::ConvertError(_, _))
+        ));
+        assert!(matches!(
+            store.incr_float("t1", "name", 1.0),
+            Err(KvError::ConvertError(_, _))
+        ));
+        assert_eq!(store.get("t1", "name"), Ok(Some("tyr".into())));
+
+        store
+            .set("t1", "max".into(), i64::MAX.into(), None)
+            .unwrap();
+        assert!(store.incr("t1", "max", 1).is_err());
+    }
+
+    fn test_compare_and_swap(store: impl Storage) {
+        // 不存在时才写入
+        let v1: Value = "v1".into();
+        let v2: Value = "v2".into();
+        assert_eq!(
+            store.compare_and_swap("t1", "k1", None, v1.clone()),
+            Ok((true, Some(v1.clone())))
+        );
+        assert_eq!(
+            store.compare_and_swap("t1", "k1", None, v2.clone()),
+            Ok((false, Some(v1.clone())))
+        );
+
+        // 期望值不对时不写入，返回当前的值
+        assert_eq!(
+            store.compare_and_swap("t1", "k1", Some(v2.clone()), v2.clone()),
+            Ok((false, Some(v1.clone())))
+        );
+        assert_eq!(
+            store.compare_and_swap("t1", "k1", Some(v1.clone()), v2.clone()),
+            Ok((true, Some(v2.clone())))
+        );
+        assert_eq!(store.get("t1", "k1"), Ok(Some(v2.clone())));
+
+        assert_eq!(
+            store.compare_and_swap("t1", "k2", Some(v1.clone()), v2.clone()),
+            Ok((false, None))
+        );
+        assert_eq!(store.get("t1", "k2"), Ok(None));
+
+        // 写入成功后会清掉过期时间
+        store
+            .expire("t1", "k1", Some(Duration::from_secs(10)))
+            .unwrap();
+        store
+            .compare_and_swap("t1", "k1", None, v1.clone())
+            .unwrap();
+        assert!(store.ttl("t1", "k1").unwrap().is_some());
+        store
+            .compare_and_swap("t1", "k1", store.get("t1", "k1").unwrap(), v1.clone())
+            .unwrap();
+        assert_eq!(store.ttl("t1", "k1"), Ok(None));
+
+        // 过期的 key 当作不存在
+        store
+            .set(
+                "t1",
+                "k3".into(),
+                v1.clone(),
+                Some(Duration::from_millis(1)),
+            )
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(
+            store.compare_and_swap("t1", "k3", None, v2.clone()),
+            Ok((true, Some(v2)))
+        );
+        assert_eq!(store.ttl("t1", "k3"), Ok(None));
+
+        // 两种存储都按 Value 比较，-0.0 和 0.0 相等，虽然编码不一样
+        store.set("t1", "k4".into(), 0.0.into(), None).unwrap();
+        assert_eq!(
+            store.compare_and_swap("t1", "k4", Some((-0.0).into()), v1.clone()),
+            Ok((true, Some(v1)))
+        );
+    }
+
+    fn test_scan(store: impl Storage) {
+        let pairs = ["a1", "a2", "a3", "b1", "b2", "c1"]
+            .iter()
+            .map(|k| Kvpair::new(*k, "v".into()))
+            .collect();
+        store.mset("t1", pairs, None).unwrap();
+        store.set("t2", "a0".into(), "v".into(), None).unwrap();
+
+        let scan = |prefix, start, end, limit| -> Vec<String> {
+            let pairs = store.scan("t1", prefix, start, end, limit).unwrap();
+            pairs.into_iter().map(|v| v.key).collect()
+        };
+        use Bound::*;
+        assert_eq!(scan("a", Unbounded, Unbounded, 10), ["a1", "a2", "a3"]);
+        assert_eq!(scan("a", Excluded("a1"), Unbounded, 1), ["a2"]);
+        assert_eq!(scan("", Included("a3"), Excluded("b2"), 10), ["a3", "b1"]);
+        assert_eq!(scan("b", Included("a"), Included("b2"), 10), ["b1", "b2"]);
+        assert!(scan("d", Unbounded, Unbounded, 10).is_empty());
+        assert!(
+            store
+                .scan("t3", "", Unbounded, Unbounded, 10)
+                .unwrap()
+                .is_empty()
+        );
+
+        // 删除和过期的 key 不会被扫描到
+        store.del("t1", "a2").unwrap();
+        store
+            .expire("t1", "b1", Some(Duration::from_millis(10)))
+            .unwrap();
+        sleep(Duration::from_millis(30));
+        assert_eq!(scan("", Unbounded, Unbounded, 10), ["a1", "a3", "b2", "c1"]);
+    }
+
+    fn test_tables(store: impl Storage) {
+        // 读不会创建 table
+        assert_eq!(store.get("t1", "k1"), Ok(None));
+        assert_eq!(store.get_all("t1"), Ok(vec![]));
+        assert_eq!(store.contains("t1", "k1"), Ok(false));
+        assert_eq!(store.del("t1", "k1"), Ok(None));
+        assert_eq!(store.expire("t1", "k1", None), Ok(false));
+        assert_eq!(
+            store.compare_and_swap("t1", "k1", Some("v".into()), "v".into()),
+            Ok((false, None))
+        );
+        assert_eq!(store.tables(), Ok(vec![]));
+
+        // key 里可以有 ':'
+        store.set("t2", "a:b".into(), "v1".into(), None).unwrap();
+        store
+            .mset(
+                "t1",
+                vec![Kvpair::new("k1", 1.into()), Kvpair::new("k2", 2.into())],
+                None,
+            )
+            .unwrap();
+        assert_eq!(store.tables(), Ok(vec!["t1".into(), "t2".into()]));
+        assert_eq!(
+            store.get_all("t2"),
+            Ok(vec![Kvpair::new("a:b", "v1".into())])
+        );
+        assert_eq!(store.len("t1"), Ok(2));
+        assert_eq!(store.len("t3"), Ok(0));
+
+        store
+            .expire("t1", "k1", Some(Duration::from_millis(10)))
+            .unwrap();
+        sleep(Duration::from_millis(30));
+        assert_eq!(store.len("t1"), Ok(1));
+
+        assert_eq!(store.drop_table("t1"), Ok(true));
+        assert_eq!(store.drop_table("t1"), Ok(false));
+        assert_eq!(store.tables(), Ok(vec!["t2".into()]));
+        assert_eq!(store.get("t1", "k2"), Ok(None));
+
+        // 删除之后可以重新创建，之前的数据不会留下来
+        store.set("t1", "k3".into(), 3.into(), None).unwrap();
+        assert_eq!(store.get_all("t1"), Ok(vec![Kvpair::new("k3", 3.into())]));
+    }
+
+    fn test_concurrent_incr<S: Storage>(store: Arc<S>) {
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let store = store.clone();
+                spawn(move || {
+                    for _ in 0..100 {
+                        store.incr("t1", "counter", 1).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(store.get("t1", "counter"), Ok(Some(800.into())));
+    }
+
     #[test]
     fn sleddb_basic_interface_should_work() {
         let dir = tempdir().unwrap();
@@ -248,4 +819,135 @@ mod tests {
         let store = SledDb::new(dir);
         test_get_iter(store);
     }
+
+    #[test]
+    fn sleddb_m_get_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_m_get(store);
+    }
+
+    #[test]
+    fn sleddb_m_set_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_m_set(store);
+    }
+
+    #[test]
+    fn sleddb_m_del_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_m_del(store);
+    }
+
+    #[test]
+    fn sleddb_m_contains_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_m_contains(store);
+    }
+
+    #[test]
+    fn sleddb_expire_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_expire(store);
+    }
+
+    #[test]
+    fn sleddb_purge_expired_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_purge_expired(store);
+    }
+
+    #[test]
+    fn sleddb_incr_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_incr(store);
+    }
+
+    #[test]
+    fn sleddb_concurrent_incr_should_work() {
+        let dir = tempdir().unwrap();
+        let store = Arc::new(SledDb::new(dir));
+        test_concurrent_incr(store);
+    }
+
+    #[test]
+    fn sleddb_compare_and_swap_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_compare_and_swap(store);
+    }
+
+    #[test]
+    fn sleddb_scan_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_scan(store);
+    }
+
+    #[test]
+    fn sleddb_tables_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_tables(store);
+    }
+
+    #[test]
+    fn sleddb_should_migrate_legacy_layout() {
+        let dir = tempdir().unwrap();
+        let db = sled::open(dir.path()).unwrap();
+        let v: Vec<u8> = Value::from("v1").try_into().unwrap();
+        db.insert("t1:k1", v.clone()).unwrap();
+        db.insert("t1:k2", v).unwrap();
+        // k2 早就过期了
+        let expires = db.open_tree("__kv_expires__").unwrap();
+        expires.insert("t1:k2", &1u64.to_be_bytes()).unwrap();
+        db.flush().unwrap();
+        drop((expires, db));
+
+        let store = SledDb::new(dir.path());
+        assert_eq!(store.tables(), Ok(vec!["t1".into()]));
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+        assert_eq!(store.get("t1", "k2"), Ok(None));
+    }
+
+    #[test]
+    fn sleddb_transaction_should_not_create_tables_for_reads() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir.path());
+        let tables = vec!["t1".to_string(), "t2".to_string()];
+        let res = store.transaction(&tables, &|tx| {
+            assert_eq!(tx.get("t1", "k1")?, None);
+            assert_eq!(tx.del("t1", "k1")?, None);
+            // 写入的 table 在事务里创建
+            tx.set("t2", "k1", "v1".into())?;
+            Ok(vec![])
+        });
+        assert_eq!(res, Ok(vec![]));
+        assert_eq!(store.tables(), Ok(vec!["t2".into()]));
+        assert_eq!(store.get("t2", "k1"), Ok(Some("v1".into())));
+        drop(store);
+
+        let db = sled::open(dir.path()).unwrap();
+        let names = db.tree_names();
+        assert!(!names.contains(&"__kv_data__:t1".into()));
+        assert!(names.contains(&"__kv_data__:t2".into()));
+    }
+
+    #[test]
+    fn sleddb_try_new_should_return_error() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("file");
+        std::fs::write(&file, "not a database").unwrap();
+        assert!(SledDb::try_new(file.join("db")).is_err());
+    }
+
+    fn keys(keys: &[&str]) -> Vec<String> {
+        keys.iter().map(|k| k.to_string()).collect()
+    }
 }
@@ -1,22 +1,205 @@
 use crate::storage::memory::StorageIter;
-use crate::{KvError, Kvpair, Storage, Value};
-use sled::{Db, Error, IVec};
+use crate::storage::{add_float, add_integer, before_end, deadline_ms, now_ms, scan_start};
+use crate::{CommandResponse, KvError, Kvpair, Storage, StorageTx, TransactionFn, Value};
+use parking_lot::{RwLock, RwLockReadGuard};
+use sled::transaction::{
+    ConflictableTransactionError, ConflictableTransactionResult, TransactionalTree,
+    UnabortableTransactionError,
+};
+use sled::{Batch, Db, Error, IVec, Transactional, Tree};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::ops::Bound;
 use std::path::Path;
+use std::time::Duration;
+use tracing::info;
+
+// 所有 table 的名字。读之前先查这里，这样读一个不存在的 table 不会创建它
+const TABLES_TREE: &str = "__kv_tables__";
+// 每个 table 的数据和过期时间各存在一个 tree 里，key 就是用户的 key
+const DATA_TREE_PREFIX: &str = "__kv_data__:";
+const EXPIRES_TREE_PREFIX: &str = "__kv_expires__:";
+// 旧版本所有数据都存在默认的 tree 里，key 是 "table:key"，过期时间存在这个 tree 里
+const LEGACY_EXPIRES_TREE: &str = "__kv_expires__";
 
 #[derive(Debug)]
-pub struct SledDb(Db);
+pub struct SledDb {
+    db: Db,
+    // 读写 table 时拿读锁，drop table 时拿写锁
+    // 避免写到一个正在被 drop 的 tree 里，或者 drop 之后又把 table 重新注册成空的
+    lock: RwLock<()>,
+}
+
+// 一个 table 的数据和过期时间，过期时间的 value 是过期时间点的毫秒时间戳
+// 持有 Table 期间这个 table 不会被 drop
+struct Table<'a> {
+    data: Tree,
+    expires: Tree,
+    _guard: RwLockReadGuard<'a, ()>,
+}
 
 impl SledDb {
     pub fn new(path: impl AsRef<Path>) -> Self {
-        Self(sled::open(path).unwrap())
+        Self::try_new(path).unwrap()
+    }
+
+    // 打开数据库，需要时把旧版本的数据迁移到新的格式
+    pub fn try_new(path: impl AsRef<Path>) -> Result<Self, KvError> {
+        let db = Self {
+            db: sled::open(path)?,
+            lock: RwLock::new(()),
+        };
+        db.migrate()?;
+        Ok(db)
+    }
+
+    fn data_tree(table: &str) -> String {
+        format!("{}{}", DATA_TREE_PREFIX, table)
+    }
+
+    fn expires_tree(table: &str) -> String {
+        format!("{}{}", EXPIRES_TREE_PREFIX, table)
+    }
+
+    fn tables_tree(&self) -> Result<Tree, KvError> {
+        Ok(self.db.open_tree(TABLES_TREE)?)
+    }
+
+    // table 的数据和过期时间两个 tree，调用的时候要持有读锁
+    fn open_trees(&self, name: &str) -> Result<(Tree, Tree), KvError> {
+        Ok((
+            self.db.open_tree(SledDb::data_tree(name))?,
+            self.db.open_tree(SledDb::expires_tree(name))?,
+        ))
+    }
+
+    // 读的时候用，table 不存在时返回 None
+    fn table(&self, name: &str) -> Result<Option<Table<'_>>, KvError> {
+        let guard = self.lock.read();
+        if !self.tables_tree()?.contains_key(name)? {
+            return Ok(None);
+        }
+        let (data, expires) = self.open_trees(name)?;
+        Ok(Some(Table {
+            data,
+            expires,
+            _guard: guard,
+        }))
+    }
+
+    // 写的时候用，table 不存在时创建
+    fn get_or_create_table(&self, name: &str) -> Result<Table<'_>, KvError> {
+        let guard = self.lock.read();
+        let tables = self.tables_tree()?;
+        if !tables.contains_key(name)? {
+            tables.insert(name, IVec::default())?;
+        }
+        let (data, expires) = self.open_trees(name)?;
+        Ok(Table {
+            data,
+            expires,
+            _guard: guard,
+        })
+    }
+
+    // 把旧版本存在默认 tree 里的数据搬到各个 table 自己的 tree 里
+    fn migrate(&self) -> Result<(), KvError> {
+        if self.db.is_empty() {
+            return Ok(());
+        }
+        let legacy_expires = match self.db.tree_names().contains(&LEGACY_EXPIRES_TREE.into()) {
+            true => Some(self.db.open_tree(LEGACY_EXPIRES_TREE)?),
+            false => None,
+        };
+
+        let mut count = 0;
+        for item in self.db.iter() {
+            let (name, value) = item?;
+            // 旧版本里第一个 ':' 前面是 table 的名字
+            let Some((table, key)) = str::from_utf8(&name).ok().and_then(|v| v.split_once(':'))
+            else {
+                continue;
+            };
+            let t = self.get_or_create_table(table)?;
+            t.data.insert(key, value)?;
+            if let Some(expires) = &legacy_expires
+                && let Some(deadline) = expires.get(&name)?
+            {
+                t.expires.insert(key, deadline)?;
+            }
+            self.db.remove(&name)?;
+            count += 1;
+        }
+        if legacy_expires.is_some() {
+            self.db.drop_tree(LEGACY_EXPIRES_TREE)?;
+        }
+        info!("Migrated {} keys to per-table trees", count);
+        Ok(())
     }
+}
 
-    fn get_full_key(table: &str, key: &str) -> String {
-        format!("{}:{}", table, key)
+impl Table<'_> {
+    // 用 update_and_fetch 做原子的读-改-写，冲突时 sled 会重试 f
+    fn update<T: Copy>(
+        &self,
+        key: &str,
+        f: impl Fn(&Value) -> Result<T, KvError>,
+    ) -> Result<T, KvError>
+    where
+        Value: From<T>,
+    {
+        self.expire_if_needed(key.as_bytes())?;
+        let mut result = Err(KvError::Internal("update is not executed".into()));
+        self.data.update_and_fetch(key, |old| {
+            let current = match old {
+                Some(v) => match Value::try_from(v) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        result = Err(e);
+                        return Some(v.to_vec());
+                    }
+                },
+                None => 0.into(),
+            };
+            result = f(&current);
+            // 出错时保持原来的值不变
+            let Ok(v) = result.as_ref() else {
+                return old.map(|v| v.to_vec());
+            };
+            match Vec::<u8>::try_from(Value::from(*v)) {
+                Ok(data) => Some(data),
+                Err(e) => {
+                    result = Err(e);
+                    old.map(|v| v.to_vec())
+                }
+            }
+        })?;
+        result
     }
 
-    fn get_table_prefix(table: &str) -> String {
-        format!("{}:", table)
+    // key 已经过期就删掉，返回 true
+    fn expire_if_needed(&self, key: &[u8]) -> Result<bool, KvError> {
+        if self.expires.get(key)?.is_none_or(|v| is_alive(&v)) {
+            return Ok(false);
+        }
+
+        let expired = (&self.data, &self.expires).transaction(|(data, expires)| {
+            // 事务里再确认一次，期间可能被重新 set 或者 expire
+            if expires.get(key)?.is_none_or(|v| is_alive(&v)) {
+                return Ok(false);
+            }
+            expires.remove(key)?;
+            data.remove(key)?;
+            ConflictableTransactionResult::<_, KvError>::Ok(true)
+        })?;
+        Ok(expired)
+    }
+}
+
+fn is_alive(deadline: &[u8]) -> bool {
+    match deadline.try_into() {
+        Ok(v) => u64::from_be_bytes(v) > now_ms(),
+        Err(_) => true,
     }
 }
 
@@ -27,59 +210,477 @@ fn flip<T, E>(x: Option<Result<T, E>>) -> Result<Option<T>, E> {
 
 impl Storage for SledDb {
     fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
-        let name = SledDb::get_full_key(table, key);
-        let result = self.0.get(name.as_bytes())?.map(|v| v.as_ref().try_into());
+        let Some(t) = self.table(table)? else {
+            return Ok(None);
+        };
+        if t.expire_if_needed(key.as_bytes())? {
+            return Ok(None);
+        }
+        let result = t.data.get(key)?.map(|v| v.as_ref().try_into());
         flip(result)
     }
 
-    fn mget<T, K>(&self, _table: &str, _keys: T) -> Result<Vec<Kvpair>, KvError>
-    where
-        K: Into<String>,
-        T: IntoIterator<Item = K>,
-    {
-        todo!()
+    fn mget(&self, table: &str, keys: &[String]) -> Result<Vec<Kvpair>, KvError> {
+        let Some(t) = self.table(table)? else {
+            return Ok(Vec::new());
+        };
+        // 在事务里读，保证拿到的是同一时刻的数据
+        let values = (&t.data, &t.expires).transaction(|(data, expires)| {
+            let mut values = Vec::with_capacity(keys.len());
+            for key in keys {
+                match expires.get(key)?.is_none_or(|v| is_alive(&v)) {
+                    true => values.push(data.get(key)?),
+                    false => values.push(None),
+                }
+            }
+            ConflictableTransactionResult::<_, KvError>::Ok(values)
+        })?;
+
+        let mut res = Vec::new();
+        for (key, value) in keys.iter().zip(values) {
+            if let Some(v) = value {
+                res.push(Kvpair::new(key, v.as_ref().try_into()?));
+            }
+        }
+        Ok(res)
     }
 
-    fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, KvError> {
-        let name = SledDb::get_full_key(table, &key);
+    fn set(
+        &self,
+        table: &str,
+        key: String,
+        value: Value,
+        ttl: Option<Duration>,
+    ) -> Result<Option<Value>, KvError> {
+        let t = self.get_or_create_table(table)?;
         let data: Vec<u8> = value.try_into()?;
-        let result = self.0.insert(name, data)?.map(|v| v.as_ref().try_into());
-        flip(result)
+        let deadline = ttl.map(|ttl| deadline_ms(ttl).to_be_bytes());
+        // 值和过期时间在同一个事务里写，已经过期的旧值不返回
+        let result = (&t.data, &t.expires).transaction(|(tree, expires)| {
+            let old_deadline = match deadline {
+                Some(deadline) => expires.insert(key.as_bytes(), &deadline)?,
+                None => expires.remove(key.as_bytes())?,
+            };
+            let alive = old_deadline.is_none_or(|v| is_alive(&v));
+            let old = tree.insert(key.as_bytes(), data.as_slice())?;
+            ConflictableTransactionResult::<_, KvError>::Ok(old.filter(|_| alive))
+        })?;
+        flip(result.map(|v| v.as_ref().try_into()))
     }
 
-    fn mset(&self, _table: &str, _items: Vec<Kvpair>) -> Result<bool, KvError> {
-        todo!()
+    fn mset(
+        &self,
+        table: &str,
+        items: Vec<Kvpair>,
+        ttl: Option<Duration>,
+    ) -> Result<bool, KvError> {
+        let mut batch = Batch::default();
+        let mut expires_batch = Batch::default();
+        let deadline = ttl.map(|ttl| deadline_ms(ttl).to_be_bytes());
+        for Kvpair { key, value } in items {
+            let data: Vec<u8> = value.unwrap_or_default().try_into()?;
+            match deadline {
+                Some(deadline) => expires_batch.insert(key.as_bytes(), &deadline),
+                None => expires_batch.remove(key.as_bytes()),
+            }
+            batch.insert(key.as_bytes(), data);
+        }
+        // 在事务里写，要么全部成功，要么全部失败
+        let t = self.get_or_create_table(table)?;
+        (&t.data, &t.expires).transaction(|(tree, expires)| {
+            tree.apply_batch(&batch)?;
+            expires.apply_batch(&expires_batch)?;
+            ConflictableTransactionResult::<_, KvError>::Ok(())
+        })?;
+        Ok(true)
     }
 
     fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
-        let name = SledDb::get_full_key(table, key);
-        Ok(self.0.contains_key(name)?)
+        let Some(t) = self.table(table)? else {
+            return Ok(false);
+        };
+        if t.expire_if_needed(key.as_bytes())? {
+            return Ok(false);
+        }
+        Ok(t.data.contains_key(key)?)
+    }
+
+    fn mcontains(&self, table: &str, keys: &[String]) -> Result<Vec<Kvpair>, KvError> {
+        let exists = match self.table(table)? {
+            Some(t) => (&t.data, &t.expires).transaction(|(data, expires)| {
+                let mut exists = Vec::with_capacity(keys.len());
+                for key in keys {
+                    let alive = expires.get(key)?.is_none_or(|v| is_alive(&v));
+                    exists.push(alive && data.get(key)?.is_some());
+                }
+                ConflictableTransactionResult::<_, KvError>::Ok(exists)
+            })?,
+            None => vec![false; keys.len()],
+        };
+
+        Ok(keys
+            .iter()
+            .zip(exists)
+            .map(|(key, exist)| Kvpair::new(key, exist.into()))
+            .collect())
     }
 
     fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
-        let name = SledDb::get_full_key(table, key);
-        let result = self.0.remove(name)?.map(|v| v.as_ref().try_into());
-        flip(result)
+        let Some(t) = self.table(table)? else {
+            return Ok(None);
+        };
+        let result = (&t.data, &t.expires).transaction(|(tree, expires)| {
+            let alive = expires.remove(key.as_bytes())?.is_none_or(|v| is_alive(&v));
+            let old = tree.remove(key.as_bytes())?;
+            ConflictableTransactionResult::<_, KvError>::Ok(old.filter(|_| alive))
+        })?;
+        flip(result.map(|v| v.as_ref().try_into()))
     }
 
-    fn mdel<T, K>(&self, _table: &str, _keys: T) -> Result<bool, KvError>
-    where
-        K: Into<String>,
-        T: IntoIterator<Item = K>,
-    {
-        todo!()
+    fn mdel(&self, table: &str, keys: &[String]) -> Result<bool, KvError> {
+        let Some(t) = self.table(table)? else {
+            return Ok(true);
+        };
+        let mut batch = Batch::default();
+        for key in keys {
+            batch.remove(key.as_bytes());
+        }
+        (&t.data, &t.expires).transaction(|(tree, expires)| {
+            tree.apply_batch(&batch)?;
+            expires.apply_batch(&batch)?;
+            ConflictableTransactionResult::<_, KvError>::Ok(())
+        })?;
+        Ok(true)
     }
 
     fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
-        let prefix = SledDb::get_table_prefix(table);
-        let result = self.0.scan_prefix(prefix).map(|v| v.into()).collect();
+        Ok(self.get_iter(table)?.collect())
+    }
+
+    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair> + Send>, KvError> {
+        let Some(Table { data, expires, .. }) = self.table(table)? else {
+            return Ok(Box::new(std::iter::empty()));
+        };
+        // 过期的 key 直接跳过，留给 purge_expired 去清理
+        let iter = data.iter().filter(move |item| match item {
+            Ok((k, _)) => !matches!(expires.get(k), Ok(Some(v)) if !is_alive(&v)),
+            Err(_) => true,
+        });
+        Ok(Box::new(StorageIter::new(iter)))
+    }
+
+    fn scan(
+        &self,
+        table: &str,
+        prefix: &str,
+        start: Bound<&str>,
+        end: Bound<&str>,
+        limit: usize,
+    ) -> Result<Vec<Kvpair>, KvError> {
+        let Some(t) = self.table(table)? else {
+            return Ok(Vec::new());
+        };
+        let start = scan_start(prefix, start);
+
+        let mut res = Vec::new();
+        for item in t.data.range::<String, _>((start, Bound::Unbounded)) {
+            let (k, v) = item?;
+            // sled 里的 key 是有序的，超出 prefix 或者 end 后面就不会再有符合的数据
+            if res.len() >= limit || !k.starts_with(prefix.as_bytes()) {
+                break;
+            }
+            let key = ivec_to_key(&k);
+            if !before_end(&key, end) {
+                break;
+            }
+            if matches!(t.expires.get(&k)?, Some(v) if !is_alive(&v)) {
+                continue;
+            }
+            res.push(Kvpair::new(key, v.as_ref().try_into()?));
+        }
+        Ok(res)
+    }
+
+    fn tables(&self) -> Result<Vec<String>, KvError> {
+        let mut names = Vec::new();
+        for name in self.tables_tree()?.iter().keys() {
+            names.push(ivec_to_key(&name?).into_owned());
+        }
+        Ok(names)
+    }
+
+    fn drop_table(&self, table: &str) -> Result<bool, KvError> {
+        let _guard = self.lock.write();
+        if self.tables_tree()?.remove(table)?.is_none() {
+            return Ok(false);
+        }
+        self.db.drop_tree(SledDb::data_tree(table))?;
+        self.db.drop_tree(SledDb::expires_tree(table))?;
+        Ok(true)
+    }
+
+    fn len(&self, table: &str) -> Result<usize, KvError> {
+        let Some(t) = self.table(table)? else {
+            return Ok(0);
+        };
+        // 有过期时间的 key 一定在数据里，减掉已经过期但还没清理的
+        let mut expired = 0;
+        for deadline in t.expires.iter().values() {
+            if !is_alive(&deadline?) {
+                expired += 1;
+            }
+        }
+        Ok(t.data.len().saturating_sub(expired))
+    }
+
+    fn incr(&self, table: &str, key: &str, delta: i64) -> Result<i64, KvError> {
+        let t = self.get_or_create_table(table)?;
+        t.update(key, |v| add_integer(v, delta))
+    }
+
+    fn incr_float(&self, table: &str, key: &str, delta: f64) -> Result<f64, KvError> {
+        let t = self.get_or_create_table(table)?;
+        t.update(key, |v| add_float(v, delta))
+    }
+
+    fn compare_and_swap(
+        &self,
+        table: &str,
+        key: &str,
+        expected: Option<Value>,
+        new: Value,
+    ) -> Result<(bool, Option<Value>), KvError> {
+        // 期望 key 已经存在时，table 不存在肯定失败，不用创建 table
+        let t = match expected {
+            Some(_) => match self.table(table)? {
+                Some(t) => t,
+                None => return Ok((false, None)),
+            },
+            None => self.get_or_create_table(table)?,
+        };
+        let data: Vec<u8> = new.clone().try_into()?;
+        // 比较、写入和清掉过期时间在同一个事务里，过期的值当作不存在
+        // 和 MemTable 一样比较解码后的 Value，而不是编码后的字节
+        let result = (&t.data, &t.expires).transaction(|(tree, expires)| {
+            let alive = expires.get(key)?.is_none_or(|v| is_alive(&v));
+            let current = match tree.get(key)? {
+                Some(v) if alive => {
+                    Some(Value::try_from(v.as_ref()).map_err(ConflictableTransactionError::Abort)?)
+                }
+                _ => None,
+            };
+            if current != expected {
+                return Ok(Err(current));
+            }
+            // 和 set 一样，写入成功后清掉过期时间
+            expires.remove(key)?;
+            tree.insert(key, data.as_slice())?;
+            Ok(Ok(()))
+        })?;
+        match result {
+            Ok(()) => Ok((true, Some(new))),
+            Err(current) => Ok((false, current)),
+        }
+    }
+
+    fn expire(&self, table: &str, key: &str, ttl: Option<Duration>) -> Result<bool, KvError> {
+        let Some(t) = self.table(table)? else {
+            return Ok(false);
+        };
+        if t.expire_if_needed(key.as_bytes())? {
+            return Ok(false);
+        }
+        let result = (&t.data, &t.expires).transaction(|(tree, expires)| {
+            if tree.get(key)?.is_none() {
+                return Ok(false);
+            }
+            match ttl {
+                Some(ttl) => expires.insert(key, &deadline_ms(ttl).to_be_bytes())?,
+                None => expires.remove(key)?,
+            };
+            Ok::<_, ConflictableTransactionError<KvError>>(true)
+        })?;
         Ok(result)
     }
 
-    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
-        let prefix = SledDb::get_table_prefix(table);
-        let iter = StorageIter::new(self.0.scan_prefix(prefix));
-        Ok(Box::new(iter))
+    fn ttl(&self, table: &str, key: &str) -> Result<Option<Duration>, KvError> {
+        let t = match self.table(table)? {
+            Some(t) if !t.expire_if_needed(key.as_bytes())? && t.data.contains_key(key)? => t,
+            _ => return Err(KvError::NotFound(table.into(), key.into())),
+        };
+        let deadline = t
+            .expires
+            .get(key)?
+            .and_then(|v| v.as_ref().try_into().ok().map(u64::from_be_bytes));
+        Ok(deadline.map(|v| Duration::from_millis(v.saturating_sub(now_ms()))))
+    }
+
+    fn purge_expired(&self) -> Result<usize, KvError> {
+        let mut count = 0;
+        for name in self.tables()? {
+            let Some(t) = self.table(&name)? else {
+                continue;
+            };
+            for item in t.expires.iter() {
+                let (key, deadline) = item?;
+                if !is_alive(&deadline) && t.expire_if_needed(&key)? {
+                    count += 1;
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    fn transaction(
+        &self,
+        tables: &[String],
+        f: &TransactionFn<'_>,
+    ) -> Result<Vec<CommandResponse>, KvError> {
+        let mut names = tables.to_vec();
+        names.sort();
+        names.dedup();
+        let _guard = self.lock.read();
+        let registry = self.tables_tree()?;
+        // 只打开已经存在的 table，读不存在的 table 时不会创建 tree
+        let mut opened = Vec::new();
+        for name in &names {
+            if registry.contains_key(name)? {
+                opened.push(name.clone());
+            }
+        }
+
+        loop {
+            // 第一个是记录所有 table 名字的 tree，后面依次是每个 table 的数据和过期时间
+            let mut trees = vec![registry.clone()];
+            for name in &opened {
+                let (data, expires) = self.open_trees(name)?;
+                trees.push(data);
+                trees.push(expires);
+            }
+
+            let missing = RefCell::new(None);
+            let res = trees[..].transaction(|trees| {
+                let mut tx = SledTx {
+                    names: &names,
+                    opened: &opened,
+                    trees,
+                    error: None,
+                    missing: None,
+                };
+                let res = f(&mut tx);
+                // sled 自己的错误（比如冲突）要原样返回，这样冲突时 sled 才会重试
+                if let Some(e) = tx.error {
+                    return Err(e.into());
+                }
+                if let Some(name) = tx.missing {
+                    *missing.borrow_mut() = Some(name);
+                    return Err(ConflictableTransactionError::Abort(KvError::Internal(
+                        "table is not opened".into(),
+                    )));
+                }
+                res.map_err(ConflictableTransactionError::Abort)
+            });
+            // 要写入一个还没有打开的 table，打开之后重新执行
+            match missing.into_inner() {
+                Some(name) => {
+                    opened.push(name);
+                    opened.sort();
+                }
+                None => return Ok(res?),
+            }
+        }
+    }
+}
+
+// SledDb 的事务，所有 table 的数据和过期时间一起参与事务
+struct SledTx<'a> {
+    // 事务里会用到的所有 table
+    names: &'a [String],
+    // 其中打开了 tree 的 table，和 trees 里的顺序一致
+    opened: &'a [String],
+    trees: &'a [TransactionalTree],
+    error: Option<UnabortableTransactionError>,
+    // 要写入但还没有打开的 table
+    missing: Option<String>,
+}
+
+impl<'a> SledTx<'a> {
+    fn check<T>(&mut self, result: Result<T, UnabortableTransactionError>) -> Result<T, KvError> {
+        result.map_err(|e| {
+            let err = match &e {
+                UnabortableTransactionError::Storage(e) => KvError::SledError(e.clone()),
+                UnabortableTransactionError::Conflict => {
+                    KvError::Internal("transaction conflict".into())
+                }
+            };
+            self.error = Some(e);
+            err
+        })
+    }
+
+    // table 的数据和过期时间两个 tree，table 不存在时返回 None
+    fn table(
+        &self,
+        table: &str,
+    ) -> Result<Option<(&'a TransactionalTree, &'a TransactionalTree)>, KvError> {
+        if !self.names.iter().any(|v| v == table) {
+            return Err(KvError::Internal(format!(
+                "table {} is not locked by the transaction",
+                table
+            )));
+        }
+        let trees = self.trees;
+        Ok(self
+            .opened
+            .iter()
+            .position(|v| v == table)
+            .map(|i| (&trees[2 * i + 1], &trees[2 * i + 2])))
+    }
+
+    fn remove(&mut self, table: &str, key: &str) -> Result<(), KvError> {
+        if let Some((data, expires)) = self.table(table)? {
+            self.check(expires.remove(key))?;
+            self.check(data.remove(key))?;
+        }
+        Ok(())
+    }
+}
+
+impl StorageTx for SledTx<'_> {
+    fn get(&mut self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let Some((data, expires)) = self.table(table)? else {
+            return Ok(None);
+        };
+        let deadline = self.check(expires.get(key))?;
+        if deadline.is_some_and(|v| !is_alive(&v)) {
+            self.remove(table, key)?;
+            return Ok(None);
+        }
+        let v = self.check(data.get(key))?;
+        flip(v.map(|v| v.as_ref().try_into()))
+    }
+
+    fn set(&mut self, table: &str, key: &str, value: Value) -> Result<Option<Value>, KvError> {
+        let old = self.get(table, key)?;
+        let Some((data, expires)) = self.table(table)? else {
+            self.missing = Some(table.to_string());
+            return Err(KvError::Internal(format!("table {} is not opened", table)));
+        };
+        let bytes: Vec<u8> = value.try_into()?;
+        self.check(expires.remove(key))?;
+        self.check(data.insert(key, bytes))?;
+        // 有写入时才把 table 记下来
+        let tables = &self.trees[0];
+        if self.check(tables.get(table))?.is_none() {
+            self.check(tables.insert(table, IVec::default()))?;
+        }
+        Ok(old)
+    }
+
+    fn del(&mut self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let old = self.get(table, key)?;
+        self.remove(table, key)?;
+        Ok(old)
     }
 }
 
@@ -95,9 +696,6 @@ impl From<Result<(IVec, IVec), Error>> for Kvpair {
     }
 }
 
-fn ivec_to_key(ivec: &[u8]) -> &str {
-    let s = str::from_utf8(ivec).unwrap();
-    let mut iter = s.split(":");
-    iter.next();
-    iter.next().unwrap()
+fn ivec_to_key(ivec: &[u8]) -> Cow<'_, str> {
+    String::from_utf8_lossy(ivec)
 }
@@ -0,0 +1,462 @@
+use crate::{KvError, LogEntry};
+use bytes::{Buf, BufMut, Bytes};
+use parking_lot::Mutex;
+use prost::Message;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+const WAL_FILE: &str = "wal.log";
+const SNAPSHOT_FILE: &str = "snapshot.db";
+const SNAPSHOT_TMP_FILE: &str = "snapshot.db.tmp";
+// 每条记录前面是长度和 CRC32，都是 4 个字节
+const HEADER_LEN: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    // 每次写入都 fsync，最安全也最慢
+    Always,
+    // 后台线程每隔这段时间 fsync 一次，机器崩溃时最多丢这段时间的数据
+    Interval(Duration),
+    // 只写到操作系统的缓存，什么时候落盘由操作系统决定
+    Never,
+}
+
+#[derive(Debug, Clone)]
+pub struct WalOptions {
+    dir: PathBuf,
+    fsync: FsyncPolicy,
+    // WAL 里累积了这么多条记录后，自动做一次快照并清空 WAL；0 表示不自动做
+    compact_after: usize,
+    // 距离上次快照超过这个时间后，下一次写入时做一次快照；None 表示不按时间做
+    compact_interval: Option<Duration>,
+}
+
+impl WalOptions {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            fsync: FsyncPolicy::Interval(Duration::from_secs(1)),
+            compact_after: 10_000,
+            compact_interval: None,
+        }
+    }
+
+    pub fn fsync(mut self, policy: FsyncPolicy) -> Self {
+        self.fsync = policy;
+        self
+    }
+
+    pub fn compact_after(mut self, n: usize) -> Self {
+        self.compact_after = n;
+        self
+    }
+
+    pub fn compact_interval(mut self, interval: Duration) -> Self {
+        self.compact_interval = Some(interval);
+        self
+    }
+}
+
+// 快照 + WAL：启动时先加载快照，再按顺序重放 WAL 里的记录
+#[derive(Debug)]
+pub(crate) struct Wal {
+    options: WalOptions,
+    file: File,
+    // WAL 里现在有多少条记录
+    entries: usize,
+    // 有还没有 fsync 的写入
+    dirty: bool,
+    last_compact: Instant,
+}
+
+impl Wal {
+    // 打开 WAL，返回需要按顺序重放的记录（先是快照，然后是 WAL）
+    pub(crate) fn open(options: WalOptions) -> Result<(Self, Vec<LogEntry>), KvError> {
+        fs::create_dir_all(&options.dir)?;
+
+        let mut entries = match fs::read(options.dir.join(SNAPSHOT_FILE)) {
+            Ok(data) => {
+                let (entries, len) = decode_entries(data.into())?;
+                if len.is_some() {
+                    return Err(KvError::Internal("snapshot is corrupted".into()));
+                }
+                entries
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let path = options.dir.join(WAL_FILE);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        // 中间的记录损坏时不能只丢掉后面的记录，直接报错
+        let (logs, len) = decode_entries(fs::read(&path)?.into())?;
+        if let Some(len) = len {
+            // 最后一条记录没写完（比如写到一半进程崩溃了），丢掉它
+            warn!("Truncate incomplete WAL record at offset {}", len);
+            file.set_len(len as u64)?;
+            file.sync_all()?;
+        }
+        info!(
+            "Loaded {} entries from snapshot and {} entries from WAL",
+            entries.len(),
+            logs.len()
+        );
+
+        let wal = Self {
+            options,
+            file,
+            entries: logs.len(),
+            dirty: false,
+            last_compact: Instant::now(),
+        };
+        entries.extend(logs);
+        Ok((wal, entries))
+    }
+
+    pub(crate) fn append(&mut self, entries: &[LogEntry]) -> Result<(), KvError> {
+        let buf = encode_entries(entries);
+        // 一次 write 写完所有记录，这样一个命令的修改不会被拆开
+        self.file.write_all(&buf)?;
+        self.entries += entries.len();
+
+        match self.options.fsync {
+            FsyncPolicy::Always => self.file.sync_data()?,
+            FsyncPolicy::Interval(_) => self.dirty = true,
+            FsyncPolicy::Never => {}
+        }
+        Ok(())
+    }
+
+    // 把还没有 fsync 的写入落盘
+    pub(crate) fn sync(&mut self) -> Result<(), KvError> {
+        if self.dirty {
+            self.file.sync_data()?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn should_compact(&self) -> bool {
+        if self.entries == 0 {
+            return false;
+        }
+        let interval = self.options.compact_interval;
+        (self.options.compact_after > 0 && self.entries >= self.options.compact_after)
+            || interval.is_some_and(|v| self.last_compact.elapsed() >= v)
+    }
+
+    // 用当前所有的数据生成新的快照，然后清空 WAL
+    // 快照先写到临时文件再 rename，中途崩溃时旧的快照和 WAL 都还在
+    pub(crate) fn compact(&mut self, entries: Vec<LogEntry>) -> Result<(), KvError> {
+        let tmp = self.options.dir.join(SNAPSHOT_TMP_FILE);
+        let buf = encode_entries(&entries);
+        let mut file = File::create(&tmp)?;
+        file.write_all(&buf)?;
+        file.sync_all()?;
+        fs::rename(&tmp, self.options.dir.join(SNAPSHOT_FILE))?;
+        sync_dir(&self.options.dir)?;
+
+        self.file.set_len(0)?;
+        self.file.sync_all()?;
+        self.entries = 0;
+        self.dirty = false;
+        self.last_compact = Instant::now();
+        info!("Compacted WAL into a snapshot of {} entries", entries.len());
+        Ok(())
+    }
+}
+
+// FsyncPolicy::Interval 时启动一个后台线程定期 fsync，WAL 被 drop 之后线程退出
+pub(crate) fn spawn_sync(wal: &Arc<Mutex<Wal>>) {
+    let FsyncPolicy::Interval(interval) = wal.lock().options.fsync else {
+        return;
+    };
+    let wal = Arc::downgrade(wal);
+    std::thread::spawn(move || {
+        loop {
+            std::thread::sleep(interval);
+            let Some(wal) = wal.upgrade() else {
+                break;
+            };
+            if let Err(e) = wal.lock().sync() {
+                warn!("Failed to sync WAL: {:?}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+impl Wal {
+    // 让之后的写入都失败
+    pub(crate) fn break_file(&mut self) {
+        self.file = File::open(self.options.dir.join(WAL_FILE)).unwrap();
+    }
+}
+
+// 记录的格式: | len: u32 | crc32: u32 | protobuf LogEntry |
+fn encode_entries(entries: &[LogEntry]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for entry in entries {
+        let data = entry.encode_to_vec();
+        buf.put_u32(data.len() as u32);
+        buf.put_u32(crc32fast::hash(&data));
+        buf.put_slice(&data);
+    }
+    buf
+}
+
+// 返回解码出来的记录，如果最后有不完整的记录，同时返回完整记录的总长度
+// 只有最后一条记录可以是不完整的（写到一半崩溃），其它记录损坏时返回错误
+fn decode_entries(mut data: Bytes) -> Result<(Vec<LogEntry>, Option<usize>), KvError> {
+    let total = data.len();
+    let mut entries = Vec::new();
+    while data.has_remaining() {
+        let offset = total - data.remaining();
+        if data.remaining() < HEADER_LEN {
+            return Ok((entries, Some(offset)));
+        }
+        let len = data.get_u32() as usize;
+        let crc = data.get_u32();
+        if data.remaining() < len {
+            return Ok((entries, Some(offset)));
+        }
+        let record = data.split_to(len);
+        let corrupted =
+            || KvError::Internal(format!("WAL record at offset {} is corrupted", offset));
+        if crc32fast::hash(&record) != crc {
+            // 最后一条记录没有完整落盘时，长度对但是内容不对
+            if !data.has_remaining() {
+                return Ok((entries, Some(offset)));
+            }
+            return Err(corrupted());
+        }
+        entries.push(LogEntry::decode(record).map_err(|_| corrupted())?);
+    }
+    Ok((entries, None))
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> Result<(), KvError> {
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> Result<(), KvError> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Kvpair, MemTable, Storage, Value};
+    use tempfile::tempdir;
+
+    #[test]
+    fn mem_table_should_recover_from_wal() {
+        let dir = tempdir().unwrap();
+        let options = WalOptions::new(dir.path()).fsync(FsyncPolicy::Always);
+
+        let store = MemTable::open(options.clone()).unwrap();
+        store.set("t1", "k1".into(), "v1".into(), None).unwrap();
+        store
+            .mset(
+                "t1",
+                vec![Kvpair::new("k2", 2.into()), Kvpair::new("k3", 3.into())],
+                None,
+            )
+            .unwrap();
+        store.del("t1", "k2").unwrap();
+        store.incr("t2", "counter", 10).unwrap();
+        store
+            .expire("t1", "k3", Some(Duration::from_secs(60)))
+            .unwrap();
+        store
+            .compare_and_swap("t1", "k1", Some("v1".into()), "v2".into())
+            .unwrap();
+        store
+            .transaction(&["t2".into()], &|tx| {
+                tx.set("t2", "k1", "tx".into())?;
+                Ok(vec![])
+            })
+            .unwrap();
+        drop(store);
+
+        let store = MemTable::open(options).unwrap();
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v2".into())));
+        assert_eq!(store.get("t1", "k2"), Ok(None));
+        assert_eq!(store.get("t1", "k3"), Ok(Some(3.into())));
+        assert!(store.ttl("t1", "k3").unwrap().is_some());
+        assert_eq!(store.get("t2", "counter"), Ok(Some(10.into())));
+        assert_eq!(store.get("t2", "k1"), Ok(Some("tx".into())));
+    }
+
+    #[test]
+    fn dropped_table_should_not_be_recovered() {
+        let dir = tempdir().unwrap();
+        let options = WalOptions::new(dir.path());
+
+        let store = MemTable::open(options.clone()).unwrap();
+        store.set("t1", "k1".into(), "v1".into(), None).unwrap();
+        store.set("t2", "k1".into(), "v1".into(), None).unwrap();
+        store.drop_table("t1").unwrap();
+        drop(store);
+
+        let store = MemTable::open(options).unwrap();
+        assert_eq!(store.tables(), Ok(vec!["t2".into()]));
+        assert_eq!(store.get("t1", "k1"), Ok(None));
+    }
+
+    #[test]
+    fn expired_keys_should_not_be_recovered() {
+        let dir = tempdir().unwrap();
+        let options = WalOptions::new(dir.path());
+
+        let store = MemTable::open(options.clone()).unwrap();
+        store.set("t1", "k1".into(), "v1".into(), None).unwrap();
+        store
+            .expire("t1", "k1", Some(Duration::from_millis(10)))
+            .unwrap();
+        drop(store);
+
+        std::thread::sleep(Duration::from_millis(20));
+        let store = MemTable::open(options).unwrap();
+        assert_eq!(store.get("t1", "k1"), Ok(None));
+    }
+
+    #[test]
+    fn compaction_should_rewrite_wal_from_snapshot() {
+        let dir = tempdir().unwrap();
+        let options = WalOptions::new(dir.path()).compact_after(5);
+
+        let store = MemTable::open(options.clone()).unwrap();
+        for i in 0..12 {
+            store.set("t1", format!("k{}", i), i.into(), None).unwrap();
+        }
+        store.del("t1", "k0").unwrap();
+        // 12 次 set 之后已经自动做了两次快照，WAL 里只剩下最后 3 条记录
+        let (logs, _) =
+            decode_entries(fs::read(dir.path().join(WAL_FILE)).unwrap().into()).unwrap();
+        assert_eq!(logs.len(), 3);
+        assert!(dir.path().join(SNAPSHOT_FILE).exists());
+
+        store.compact().unwrap();
+        assert_eq!(fs::metadata(dir.path().join(WAL_FILE)).unwrap().len(), 0);
+        drop(store);
+
+        let store = MemTable::open(options).unwrap();
+        assert_eq!(store.get_all("t1").unwrap().len(), 11);
+        assert_eq!(store.get("t1", "k0"), Ok(None));
+        assert_eq!(store.get("t1", "k11"), Ok(Some(11.into())));
+    }
+
+    #[test]
+    fn compaction_should_be_triggered_by_interval() {
+        let dir = tempdir().unwrap();
+        let options = WalOptions::new(dir.path()).compact_interval(Duration::from_millis(10));
+
+        let store = MemTable::open(options).unwrap();
+        store.set("t1", "k1".into(), "v1".into(), None).unwrap();
+        assert!(!dir.path().join(SNAPSHOT_FILE).exists());
+        std::thread::sleep(Duration::from_millis(20));
+        store.set("t1", "k2".into(), "v2".into(), None).unwrap();
+        assert!(dir.path().join(SNAPSHOT_FILE).exists());
+        assert_eq!(fs::metadata(dir.path().join(WAL_FILE)).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn failed_compaction_should_not_fail_writes() {
+        let dir = tempdir().unwrap();
+        let options = WalOptions::new(dir.path()).compact_after(2);
+        // 临时文件的位置是一个目录，快照写不进去
+        fs::create_dir_all(dir.path().join(SNAPSHOT_TMP_FILE)).unwrap();
+
+        let store = MemTable::open(options.clone()).unwrap();
+        for i in 0..3 {
+            store.set("t1", format!("k{}", i), i.into(), None).unwrap();
+        }
+        drop(store);
+
+        let store = MemTable::open(options).unwrap();
+        assert_eq!(store.len("t1"), Ok(3));
+    }
+
+    #[test]
+    fn interval_fsync_should_run_in_background() {
+        let dir = tempdir().unwrap();
+        let options =
+            WalOptions::new(dir.path()).fsync(FsyncPolicy::Interval(Duration::from_millis(10)));
+        let (wal, _) = Wal::open(options).unwrap();
+        let wal = Arc::new(Mutex::new(wal));
+        spawn_sync(&wal);
+
+        wal.lock().append(&[LogEntry::default()]).unwrap();
+        assert!(wal.lock().dirty);
+        // 之后没有写入，也会被后台线程 fsync
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!wal.lock().dirty);
+    }
+
+    #[test]
+    fn incomplete_record_should_be_truncated() {
+        let dir = tempdir().unwrap();
+        let options = WalOptions::new(dir.path());
+
+        let store = MemTable::open(options.clone()).unwrap();
+        store.set("t1", "k1".into(), "v1".into(), None).unwrap();
+        drop(store);
+
+        // 模拟写到一半崩溃：长度头说有 100 个字节，实际只有 2 个
+        let path = dir.path().join(WAL_FILE);
+        let len = fs::metadata(&path).unwrap().len();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[100, 1, 2]).unwrap();
+        drop(file);
+
+        let store = MemTable::open(options.clone()).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), len);
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+        store.set("t1", "k2".into(), Value::from(2), None).unwrap();
+        drop(store);
+
+        let store = MemTable::open(options.clone()).unwrap();
+        assert_eq!(store.get("t1", "k2"), Ok(Some(2.into())));
+        drop(store);
+
+        // 最后一条记录长度完整，但是内容没有完整落盘
+        let len = fs::metadata(&path).unwrap().len();
+        let mut data = fs::read(&path).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        fs::write(&path, &data).unwrap();
+        let store = MemTable::open(options).unwrap();
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+        assert_eq!(store.get("t1", "k2"), Ok(None));
+        assert!(fs::metadata(&path).unwrap().len() < len);
+    }
+
+    #[test]
+    fn corrupted_record_in_the_middle_should_fail_to_open() {
+        let dir = tempdir().unwrap();
+        let options = WalOptions::new(dir.path());
+
+        let store = MemTable::open(options.clone()).unwrap();
+        store.set("t1", "k1".into(), "v1".into(), None).unwrap();
+        store.set("t1", "k2".into(), "v2".into(), None).unwrap();
+        drop(store);
+
+        // 改掉第一条记录里的一个字节，后面还有完整的记录
+        let path = dir.path().join(WAL_FILE);
+        let mut data = fs::read(&path).unwrap();
+        data[HEADER_LEN] ^= 0xff;
+        fs::write(&path, &data).unwrap();
+
+        assert!(MemTable::open(options).is_err());
+        // 不会截断 WAL，修复之后数据还在
+        assert_eq!(fs::read(&path).unwrap(), data);
+    }
+}